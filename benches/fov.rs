@@ -0,0 +1,118 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// timings for `fov`, over generated worlds of the kinds games tend to have.
+// Benches of calls that are meant not to allocate check that they don't
+// before timing them.
+
+#![feature(globs)]
+
+extern crate test;
+extern crate libc;
+extern crate serialize;
+extern crate uuid;
+extern crate p2d;
+
+use test::Bencher;
+
+use p2d::world::RelativeCoord;
+use p2d::fov;
+use p2d::fov::{Chebyshev, Euclidean};
+
+use support::{TestWorld, open_field, scattered, maze, portal_grid, viewer, angle_buffers};
+use support::alloc;
+
+#[path = "../tests/support/mod.rs"]
+mod support;
+
+fn bench_compute(b: &mut Bencher, world: &TestWorld, focus: RelativeCoord, radius: uint) {
+    let (mut start_ang, mut end_ang) = angle_buffers(radius);
+    b.iter(|| {
+        fov::compute(world, focus.clone(), radius,
+                     start_ang.as_mut_slice(), end_ang.as_mut_slice())
+    });
+}
+
+#[bench]
+fn open_field_radius_8(b: &mut Bencher) {
+    let (world, zid) = open_field(64);
+    bench_compute(b, &world, viewer(zid, (32, 32)), 8);
+}
+
+#[bench]
+fn open_field_radius_20(b: &mut Bencher) {
+    let (world, zid) = open_field(64);
+    bench_compute(b, &world, viewer(zid, (32, 32)), 20);
+}
+
+#[bench]
+fn open_field_radius_60(b: &mut Bencher) {
+    let (world, zid) = open_field(128);
+    bench_compute(b, &world, viewer(zid, (64, 64)), 60);
+}
+
+#[bench]
+fn scattered_radius_20(b: &mut Bencher) {
+    let (world, zid) = scattered(64, 8, 1);
+    bench_compute(b, &world, viewer(zid, (32, 32)), 20);
+}
+
+#[bench]
+fn maze_radius_20(b: &mut Bencher) {
+    let (world, zid) = maze(64, 1);
+    bench_compute(b, &world, viewer(zid, (31, 31)), 20);
+}
+
+#[bench]
+fn portal_grid_radius_20(b: &mut Bencher) {
+    // 16x16 zones, so a radius of 20 looks through several portals each way
+    let (world, zones) = portal_grid(5, 16);
+    bench_compute(b, &world, viewer(zones[12], (8, 8)), 20);
+}
+
+#[bench]
+fn reused_scratch_radius_20(b: &mut Bencher) {
+    let (world, zid) = scattered(64, 8, 1);
+    let focus = viewer(zid, (32, 32));
+    let mut scratch = fov::FovScratch::new();
+    let mut out = Vec::new();
+    let mut run = || {
+        fov::compute_into(&world, &focus, 20, Chebyshev, &mut scratch, &mut out);
+        out.len()
+    };
+    alloc::assert_none("compute_into", || run());
+    b.iter(run);
+}
+
+#[bench]
+fn euclidean_radius_20(b: &mut Bencher) {
+    let (world, zid) = scattered(64, 8, 1);
+    let (mut start_ang, mut end_ang) = angle_buffers(20);
+    b.iter(|| {
+        fov::compute_with_metric(&world, viewer(zid, (32, 32)), 20, Euclidean,
+                                 start_ang.as_mut_slice(), end_ang.as_mut_slice())
+    });
+}
+
+#[bench]
+fn symmetric_radius_20(b: &mut Bencher) {
+    let (world, zid) = scattered(64, 8, 1);
+    b.iter(|| fov::compute_symmetric(&world, viewer(zid, (32, 32)), 20, Chebyshev));
+}
+
+#[bench]
+fn many_viewers_radius_8(b: &mut Bencher) {
+    let (world, zid) = scattered(64, 8, 1);
+    let viewers: Vec<(RelativeCoord, uint)> = range(0u, 32)
+        .map(|i| (viewer(zid, (8 + (i % 8) * 6, 8 + (i / 8) * 12)), 8))
+        .collect();
+    let (mut start_ang, mut end_ang) = angle_buffers(8);
+    b.iter(|| {
+        fov::compute_many(&world, viewers.as_slice(), Chebyshev,
+                          start_ang.as_mut_slice(), end_ang.as_mut_slice())
+    });
+}
@@ -0,0 +1,76 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// timings for `World::try_traversal`, within a zone and across portals.
+// Stepping is meant never to allocate, which each bench checks before
+// timing it.
+
+#![feature(globs)]
+
+extern crate test;
+extern crate libc;
+extern crate serialize;
+extern crate uuid;
+extern crate p2d;
+
+use test::Bencher;
+
+use p2d::world::GlobalCoord;
+use p2d::world::TraversalDirection::*;
+use p2d::zone::ZoneTraversalResult::Destination;
+
+use support::{open_field, portal_grid};
+use support::alloc;
+
+#[path = "../tests/support/mod.rs"]
+mod support;
+
+#[bench]
+fn step_within_zone(b: &mut Bencher) {
+    let (world, zid) = open_field(64);
+    let mut lap = || {
+        // a lap around the middle of the zone
+        let mut curr = GlobalCoord::new(zid, (16, 16));
+        for dir in [East, South, West, North].iter() {
+            for _ in range(0u, 32) {
+                curr = match world.try_traversal(curr.clone(), *dir) {
+                    Destination(gc) => gc,
+                    _ => panic!("step_within_zone: blocked at {}", curr)
+                };
+            }
+        }
+        curr
+    };
+    alloc::assert_none("a lap within a zone", || lap());
+    b.iter(lap);
+}
+
+#[bench]
+fn step_across_portals(b: &mut Bencher) {
+    // a row of 16x16 zones, walked end to end
+    let (world, zones) = portal_grid(8, 16);
+    let mut walk = || {
+        let mut curr = GlobalCoord::new(zones[0], (0, 8));
+        loop {
+            curr = match world.try_traversal(curr.clone(), East) {
+                Destination(gc) => gc,
+                _ => break
+            };
+        }
+        curr
+    };
+    alloc::assert_none("a walk across portals", || walk());
+    b.iter(walk);
+}
+
+#[bench]
+fn blocked_at_edge(b: &mut Bencher) {
+    let (world, zid) = open_field(64);
+    let edge = GlobalCoord::new(zid, (0, 32));
+    alloc::assert_none("a blocked step", || world.try_traversal(edge.clone(), West));
+    b.iter(|| world.try_traversal(edge.clone(), West));
+}
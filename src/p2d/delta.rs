@@ -0,0 +1,239 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// the difference between two states of a `World`, small enough to send to
+// clients each tick instead of the whole world. Applying `diff(old, new)` to a
+// copy of `old` leaves it matching `new`.
+
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use world::{World, Payloadable};
+use zone::{Zone, Tile};
+use layer::LayerCell;
+use portal::Portal;
+
+/// Everything that changed between two `World`s.
+#[deriving(Clone, Encodable, Decodable)]
+pub struct WorldDelta<TWorldPayload, TZonePayload, TTilePayload> {
+    /// The world's new `data`, if it changed.
+    pub data: Option<TWorldPayload>,
+    /// Zones that are new, or were replaced by a zone of a different size or
+    /// with different layers, sent whole (less anything they'd recorded).
+    pub added_zones: Vec<Zone<TZonePayload, TTilePayload>>,
+    pub removed_zones: Vec<Uuid>,
+    /// Portals that are new or have changed, sent whole. The tiles they
+    /// cover come with their zones.
+    pub added_portals: Vec<Portal>,
+    pub removed_portals: Vec<Uuid>,
+    pub zones: Vec<ZoneDelta<TZonePayload, TTilePayload>>
+}
+
+/// What changed within a zone that's in both worlds.
+#[deriving(Clone, Encodable, Decodable)]
+pub struct ZoneDelta<TZonePayload, TTilePayload> {
+    pub zone_id: Uuid,
+    /// The zone's new `data`, if it changed.
+    pub data: Option<TZonePayload>,
+    /// Changed tiles, by index (as for `Zone::tile_at_idx`).
+    pub tiles: Vec<(uint, Tile<TTilePayload>)>,
+    /// Changed layer cells, by layer name and index.
+    pub layer_cells: Vec<(String, uint, LayerCell<TTilePayload>)>,
+    /// Portals whose origin is new or has moved (`Some`), or that are gone
+    /// (`None`).
+    pub portals: Vec<(Uuid, Option<(uint, uint)>)>,
+    /// Payloads that are new or have moved (`Some`), or that are gone
+    /// (`None`).
+    pub payloads: Vec<(Uuid, Option<(uint, uint)>)>
+}
+
+impl<TZonePayload, TTilePayload> ZoneDelta<TZonePayload, TTilePayload> {
+    pub fn is_empty(&self) -> bool {
+        self.data.is_none() && self.tiles.is_empty() && self.layer_cells.is_empty()
+            && self.portals.is_empty() && self.payloads.is_empty()
+    }
+}
+
+impl<TWorldPayload, TZonePayload, TTilePayload>
+        WorldDelta<TWorldPayload, TZonePayload, TTilePayload> {
+    pub fn is_empty(&self) -> bool {
+        self.data.is_none() && self.added_zones.is_empty() && self.removed_zones.is_empty()
+            && self.added_portals.is_empty() && self.removed_portals.is_empty()
+            && self.zones.is_empty()
+    }
+}
+
+impl<TWorldPayload: Clone, TZonePayload: Clone,
+     TTilePayload: Clone + Send + Payloadable + PartialEq>
+        WorldDelta<TWorldPayload, TZonePayload, TTilePayload> {
+    /// Bring `world` from the state the delta was taken against to the one
+    /// it was taken to. Changes go through the usual `World` and `Zone`
+    /// methods, so revisions are bumped and events recorded as they would
+    /// be for any other edit.
+    pub fn apply(&self, world: &mut World<TWorldPayload, TZonePayload, TTilePayload>) {
+        match self.data {
+            Some(ref data) => world.data = data.clone(),
+            None => {}
+        }
+        for pid in self.removed_portals.iter() {
+            world.remove_portal(pid);
+        }
+        for zid in self.removed_zones.iter() {
+            world.remove_zone(zid);
+        }
+        for zone in self.added_zones.iter() {
+            world.insert_zone(zone.clone());
+        }
+        // a changed portal replaces the old one in place; its tiles and
+        // origins are brought up to date with the zones below
+        for portal in self.added_portals.iter() {
+            world.insert_portal(portal.clone());
+        }
+        for zd in self.zones.iter() {
+            apply_zone(world.get_zone_mut(&zd.zone_id), zd);
+        }
+    }
+}
+
+fn apply_zone<TZonePayload: Clone, TTilePayload: Clone + Send + Payloadable + PartialEq>(
+        zone: &mut Zone<TZonePayload, TTilePayload>,
+        zd: &ZoneDelta<TZonePayload, TTilePayload>) {
+    match zd.data {
+        Some(ref data) => *zone.data_mut() = data.clone(),
+        None => {}
+    }
+    for &(idx, ref tile) in zd.tiles.iter() {
+        zone.set_tile_at_idx(idx, tile.clone());
+    }
+    for &(ref name, idx, ref cell) in zd.layer_cells.iter() {
+        let coords = (idx % zone.size, idx / zone.size);
+        zone.set_layer_cell(name.as_slice(), coords, cell.clone());
+    }
+    for &(pid, coords) in zd.portals.iter() {
+        match coords {
+            Some(c) => { zone.portal_coords.insert(pid, c); },
+            None => { zone.portal_coords.remove(&pid); }
+        }
+    }
+    // origins are set directly, so the change has to be counted by hand
+    if !zd.portals.is_empty() { zone.touch(); }
+    for &(plid, coords) in zd.payloads.iter() {
+        let known = zone.find_payload(&plid).is_some();
+        match coords {
+            Some(c) if known => zone.move_payload(&plid, c),
+            Some(c) => zone.add_payload(plid, c),
+            None => { zone.remove_payload(&plid); }
+        }
+    }
+}
+
+/// The changes that turn `old` into `new`.
+pub fn diff<TWorldPayload: Clone + PartialEq, TZonePayload: Clone + PartialEq,
+            TTilePayload: Clone + PartialEq + Send + Payloadable>(
+        old: &World<TWorldPayload, TZonePayload, TTilePayload>,
+        new: &World<TWorldPayload, TZonePayload, TTilePayload>)
+            -> WorldDelta<TWorldPayload, TZonePayload, TTilePayload> {
+    let mut delta = WorldDelta {
+        data: if old.data == new.data { None } else { Some(new.data.clone()) },
+        added_zones: Vec::new(),
+        removed_zones: Vec::new(),
+        added_portals: Vec::new(),
+        removed_portals: Vec::new(),
+        zones: Vec::new()
+    };
+    for (zid, old_zone) in old.zones.iter() {
+        match new.zones.find(zid) {
+            Some(new_zone) if new_zone.size == old_zone.size
+                              && same_layers(old_zone, new_zone) => {
+                let zd = diff_zone(old_zone, new_zone);
+                if !zd.is_empty() { delta.zones.push(zd); }
+            },
+            Some(new_zone) => {
+                delta.removed_zones.push(*zid);
+                delta.added_zones.push(detached(new_zone));
+            },
+            None => delta.removed_zones.push(*zid)
+        }
+    }
+    for (zid, new_zone) in new.zones.iter() {
+        if !old.zones.contains_key(zid) {
+            delta.added_zones.push(detached(new_zone));
+        }
+    }
+    // a portal that changed is only sent again, not removed first, as
+    // removing it would clear tiles that the zone deltas wouldn't put back
+    for (pid, _) in old.portals.iter() {
+        if !new.portals.contains_key(pid) { delta.removed_portals.push(*pid); }
+    }
+    for (pid, new_portal) in new.portals.iter() {
+        if old.portals.find(pid) != Some(new_portal) {
+            delta.added_portals.push(new_portal.clone());
+        }
+    }
+    delta
+}
+
+fn diff_zone<TZonePayload: Clone + PartialEq, TTilePayload: Clone + PartialEq + Send + Payloadable>(
+        old: &Zone<TZonePayload, TTilePayload>, new: &Zone<TZonePayload, TTilePayload>)
+            -> ZoneDelta<TZonePayload, TTilePayload> {
+    let mut tiles = Vec::new();
+    for idx in range(0, new.size * new.size) {
+        let tile = new.tile_at_idx(idx);
+        if tile != old.tile_at_idx(idx) {
+            tiles.push((idx, tile.clone()));
+        }
+    }
+    let mut layer_cells = Vec::new();
+    for (old_layer, new_layer) in old.layers().iter().zip(new.layers().iter()) {
+        for idx in range(0, new.size * new.size) {
+            let cell = new_layer.cell_at_idx(idx);
+            if cell != old_layer.cell_at_idx(idx) {
+                layer_cells.push((new_layer.name.clone(), idx, cell.clone()));
+            }
+        }
+    }
+    ZoneDelta {
+        zone_id: new.id,
+        data: if old.data == new.data { None } else { Some(new.data.clone()) },
+        tiles: tiles,
+        layer_cells: layer_cells,
+        portals: diff_coords(&old.portal_coords, &new.portal_coords),
+        payloads: diff_coords(old.payload_coords(), new.payload_coords())
+    }
+}
+
+// a copy of `zone` to send whole, without the events it has waiting
+fn detached<TZonePayload: Clone, TTilePayload: Clone + Send + Payloadable>(
+        zone: &Zone<TZonePayload, TTilePayload>) -> Zone<TZonePayload, TTilePayload> {
+    let mut zone = zone.clone();
+    zone.record_events(false);
+    zone
+}
+
+// whether both zones have the same layers, in the same order, with the same
+// rules
+fn same_layers<TZonePayload, TTilePayload: Send + Payloadable>(
+        old: &Zone<TZonePayload, TTilePayload>, new: &Zone<TZonePayload, TTilePayload>) -> bool {
+    old.layers().len() == new.layers().len()
+        && old.layers().iter().zip(new.layers().iter()).all(|(a, b)| {
+            a.name == b.name && a.affects_fov == b.affects_fov
+                && a.affects_movement == b.affects_movement
+        })
+}
+
+fn diff_coords(old: &HashMap<Uuid, (uint, uint)>, new: &HashMap<Uuid, (uint, uint)>)
+        -> Vec<(Uuid, Option<(uint, uint)>)> {
+    let mut changes = Vec::new();
+    // removals first, so a payload can take over a spot another has left
+    for (id, _) in old.iter() {
+        if !new.contains_key(id) { changes.push((*id, None)); }
+    }
+    for (id, coords) in new.iter() {
+        if old.find(id) != Some(coords) { changes.push((*id, Some(*coords))); }
+    }
+    changes
+}
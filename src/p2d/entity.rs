@@ -0,0 +1,357 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// components and systems for running a `World`'s entities on `ecs`. An
+// entity with a `Body` is the payload of that id in the zones, and its
+// `Position` is kept in step with wherever the zones have it: `place`,
+// `step` and `remove` change both together, and `SyncProcess` catches up
+// with anything done to the zones directly (`World::move_payload`,
+// `World::remove_zone`, `delta::apply`, ...). Register `SyncProcess` ahead
+// of the other systems so they see where entities are now.
+//
+// The systems share the world with whoever else is changing it, as a
+// `SharedWorld`.
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+use ecs::{Aspect, Component, ComponentId, Components, Entity, EntityIter};
+use ecs::system::EntityProcess;
+
+use world::{Payloadable, World, GlobalCoord, RelativeCoord, TraversalDirection};
+use zone::ZoneTraversalResult;
+use zone::ZoneTraversalResult::*;
+use layer::BASE_LAYER;
+use fov;
+use fov::{FovItem, FovScratch, RadiusMetric};
+
+pub type SharedWorld<TWorldPayload, TZonePayload, TTilePayload> =
+    Rc<RefCell<World<TWorldPayload, TZonePayload, TTilePayload>>>;
+
+/// The id the zones know an entity by, as a payload.
+#[deriving(Clone, PartialEq, Eq, Hash, Show)]
+pub struct Body(pub Uuid);
+impl Component for Body {}
+
+/// Where an entity stands.
+#[deriving(Clone, PartialEq, Eq, Hash, Show, Encodable, Decodable)]
+pub struct Position(pub GlobalCoord);
+impl Component for Position {}
+
+/// The way an entity is to step on `MovementProcess`'s next run. Taken off
+/// once it's been tried.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Heading(pub TraversalDirection);
+impl Component for Heading {}
+
+/// What came of an entity's last `Heading`.
+#[deriving(Clone, PartialEq, Show)]
+pub struct LastStep(pub ZoneTraversalResult);
+impl Component for LastStep {}
+
+/// How far an entity can see.
+#[deriving(Clone, PartialEq, Show)]
+pub struct Sight(pub uint);
+impl Component for Sight {}
+
+/// Register the components above with an `ecs::World`, before it's
+/// finalised.
+pub fn register_components(ecs: &mut ::ecs::World) {
+    ecs.register_component::<Body>();
+    ecs.register_component::<Position>();
+    ecs.register_component::<Heading>();
+    ecs.register_component::<LastStep>();
+    ecs.register_component::<Sight>();
+}
+
+/// Put `entity` in the world at `at`, as the payload `id`.
+pub fn place<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>(
+    world: &mut World<TWorldPayload, TZonePayload, TTilePayload>, components: &mut Components,
+    entity: Entity, id: Uuid, at: GlobalCoord) {
+    if components.has::<Body>(&entity) {
+        panic!("entity::place: entity {} has already been placed", id);
+    }
+    world.get_zone_mut(&at.zone_id).add_payload(id, at.coords);
+    components.add(&entity, Body(id));
+    components.add(&entity, Position(at));
+}
+
+/// Take `entity` out of the world, returning where it was.
+pub fn remove<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>(
+    world: &mut World<TWorldPayload, TZonePayload, TTilePayload>, components: &mut Components,
+    entity: Entity) -> Option<Position> {
+    let Body(id) = match components.get::<Body>(&entity) {
+        Some(body) => body,
+        None => return None
+    };
+    let at = components.get::<Position>(&entity);
+    match at {
+        Some(Position(ref gc)) => {
+            match world.zones.find_mut(&gc.zone_id) {
+                Some(zone) => { zone.remove_payload(&id); },
+                None => {}
+            }
+        },
+        None => {}
+    }
+    components.remove::<Body>(&entity);
+    components.remove::<Position>(&entity);
+    at
+}
+
+/// Step `entity` one tile in `dir`, through portals as
+/// `World::try_traversal` allows. Another payload standing where it would
+/// land stops it, as `DestinationOccupied`.
+pub fn step<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>(
+    world: &mut World<TWorldPayload, TZonePayload, TTilePayload>, components: &mut Components,
+    entity: Entity, dir: TraversalDirection) -> ZoneTraversalResult {
+    let (Body(id), Position(from)) =
+        match (components.get::<Body>(&entity), components.get::<Position>(&entity)) {
+            (Some(body), Some(at)) => (body, at),
+            _ => panic!("entity::step: entity hasn't been placed")
+        };
+    let result = world.try_traversal(from.clone(), dir);
+    match result {
+        Destination(ref to) => {
+            let occupant = world.get_zone(&to.zone_id).spatial()
+                .in_rect(to.coords, to.coords).into_iter()
+                .map(|(other, _)| other)
+                .find(|other| *other != id);
+            match occupant {
+                Some(other) => return DestinationOccupied(other),
+                None => {}
+            }
+            world.move_payload(&id, &from.zone_id, to.clone());
+            components.set(&entity, Position(to.clone()));
+        },
+        _ => {}
+    }
+    result
+}
+
+/// Brings every `Position` up to date with where the zones have the
+/// entity's `Body`, taking it away if the payload's gone from the world.
+pub struct SyncProcess<TWorldPayload, TZonePayload, TTilePayload> {
+    world: SharedWorld<TWorldPayload, TZonePayload, TTilePayload>
+}
+
+impl<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>
+        SyncProcess<TWorldPayload, TZonePayload, TTilePayload> {
+    pub fn new(world: SharedWorld<TWorldPayload, TZonePayload, TTilePayload>)
+            -> SyncProcess<TWorldPayload, TZonePayload, TTilePayload> {
+        SyncProcess { world: world }
+    }
+    pub fn aspect(&self) -> Aspect {
+        Aspect::for_all(vec!(ComponentId::of::<Body>()))
+    }
+}
+
+impl<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>
+        EntityProcess for SyncProcess<TWorldPayload, TZonePayload, TTilePayload> {
+    fn process(&self, entities: EntityIter, components: &mut Components) {
+        let world = self.world.borrow();
+        for entity in entities {
+            let Body(id) = components.get::<Body>(&entity).unwrap();
+            let known = components.get::<Position>(&entity);
+            // most likely it's still in the zone it was last seen in
+            let in_known = match known {
+                Some(Position(ref gc)) => world.zones.find(&gc.zone_id)
+                    .and_then(|zone| zone.find_payload(&id))
+                    .map(|coords| GlobalCoord::new(gc.zone_id, coords)),
+                None => None
+            };
+            let now = match in_known {
+                Some(gc) => Some(gc),
+                None => world.find_payload(&id)
+            };
+            match (known, now) {
+                (Some(ref was), Some(ref gc)) if *was == Position(gc.clone()) => {},
+                (Some(_), Some(gc)) => { components.set(&entity, Position(gc)); },
+                (None, Some(gc)) => { components.add(&entity, Position(gc)); },
+                (Some(_), None) => { components.remove::<Position>(&entity); },
+                (None, None) => {}
+            }
+        }
+    }
+}
+
+/// Steps entities with a `Heading` one tile each per run, leaving what
+/// came of it as their `LastStep`. Entities step in order of their ids, so
+/// when two go for the same tile the same one gets it every time.
+pub struct MovementProcess<TWorldPayload, TZonePayload, TTilePayload> {
+    world: SharedWorld<TWorldPayload, TZonePayload, TTilePayload>
+}
+
+impl<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>
+        MovementProcess<TWorldPayload, TZonePayload, TTilePayload> {
+    pub fn new(world: SharedWorld<TWorldPayload, TZonePayload, TTilePayload>)
+            -> MovementProcess<TWorldPayload, TZonePayload, TTilePayload> {
+        MovementProcess { world: world }
+    }
+    pub fn aspect(&self) -> Aspect {
+        Aspect::for_all(vec!(ComponentId::of::<Body>(), ComponentId::of::<Position>(),
+                             ComponentId::of::<Heading>()))
+    }
+}
+
+impl<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>
+        EntityProcess for MovementProcess<TWorldPayload, TZonePayload, TTilePayload> {
+    fn process(&self, entities: EntityIter, components: &mut Components) {
+        let mut world = self.world.borrow_mut();
+        let mut movers: Vec<(Uuid, Entity, TraversalDirection)> = entities.map(|entity| {
+            let Body(id) = components.get::<Body>(&entity).unwrap();
+            let Heading(dir) = components.get::<Heading>(&entity).unwrap();
+            (id, entity, dir)
+        }).collect();
+        movers.sort_by(|&(a, _, _), &(b, _, _)| a.as_bytes().cmp(&b.as_bytes()));
+        for &(_, entity, dir) in movers.iter() {
+            let result = step(&mut *world, components, entity, dir);
+            components.remove::<Heading>(&entity);
+            if components.has::<LastStep>(&entity) {
+                components.set(&entity, LastStep(result));
+            } else {
+                components.add(&entity, LastStep(result));
+            }
+        }
+    }
+}
+
+/// What each entity with `Sight` could see as of the last run, by its
+/// `Body` id.
+pub type Visible = Rc<RefCell<HashMap<Uuid, Vec<RelativeCoord>>>>;
+
+/// Works out what every entity with `Sight` can see, from wherever it
+/// stands.
+pub struct VisionProcess<TWorldPayload, TZonePayload, TTilePayload> {
+    world: SharedWorld<TWorldPayload, TZonePayload, TTilePayload>,
+    metric: RadiusMetric,
+    scratch: RefCell<FovScratch<TTilePayload>>,
+    visible: Visible
+}
+
+impl<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>
+        VisionProcess<TWorldPayload, TZonePayload, TTilePayload> {
+    pub fn new(world: SharedWorld<TWorldPayload, TZonePayload, TTilePayload>,
+               metric: RadiusMetric)
+            -> VisionProcess<TWorldPayload, TZonePayload, TTilePayload> {
+        VisionProcess {
+            world: world,
+            metric: metric,
+            scratch: RefCell::new(FovScratch::new()),
+            visible: Rc::new(RefCell::new(HashMap::new()))
+        }
+    }
+    pub fn aspect(&self) -> Aspect {
+        Aspect::for_all(vec!(ComponentId::of::<Body>(), ComponentId::of::<Position>(),
+                             ComponentId::of::<Sight>()))
+    }
+    /// Where the results end up; take a handle before registering the
+    /// system.
+    pub fn visible(&self) -> Visible { self.visible.clone() }
+}
+
+impl<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable + FovItem>
+        EntityProcess for VisionProcess<TWorldPayload, TZonePayload, TTilePayload> {
+    fn process(&self, entities: EntityIter, components: &mut Components) {
+        let world = self.world.borrow();
+        let mut scratch = self.scratch.borrow_mut();
+        let mut visible = self.visible.borrow_mut();
+        let mut seen = HashSet::new();
+        for entity in entities {
+            let Body(id) = components.get::<Body>(&entity).unwrap();
+            let Position(gc) = components.get::<Position>(&entity).unwrap();
+            let Sight(radius) = components.get::<Sight>(&entity).unwrap();
+            let focus = RelativeCoord::new(gc.zone_id, gc.coords, (0, 0));
+            if !visible.contains_key(&id) {
+                visible.insert(id, Vec::new());
+            }
+            let out = visible.find_mut(&id).unwrap();
+            fov::compute_into(&*world, &focus, radius, self.metric, &mut *scratch, out);
+            seen.insert(id);
+        }
+        // whoever has lost their sight, or left the world
+        let gone: Vec<Uuid> = visible.keys().filter(|id| !seen.contains(*id))
+            .map(|id| *id).collect();
+        for id in gone.iter() {
+            visible.remove(id);
+        }
+    }
+}
+
+/// Whatever draws zones and the entities in them, e.g. a `p2dux` view.
+/// Tiles are kept until cleared, so only zones that changed are drawn
+/// again; entities are drawn afresh every run.
+pub trait Renderer<TTilePayload> {
+    /// Forget everything drawn for zone `zid`, which is about to be drawn
+    /// again or has gone.
+    fn clear_zone(&mut self, zid: &Uuid);
+    /// Draw the sprite for `payload`, from the layer named `layer` (or
+    /// `BASE_LAYER`, for the zone's own tiles), at `at`.
+    fn draw_tile(&mut self, layer: &str, payload: &TTilePayload, at: &GlobalCoord);
+    fn draw(&mut self, id: &Uuid, at: &GlobalCoord);
+}
+
+/// Hands a `Renderer` the tiles of each zone whose revision has changed
+/// since it was last drawn, a layer at a time from the bottom up, then
+/// every placed entity over them.
+pub struct RenderProcess<TWorldPayload, TZonePayload, TTilePayload, TRenderer> {
+    world: SharedWorld<TWorldPayload, TZonePayload, TTilePayload>,
+    renderer: Rc<RefCell<TRenderer>>,
+    // the revision each zone was at when it was last drawn
+    drawn: RefCell<HashMap<Uuid, uint>>
+}
+
+impl<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable, TRenderer>
+        RenderProcess<TWorldPayload, TZonePayload, TTilePayload, TRenderer> {
+    pub fn new(world: SharedWorld<TWorldPayload, TZonePayload, TTilePayload>,
+               renderer: Rc<RefCell<TRenderer>>)
+            -> RenderProcess<TWorldPayload, TZonePayload, TTilePayload, TRenderer> {
+        RenderProcess { world: world, renderer: renderer, drawn: RefCell::new(HashMap::new()) }
+    }
+    pub fn aspect(&self) -> Aspect {
+        Aspect::for_all(vec!(ComponentId::of::<Body>(), ComponentId::of::<Position>()))
+    }
+}
+
+impl<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable,
+     TRenderer: Renderer<TTilePayload>>
+        EntityProcess for RenderProcess<TWorldPayload, TZonePayload, TTilePayload, TRenderer> {
+    fn process(&self, entities: EntityIter, components: &mut Components) {
+        let world = self.world.borrow();
+        let mut renderer = self.renderer.borrow_mut();
+        let mut drawn = self.drawn.borrow_mut();
+        let gone: Vec<Uuid> = drawn.keys().filter(|zid| !world.zones.contains_key(*zid))
+            .map(|zid| *zid).collect();
+        for zid in gone.iter() {
+            renderer.clear_zone(zid);
+            drawn.remove(zid);
+        }
+        for (zid, zone) in world.zones.iter() {
+            if drawn.find(zid) == Some(&zone.revision()) { continue; }
+            renderer.clear_zone(zid);
+            let limit = zone.size * zone.size;
+            for idx in range(0, limit) {
+                let at = GlobalCoord::new(*zid, (idx % zone.size, idx / zone.size));
+                renderer.draw_tile(BASE_LAYER, &zone.tile_at_idx(idx).payload, &at);
+            }
+            for layer in zone.layers().iter() {
+                for idx in range(0, limit) {
+                    let at = GlobalCoord::new(*zid, (idx % zone.size, idx / zone.size));
+                    renderer.draw_tile(layer.name.as_slice(), &layer.cell_at_idx(idx).payload,
+                                       &at);
+                }
+            }
+            drawn.insert(*zid, zone.revision());
+        }
+        for entity in entities {
+            let Body(id) = components.get::<Body>(&entity).unwrap();
+            let Position(gc) = components.get::<Position>(&entity).unwrap();
+            renderer.draw(&id, &gc);
+        }
+    }
+}
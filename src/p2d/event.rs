@@ -0,0 +1,191 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// a record of changes made to a `World` and its zones, for anything that has
+// to keep up with them (UI, networking, caches). Nothing is recorded until
+// `World::record_events` is turned on. What's recorded is held until it's
+// drained, but only so much of it: past a limit the oldest events are
+// dropped, and whoever drains next is told how many with `EventsDropped`.
+
+use std::mem;
+use std::collections::{HashMap, RingBuf};
+use uuid::Uuid;
+
+use world::GlobalCoord;
+
+use self::WorldEvent::*;
+
+#[deriving(Clone, PartialEq, Show, Encodable, Decodable)]
+pub enum WorldEvent {
+    /// A zone was created by `World::new_zone`.
+    ZoneAdded(Uuid),
+    /// A zone was taken out of the world by `World::remove_zone`.
+    ZoneRemoved(Uuid),
+    /// A portal was created between two zones by `World::new_portal` (or
+    /// `new_portal_span`).
+    PortalAdded(Uuid),
+    /// A portal was taken out of the world by `World::remove_portal`.
+    PortalRemoved(Uuid),
+    /// Zone `0` took on portal `1`, with its origin at `2`.
+    ZonePortalAdded(Uuid, Uuid, (uint, uint)),
+    /// Zone `0` gave up portal `1`, which had its origin at `2`.
+    ZonePortalRemoved(Uuid, Uuid, (uint, uint)),
+    /// A tile was borrowed mutably, so may have changed.
+    TileChanged(GlobalCoord),
+    PayloadAdded(Uuid, GlobalCoord),
+    /// A payload moved from `1` to `2`; these are in different zones for a
+    /// move made with `World::move_payload`.
+    PayloadMoved(Uuid, GlobalCoord, GlobalCoord),
+    PayloadRemoved(Uuid, GlobalCoord),
+    /// At least `0` events were dropped here, for not being drained in
+    /// time. Anything kept in step with the world by its events has to be
+    /// brought up to date some other way.
+    EventsDropped(uint)
+}
+
+/// How many events are held for draining, by default, before the oldest
+/// start being dropped. See `World::limit_events`.
+pub static DEFAULT_EVENT_LIMIT: uint = 4096;
+
+impl WorldEvent {
+    /// The zone the event happened in, if it belongs to just one.
+    pub fn zone_id(&self) -> Option<Uuid> {
+        match self {
+            &ZoneAdded(zid) => Some(zid),
+            &ZoneRemoved(zid) => Some(zid),
+            &PortalAdded(_) | &PortalRemoved(_) => None,
+            &ZonePortalAdded(zid, _, _) => Some(zid),
+            &ZonePortalRemoved(zid, _, _) => Some(zid),
+            &TileChanged(ref gc) => Some(gc.zone_id),
+            &PayloadAdded(_, ref gc) => Some(gc.zone_id),
+            &PayloadMoved(_, ref from, ref to) if from.zone_id == to.zone_id => {
+                Some(from.zone_id)
+            },
+            &PayloadMoved(..) => None,
+            &PayloadRemoved(_, ref gc) => Some(gc.zone_id),
+            &EventsDropped(_) => None
+        }
+    }
+}
+
+/// Events waiting to be handed out, kept by the `World` and by each `Zone`,
+/// and for each subscriber. Holds at most `limit` of them.
+#[deriving(Clone, Encodable, Decodable)]
+pub struct EventBuffer {
+    recording: bool,
+    pending: RingBuf<WorldEvent>,
+    limit: uint,
+    // how many have been pushed out of the front since the last `take`
+    dropped: uint
+}
+
+impl EventBuffer {
+    pub fn new() -> EventBuffer {
+        EventBuffer {
+            recording: false,
+            pending: RingBuf::new(),
+            limit: DEFAULT_EVENT_LIMIT,
+            dropped: 0
+        }
+    }
+    pub fn is_recording(&self) -> bool { self.recording }
+    /// Start or stop recording; stopping throws away anything pending.
+    pub fn set_recording(&mut self, on: bool) {
+        self.recording = on;
+        if !on {
+            self.pending.clear();
+            self.dropped = 0;
+        }
+    }
+    pub fn limit(&self) -> uint { self.limit }
+    /// Hold at most `limit` events, dropping the oldest to make room.
+    pub fn set_limit(&mut self, limit: uint) {
+        if limit == 0 {
+            panic!("EventBuffer::set_limit: there has to be room for something");
+        }
+        self.limit = limit;
+        while self.pending.len() > limit { self.drop_oldest(); }
+    }
+    pub fn push(&mut self, event: WorldEvent) {
+        if !self.recording { return; }
+        match event {
+            // passed on from another buffer; the count is all that matters
+            EventsDropped(n) => self.dropped += n,
+            event => {
+                if self.pending.len() >= self.limit { self.drop_oldest(); }
+                self.pending.push_back(event);
+            }
+        }
+    }
+    /// Everything pending, oldest first, after an `EventsDropped` if any
+    /// had to go.
+    pub fn take(&mut self) -> Vec<WorldEvent> {
+        let mut taken = Vec::with_capacity(self.pending.len() + 1);
+        if self.dropped > 0 {
+            taken.push(EventsDropped(self.dropped));
+            self.dropped = 0;
+        }
+        taken.extend(mem::replace(&mut self.pending, RingBuf::new()).into_iter());
+        taken
+    }
+    fn drop_oldest(&mut self) {
+        self.pending.pop_front();
+        self.dropped += 1;
+    }
+}
+
+pub type SubscriberId = uint;
+
+/// Each subscriber's queue of events it hasn't drained yet.
+#[deriving(Encodable, Decodable)]
+pub struct Subscribers {
+    queues: HashMap<SubscriberId, EventBuffer>,
+    next_id: SubscriberId,
+    limit: uint
+}
+
+impl Subscribers {
+    pub fn new() -> Subscribers {
+        Subscribers { queues: HashMap::new(), next_id: 0, limit: DEFAULT_EVENT_LIMIT }
+    }
+    /// As `EventBuffer::set_limit`, for every subscriber's queue.
+    pub fn set_limit(&mut self, limit: uint) {
+        self.limit = limit;
+        for (_, queue) in self.queues.iter_mut() {
+            queue.set_limit(limit);
+        }
+    }
+    pub fn is_empty(&self) -> bool { self.queues.is_empty() }
+    pub fn subscribe(&mut self) -> SubscriberId {
+        let id = self.next_id;
+        self.next_id += 1;
+        let mut queue = EventBuffer::new();
+        queue.set_limit(self.limit);
+        queue.set_recording(true);
+        self.queues.insert(id, queue);
+        id
+    }
+    pub fn unsubscribe(&mut self, id: SubscriberId) -> bool {
+        let known = self.queues.contains_key(&id);
+        self.queues.remove(&id);
+        known
+    }
+    /// Hand `events` to every subscriber.
+    pub fn publish(&mut self, events: &[WorldEvent]) {
+        if events.is_empty() { return; }
+        for (_, queue) in self.queues.iter_mut() {
+            for e in events.iter() {
+                queue.push(e.clone());
+            }
+        }
+    }
+    pub fn drain(&mut self, id: SubscriberId) -> Vec<WorldEvent> {
+        let queue = self.queues.find_mut(&id).expect(
+            format!("Subscribers::drain: no subscriber {}", id).as_slice());
+        queue.take()
+    }
+}
@@ -0,0 +1,243 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// flow fields: for a single target, the direction every tile that can reach
+// it should step in next. Lots of units heading for the same place can share
+// one field instead of each planning its own route.
+
+use std::f64;
+use std::uint;
+use std::collections::{HashMap, HashSet, BinaryHeap};
+use uuid::Uuid;
+
+use world::{Payloadable, World, GlobalCoord, TraversalDirection};
+use world::TraversalDirection::NoDirection;
+use path::{MovementCost, Frontier, DIRECTIONS, neighbors, predecessors, step_cost};
+
+struct FlowEntry {
+    cost: f64,
+    dir: TraversalDirection,
+    // the tile stepped onto by following `dir`
+    next: Option<GlobalCoord>
+}
+
+pub struct FlowField<TMover> {
+    pub target: GlobalCoord,
+    mover: TMover,
+    entries: HashMap<GlobalCoord, FlowEntry>,
+    // the revision of each zone the field covers, as of the last update
+    revisions: HashMap<Uuid, uint>,
+    // every zone looked into while relaxing, even those that turned out to
+    // be blocked off, since a change there could open up a new route
+    reached: HashSet<Uuid>
+}
+
+impl<TMover> FlowField<TMover> {
+    pub fn new<TWorldPayload, TZonePayload,
+               TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+        world: &World<TWorldPayload, TZonePayload, TTilePayload>, target: GlobalCoord,
+        mover: TMover) -> FlowField<TMover> {
+        let mut field = FlowField {
+            target: target,
+            mover: mover,
+            entries: HashMap::new(),
+            revisions: HashMap::new(),
+            reached: HashSet::new()
+        };
+        field.rebuild(world);
+        field
+    }
+
+    /// The direction to step in from `gc` to get closer to the target;
+    /// `NoDirection` on the target itself and `None` where it can't be
+    /// reached from. On portal tiles this may be the portal's own direction,
+    /// so following it with `World::try_traversal` steps through.
+    pub fn direction(&self, gc: &GlobalCoord) -> Option<TraversalDirection> {
+        self.entries.find(gc).map(|e| e.dir)
+    }
+
+    /// The total cost of getting from `gc` to the target.
+    pub fn cost(&self, gc: &GlobalCoord) -> Option<f64> {
+        self.entries.find(gc).map(|e| e.cost)
+    }
+
+    /// Throw the whole field away and compute it again.
+    pub fn rebuild<TWorldPayload, TZonePayload,
+                   TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+        &mut self, world: &World<TWorldPayload, TZonePayload, TTilePayload>) {
+        self.entries.clear();
+        self.reached.clear();
+        let mut frontier = BinaryHeap::new();
+        if step_cost(world, &self.target, &self.mover) != f64::INFINITY {
+            self.entries.insert(self.target.clone(),
+                                FlowEntry { cost: 0.0, dir: NoDirection, next: None });
+            frontier.push(Frontier { cost: 0.0, gc: self.target.clone() });
+        }
+        self.relax(world, frontier);
+        self.update_revisions(world);
+    }
+
+    /// Bring the field up to date with any zones that have changed since it
+    /// was last computed, only redoing the parts of it that relied on those
+    /// zones. Returns `false` if nothing needed doing.
+    pub fn refresh<TWorldPayload, TZonePayload,
+                   TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+        &mut self, world: &World<TWorldPayload, TZonePayload, TTilePayload>) -> bool {
+        let mut changed = HashSet::new();
+        for (zid, revision) in self.revisions.iter() {
+            let stale = match world.zones.find(zid) {
+                Some(z) => z.revision() != *revision,
+                None => true
+            };
+            if stale { changed.insert(*zid); }
+        }
+        if changed.len() == 0 { return false; }
+        if changed.contains(&self.target.zone_id) {
+            self.rebuild(world);
+            return true;
+        }
+        debug!("flow: refreshing {} changed zones", changed.len());
+
+        // everything in a changed zone, or whose route runs through one, has
+        // to be worked out again
+        let mut invalid: HashSet<GlobalCoord> = HashSet::new();
+        let mut valid: HashSet<GlobalCoord> = HashSet::new();
+        let tiles: Vec<GlobalCoord> = self.entries.keys().map(|gc| gc.clone()).collect();
+        for gc in tiles.into_iter() {
+            let mut chain = Vec::new();
+            let mut curr = Some(gc);
+            let mut is_invalid = false;
+            // follow the route until it reaches the target or a tile whose
+            // fate is already known
+            loop {
+                let c = match curr {
+                    Some(c) => c,
+                    None => break
+                };
+                if invalid.contains(&c) || changed.contains(&c.zone_id) {
+                    is_invalid = true;
+                    break;
+                }
+                if valid.contains(&c) { break; }
+                curr = self.entries.find(&c).and_then(|e| e.next.clone());
+                chain.push(c);
+            }
+            for c in chain.into_iter() {
+                if is_invalid { invalid.insert(c); } else { valid.insert(c); }
+            }
+        }
+        for gc in invalid.iter() {
+            self.entries.remove(gc);
+        }
+        // tiles in changed zones that weren't reachable before might be now
+        for zid in changed.iter() {
+            match world.zones.find(zid) {
+                Some(zone) => {
+                    for y in range(0, zone.size) {
+                        for x in range(0, zone.size) {
+                            invalid.insert(GlobalCoord::new(*zid, (x, y)));
+                        }
+                    }
+                },
+                None => {}
+            }
+        }
+        // start again from the edges of what's still known to be right
+        let mut frontier = BinaryHeap::new();
+        for gc in invalid.iter() {
+            if world.zones.find(&gc.zone_id).is_none() { continue; }
+            for (_, n, _) in neighbors(world, gc, &self.mover).into_iter() {
+                match self.entries.find(&n) {
+                    Some(e) => frontier.push(Frontier { cost: e.cost, gc: n.clone() }),
+                    None => {}
+                }
+            }
+        }
+        self.relax(world, frontier);
+        self.update_revisions(world);
+        true
+    }
+
+    fn relax<TWorldPayload, TZonePayload,
+             TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+        &mut self, world: &World<TWorldPayload, TZonePayload, TTilePayload>,
+        mut frontier: BinaryHeap<Frontier>) {
+        loop {
+            let Frontier { cost, gc } = match frontier.pop() {
+                Some(f) => f,
+                None => break
+            };
+            match self.entries.find(&gc) {
+                Some(e) if cost > e.cost => continue,
+                _ => {}
+            }
+            for zid in zones_around(world, &gc).into_iter() {
+                self.reached.insert(zid);
+            }
+            let step = step_cost(world, &gc, &self.mover);
+            if step == f64::INFINITY { continue; }
+            let next = cost + step;
+            for (dir, prev) in predecessors(world, &gc, &self.mover).into_iter() {
+                let better = match self.entries.find(&prev) {
+                    Some(e) => next < e.cost,
+                    None => true
+                };
+                if better {
+                    self.entries.insert(prev.clone(), FlowEntry {
+                        cost: next, dir: dir, next: Some(gc.clone())
+                    });
+                    frontier.push(Frontier { cost: next, gc: prev });
+                }
+            }
+        }
+    }
+
+    fn update_revisions<TWorldPayload, TZonePayload,
+                        TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+        &mut self, world: &World<TWorldPayload, TZonePayload, TTilePayload>) {
+        self.revisions.clear();
+        self.reached.insert(self.target.zone_id);
+        for gc in self.entries.keys() {
+            self.reached.insert(gc.zone_id);
+        }
+        for zid in self.reached.iter() {
+            match world.zones.find(zid) {
+                Some(zone) => { self.revisions.insert(*zid, zone.revision()); },
+                // gone already; `refresh` will see it as stale
+                None => { self.revisions.insert(*zid, uint::MAX); }
+            }
+        }
+    }
+}
+
+// the zone `gc` is in, plus those on the far side of any portal on it or on
+// the tiles beside it, whether or not they can be stepped through
+fn zones_around<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>(
+    world: &World<TWorldPayload, TZonePayload, TTilePayload>, gc: &GlobalCoord) -> Vec<Uuid> {
+    let zone = world.get_zone(&gc.zone_id);
+    let (x, y) = gc.coords;
+    let mut found = vec!(zone.id);
+    let mut tiles = vec!(gc.coords);
+    for dir in DIRECTIONS.iter() {
+        if dir.is_vertical() { continue; }
+        let (dx, dy) = dir.delta();
+        let (nx, ny) = (x as int + dx, y as int + dy);
+        if nx >= 0 && ny >= 0 && zone.coords_in_bounds((nx as uint, ny as uint)) {
+            tiles.push((nx as uint, ny as uint));
+        }
+    }
+    for c in tiles.into_iter() {
+        match zone.get_tile(c).portal_id {
+            Some(pid) => {
+                let (ozid, _) = world.get_portal(pid).info_from(zone.id);
+                if !found.contains(&ozid) { found.push(ozid); }
+            },
+            None => {}
+        }
+    }
+    found
+}
@@ -7,19 +7,28 @@
 
 use std::vec::Vec;
 use std::default::Default;
-use std::collections::HashSet;
+use std::num::Float;
+use std::f64::consts::PI;
+use std::mem;
+use std::collections::{HashMap, HashSet, RingBuf, Bitv};
+#[cfg(feature = "parallel")]
+use std::sync::{Arc, TaskPool};
 use uuid::Uuid;
 
-use world::{Payloadable, World, RelativeCoord, TraversalDirection};
+use world::{Payloadable, World, GlobalCoord, RelativeCoord, TraversalDirection};
 use world::TraversalDirection::*;
-use zone::{Zone, Tile};
+use zone::{Zone, Tile, coords_to_idx};
 
 pub use self::FovType::*;
+pub use self::RadiusMetric::*;
 
 #[deriving(Clone, Encodable, Decodable, Copy, Show, PartialEq)]
 pub enum FovType {
     Blocking,
     Transparent,
+    /// Lets some light through, e.g. smoke, foliage or tinted glass. Holds
+    /// the tile's opacity, from 0.0 (clear) to 1.0 (solid).
+    Translucent(f64),
     Void
 }
 impl Default for FovType {
@@ -28,91 +37,1028 @@ impl Default for FovType {
 impl FovType {
     pub fn allow_los(&self) -> bool {
         match *self {
-            Transparent | Void => true,
+            Transparent | Translucent(_) | Void => true,
             Blocking => false
         }
     }
+    pub fn opacity(&self) -> f64 {
+        match *self {
+            Blocking => 1.0,
+            Translucent(o) => o,
+            Transparent | Void => 0.0
+        }
+    }
 }
 
 pub trait FovItem {
     fn get_fov(&self) -> FovType;
 }
 
+/// How distance from the viewer is measured against the FOV radius, and so
+/// the shape of the area that can be seen.
+#[deriving(Clone, Copy, PartialEq, Eq, Hash, Show, Encodable, Decodable)]
+pub enum RadiusMetric {
+    /// max(dx, dy); a square
+    Chebyshev,
+    /// straight-line distance; a circle
+    Euclidean,
+    /// dx + dy; a diamond
+    Manhattan
+}
+impl Default for RadiusMetric {
+    fn default() -> RadiusMetric { Chebyshev }
+}
+impl RadiusMetric {
+    pub fn within(&self, dx: int, dy: int, radius: uint) -> bool {
+        let (dx, dy, r) = (dx.abs() as uint, dy.abs() as uint, radius);
+        match *self {
+            Chebyshev => dx <= r && dy <= r,
+            Euclidean => dx * dx + dy * dy <= r * r,
+            Manhattan => dx + dy <= r
+        }
+    }
+    /// How far (dx, dy) is from the viewer, by this metric.
+    pub fn distance(&self, dx: int, dy: int) -> f64 {
+        let (dx, dy) = (dx.abs() as f64, dy.abs() as f64);
+        match *self {
+            Chebyshev => dx.max(dy),
+            Euclidean => (dx * dx + dy * dy).sqrt(),
+            Manhattan => dx + dy
+        }
+    }
+}
+
+/// The algorithms a `FovCache` can keep results for.
+#[deriving(Clone, Copy, PartialEq, Eq, Hash, Show)]
+pub enum FovAlgorithm {
+    /// The modified MRPAS used by `compute`, with the given radius metric
+    Mrpas(RadiusMetric),
+    /// The line-of-sight test used by `compute_symmetric` and
+    /// `symmetric_observers_of`
+    Symmetric(RadiusMetric)
+}
+
+struct CachedFov {
+    tiles: Vec<RelativeCoord>,
+    // the revision of every zone the result could depend on
+    revisions: Vec<(Uuid, uint)>
+}
+
+/// Keeps the results of earlier FOV computations, so one can be reused
+/// while neither the viewer nor any zone it saw into has changed.
+pub struct FovCache {
+    entries: HashMap<(RelativeCoord, uint, FovAlgorithm), CachedFov>
+}
+
+impl FovCache {
+    pub fn new() -> FovCache {
+        FovCache { entries: HashMap::new() }
+    }
+
+    /// As `compute`, but reusing the previous result for this viewer,
+    /// radius and algorithm if it's still good.
+    pub fn compute<'a, TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+        &'a mut self, world: &World<TWorldPayload, TZonePayload, TPayload>,
+        focus: RelativeCoord, radius: uint, algorithm: FovAlgorithm,
+        start_ang: &mut [f64], end_ang: &mut [f64]) -> &'a [RelativeCoord] {
+        let key = (focus.clone(), radius, algorithm);
+        let fresh = match self.entries.find(&key) {
+            Some(cached) => cached.revisions.iter().all(|&(zid, revision)| {
+                match world.find_zone(&zid) {
+                    Some(z) => z.revision() == revision,
+                    None => false
+                }
+            }),
+            None => false
+        };
+        if !fresh {
+            let tiles = match algorithm {
+                FovAlgorithm::Mrpas(metric) =>
+                    compute_with_metric(world, focus.clone(), radius, metric, start_ang, end_ang),
+                FovAlgorithm::Symmetric(metric) =>
+                    compute_symmetric(world, focus.clone(), radius, metric)
+            };
+            let revisions = touched_zones(world, &focus, tiles.as_slice());
+            self.entries.insert(key.clone(), CachedFov { tiles: tiles, revisions: revisions });
+        }
+        self.entries.find(&key).unwrap().tiles.as_slice()
+    }
+
+    /// Drop any results that are out of date, e.g. those for viewers that
+    /// have since moved on.
+    pub fn prune<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+        &mut self, world: &World<TWorldPayload, TZonePayload, TPayload>) {
+        let stale: Vec<(RelativeCoord, uint, FovAlgorithm)> = self.entries.iter()
+            .filter(|&(_, cached)| cached.revisions.iter().any(|&(zid, revision)| {
+                match world.zones.find(&zid) {
+                    Some(z) => z.revision() != revision,
+                    None => true
+                }
+            }))
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in stale.iter() {
+            self.entries.remove(key);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+// The zones a result was computed from: the viewer's, those it saw tiles
+// in, and those on the far side of any portals it saw.
+fn touched_zones<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, focus: &RelativeCoord,
+    tiles: &[RelativeCoord]) -> Vec<(Uuid, uint)> {
+    let mut zones = HashSet::new();
+    zones.insert(focus.zone_id);
+    for t in tiles.iter() {
+        zones.insert(t.zone_id);
+        match world.get_zone(&t.zone_id).get_tile((t.lx, t.ly)).portal_id {
+            Some(pid) => {
+                let (ozid, _) = world.get_portal(pid).info_from(t.zone_id);
+                zones.insert(ozid);
+            },
+            None => {}
+        }
+    }
+    zones.into_iter().map(|zid| (zid, world.get_zone(&zid).revision())).collect()
+}
+
+/// A field of view limited to a sector, e.g. a guard's vision cone.
+/// `facing` is the angle looked along and `width` the total angle covered,
+/// both in radians. Angles follow tile coordinates, so 0 faces east and
+/// PI/2 faces south.
+#[deriving(Clone, Copy, Show, PartialEq)]
+pub struct Cone {
+    pub facing: f64,
+    pub width: f64
+}
+
+impl Cone {
+    pub fn new(facing: f64, width: f64) -> Cone {
+        Cone { facing: facing, width: width }
+    }
+    // whether the tile at (dx, dy) from the viewer is inside the cone
+    fn contains(&self, dx: int, dy: int) -> bool {
+        if (dx == 0 && dy == 0) || self.width >= 2.0 * PI { return true; }
+        let angle = (dy as f64).atan2(dx as f64);
+        angle_between(angle, self.facing) <= self.width / 2.0
+    }
+    // whether any of an octant, which lies between its axis and diagonal,
+    // falls inside the cone
+    fn overlaps_octant(&self, dn: (int, int), is_vert: bool) -> bool {
+        if self.width >= 2.0 * PI { return true; }
+        let (dx, dy) = dn;
+        let axis = if is_vert { (dy as f64).atan2(0.0) } else { (0.0f64).atan2(dx as f64) };
+        let diagonal = (dy as f64).atan2(dx as f64);
+        let half = PI / 8.0;
+        let center = if axis - diagonal > PI || diagonal - axis > PI {
+            // straddling the -PI/PI seam, i.e. around west
+            PI + (axis + diagonal) / 2.0
+        } else { (axis + diagonal) / 2.0 };
+        angle_between(center, self.facing) <= self.width / 2.0 + half
+    }
+}
+
+// the absolute difference between two angles, in 0..PI
+fn angle_between(a: f64, b: f64) -> f64 {
+    let mut d = (a - b) % (2.0 * PI);
+    if d < 0.0 { d += 2.0 * PI; }
+    if d > PI { 2.0 * PI - d } else { d }
+}
+
 pub fn compute<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
     world: &World<TWorldPayload, TZonePayload, TPayload>, focus: RelativeCoord, radius: uint,
             start_ang: &mut [f64], end_ang: &mut [f64])
                 -> Vec<RelativeCoord> {
-    let mut visible_tiles: HashSet<RelativeCoord> = HashSet::new();
-    let mut pending_zones = vec!((focus.zone_id, (focus.lx, focus.ly),
-                              (focus.gx, focus.gy), radius, Uuid::nil(), NoDirection));
-    let octants = [
-        ((1, 1), true),   // 0 - SE-vert
-        ((1, 1), false),  // 1 - SE-horiz
-        ((1, -1), true),  // 2 - NE-vert
-        ((1, -1), false), // 3 - NE-horiz
-        ((-1, 1), true),  // 4 - SW-vert
-        ((-1, 1), false), // 5 - SW-horiz
-        ((-1, -1), true), // 6 - NW-vert
-        ((-1, -1), false) // 7 - NW-horiz
-    ];
-    while pending_zones.len() > 0 {
-        let before_len = visible_tiles.len();
-        let (curr_zid, curr_focus, curr_offset, max_radius,
-             from_pid, from_dir) =
-            pending_zones.pop().expect("fov::compute .. popping zone, shouldn't happen");
-        // When processing a connected zone, we only do the half of the screen
-        // that we'll see based on the direction into which we arrived at the portal
-        let mut octants_slice = match from_dir {
-            // originating zone.. process all quads
-            NoDirection => octants.iter().filter(|_| { true }),
-            // North - NW and NE quads
-            North => octants.iter().filter(|o| {
-                let &((_,y),_) = *o;
-                y == -1
-            }),
-            // South - SW and SE quads
-            South => octants.iter().filter(|o| {
-                    let &((_,y),_) = *o;
-                    y == 1
-            }),
-            // West - NW and SW quads
-            West => octants.iter().filter(|o| {
-                    let &((x,_),_) = *o;
-                    x == -1
-            }),
-            // East - NE and SE quads
-            East => octants.iter().filter(|o| {
-                    let &((x,_),_) = *o;
-                    x == 1
-            }),
+    compute_with_metric(world, focus, radius, Chebyshev, start_ang, end_ang)
+}
+
+/// As `compute`, but with the radius measured by `metric`, e.g. to see a
+/// circle instead of a square.
+pub fn compute_with_metric<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, focus: RelativeCoord, radius: uint,
+            metric: RadiusMetric, start_ang: &mut [f64], end_ang: &mut [f64])
+                -> Vec<RelativeCoord> {
+    let scan = Scan { metric: metric, cone: None, threshold: 1.0 };
+    compute_scan(world, focus, radius, scan, start_ang, end_ang)
+        .into_iter().map(|(t, _)| t).collect()
+}
+
+/// As `compute_with_metric`, but only the tiles inside `cone` are returned.
+/// Octants lying wholly outside the cone aren't scanned at all.
+pub fn compute_cone<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, focus: RelativeCoord, radius: uint,
+            metric: RadiusMetric, cone: Cone, start_ang: &mut [f64], end_ang: &mut [f64])
+                -> Vec<RelativeCoord> {
+    let scan = Scan { metric: metric, cone: Some(cone), threshold: 1.0 };
+    compute_scan(world, focus, radius, scan, start_ang, end_ang)
+        .into_iter().map(|(t, _)| t).collect()
+}
+
+/// As `compute_with_metric`, but also says how well each tile is seen: 1.0
+/// when nothing stands between it and the viewer, less when the view passes
+/// through `Translucent` tiles. Once the opacity built up along the way
+/// reaches `threshold`, whatever lies beyond is hidden as if by a wall; a
+/// `threshold` of 1.0 never hides anything that `compute` would show.
+pub fn compute_lit<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, focus: RelativeCoord, radius: uint,
+            metric: RadiusMetric, threshold: f64, start_ang: &mut [f64], end_ang: &mut [f64])
+                -> Vec<(RelativeCoord, f64)> {
+    let scan = Scan { metric: metric, cone: None, threshold: threshold };
+    compute_scan(world, focus, radius, scan, start_ang, end_ang)
+}
+
+// The settings a scan runs with, beyond its viewer and radius.
+#[deriving(Clone, Copy)]
+struct Scan {
+    metric: RadiusMetric,
+    cone: Option<Cone>,
+    // the opacity built up along a line of sight at which it's cut off
+    threshold: f64
+}
+
+fn compute_scan<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, focus: RelativeCoord, radius: uint,
+            scan: Scan, start_ang: &mut [f64], end_ang: &mut [f64])
+                -> Vec<(RelativeCoord, f64)> {
+    let mut state = ScanState::new();
+    let stub: Tile<TPayload> = Tile::stub();
+    scan_into(world, &focus, radius, &scan, stub.payload.get_fov(), &mut state,
+              start_ang, end_ang);
+    debug!("num of visible_tiles: {}", state.seen.len());
+    range(0, state.seen.len()).map(|i| state.seen.tile(i)).collect()
+}
+
+/// Buffers for `compute_into` to work in. Keep one around and pass it to
+/// every call; once its buffers have grown to fit, scanning doesn't
+/// allocate.
+pub struct FovScratch<TPayload> {
+    state: ScanState,
+    // stands in for everything past a zone's edges
+    stub: Tile<TPayload>,
+    start_ang: Vec<f64>,
+    end_ang: Vec<f64>
+}
+
+impl<TPayload: Send + Payloadable> FovScratch<TPayload> {
+    pub fn new() -> FovScratch<TPayload> {
+        FovScratch {
+            state: ScanState::new(),
+            stub: Tile::stub(),
+            start_ang: Vec::new(),
+            end_ang: Vec::new()
+        }
+    }
+}
+
+/// As `compute_with_metric`, but working in `scratch` and replacing the
+/// contents of `out` with the result, so that repeated calls reuse the same
+/// memory instead of allocating.
+pub fn compute_into<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, focus: &RelativeCoord, radius: uint,
+            metric: RadiusMetric, scratch: &mut FovScratch<TPayload>,
+            out: &mut Vec<RelativeCoord>) {
+    let scan = Scan { metric: metric, cone: None, threshold: 1.0 };
+    // an octant can't hold more obstacles than it has cells
+    let size = (radius + 1) * (radius + 2);
+    while scratch.start_ang.len() < size {
+        scratch.start_ang.push(0.0);
+        scratch.end_ang.push(0.0);
+    }
+    scan_into(world, focus, radius, &scan, scratch.stub.payload.get_fov(), &mut scratch.state,
+              scratch.start_ang.as_mut_slice(), scratch.end_ang.as_mut_slice());
+    out.clear();
+    let seen = &scratch.state.seen;
+    for i in range(0, seen.len()) {
+        let (t, _) = seen.tile(i);
+        out.push(t);
+    }
+}
+
+/// As `compute_with_metric`, but with the passes into zones seen through
+/// portals worked on by `pool`, one task per pass. The result is the same as
+/// `compute_with_metric`'s, whatever order the work finishes in.
+#[cfg(feature = "parallel")]
+pub fn compute_parallel<TWorldPayload: Send + Sync, TZonePayload: Send + Sync,
+                        TPayload: Send + Sync + Payloadable + FovItem>(
+    world: Arc<World<TWorldPayload, TZonePayload, TPayload>>, focus: RelativeCoord,
+            radius: uint, metric: RadiusMetric, pool: &TaskPool) -> Vec<RelativeCoord> {
+    let scan = Scan { metric: metric, cone: None, threshold: 1.0 };
+    let stub: Tile<TPayload> = Tile::stub();
+    let outside = stub.payload.get_fov();
+    let mut seen = SeenTiles::new();
+    let mut wave = Vec::new();
+    let mut next_wave = Vec::new();
+    start_scan(&*world, &focus, radius, &mut seen, &mut wave, &mut next_wave);
+    while wave.len() > 0 {
+        let (tx, rx) = channel();
+        for (idx, pass) in wave.iter().enumerate() {
+            let (tx, world, pass) = (tx.clone(), world.clone(), pass.clone());
+            pool.execute(proc() {
+                // every pass gets its own buffers; an octant can't hold more
+                // obstacles than it has cells
+                let (_, _, _, radius, _, _, _) = pass;
+                let size = (radius + 1) * (radius + 2);
+                let mut start_ang = Vec::from_elem(size, 0.0f64);
+                let mut end_ang = Vec::from_elem(size, 0.0f64);
+                let mut state = PassState::new();
+                let mut seen = SeenTiles::new();
+                let mut found = Vec::new();
+                run_pass(&*world, &pass, &scan, outside, &mut state, &mut None,
+                         start_ang.as_mut_slice(), end_ang.as_mut_slice(),
+                         &mut seen, &mut found, 0);
+                let tiles: Vec<(RelativeCoord, f64)> =
+                    range(0, seen.len()).map(|i| seen.tile(i)).collect();
+                tx.send((idx, (tiles, found)));
+            });
+        }
+        // put the results back in wave order, so merging them doesn't
+        // depend on which finished first
+        let mut results: Vec<(uint, ScanResult)> =
+            range(0, wave.len()).map(|_| rx.recv()).collect();
+        results.sort_by(|&(a, _), &(b, _)| a.cmp(&b));
+        for (idx, (tiles, found)) in results.into_iter() {
+            // the first pass also owns anything start_scan queued
+            let first = if idx == 0 { 0 } else { next_wave.len() };
+            for (t, l) in tiles.into_iter() {
+                let size = world.get_zone(&t.zone_id).size;
+                seen.insert(size, t, l);
+            }
+            for z in found.into_iter() { merge_pending_zone(&mut next_wave, first, z); }
+        }
+        mem::swap(&mut wave, &mut next_wave);
+        next_wave.clear();
+    }
+    range(0, seen.len()).map(|i| { let (t, _) = seen.tile(i); t }).collect()
+}
+
+// What a pass hands back when worked on by itself: the tiles it saw, and the
+// passes it found.
+#[cfg(feature = "parallel")]
+type ScanResult = (Vec<(RelativeCoord, f64)>, Vec<ComputeOctantPendingZones>);
+
+static OCTANTS: [((int, int), bool), ..8] = [
+    ((1, 1), true),   // 0 - SE-vert
+    ((1, 1), false),  // 1 - SE-horiz
+    ((1, -1), true),  // 2 - NE-vert
+    ((1, -1), false), // 3 - NE-horiz
+    ((-1, 1), true),  // 4 - SW-vert
+    ((-1, 1), false), // 5 - SW-horiz
+    ((-1, -1), true), // 6 - NW-vert
+    ((-1, -1), false) // 7 - NW-horiz
+];
+
+// how far past a zone's edges an octant scans, so the space beyond a portal
+// can be lined up with the zone behind it
+static PADDING: int = 34;
+
+// Everything a scan works in, besides the angle buffers.
+struct ScanState {
+    pass: PassState,
+    seen: SeenTiles,
+    wave: Vec<ComputeOctantPendingZones>,
+    next_wave: Vec<ComputeOctantPendingZones>,
+    cells: CellTables
+}
+
+impl ScanState {
+    fn new() -> ScanState {
+        ScanState {
+            pass: PassState::new(),
+            seen: SeenTiles::new(),
+            wave: Vec::new(),
+            next_wave: Vec::new(),
+            cells: None
+        }
+    }
+}
+
+// What an octant needs of each of a zone's cells: the portal on it, and its
+// FOV with the layers over it taken into account. Looking these up is the
+// part of scanning a zone that doesn't depend on where the viewer stands.
+struct ZoneCells {
+    portal: Vec<Option<Uuid>>,
+    fov: Vec<FovType>
+}
+
+impl ZoneCells {
+    fn new<TZonePayload, TPayload: Send + Payloadable + FovItem>(
+        zone: &Zone<TZonePayload, TPayload>) -> ZoneCells {
+        let cells = zone.size * zone.size;
+        let mut portal = Vec::with_capacity(cells);
+        let mut fov = Vec::with_capacity(cells);
+        for idx in range(0, cells) {
+            let c = (idx % zone.size, idx / zone.size);
+            portal.push(zone.get_tile(c).portal_id);
+            fov.push(zone.fov_at(c));
+        }
+        ZoneCells { portal: portal, fov: fov }
+    }
+}
+
+// the `ZoneCells` of every zone looked into so far, for scans run one after
+// another against a world that can't change in between (as in
+// `compute_many`); `None` to read the zones directly
+type CellTables = Option<HashMap<Uuid, ZoneCells>>;
+
+// Scan wave after wave of zone passes, starting with the viewer's own zone,
+// until no more portals are seen. `outside` is the FOV of what lies past a
+// zone's edges. Leaves the result in `state.seen`.
+fn scan_into<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, focus: &RelativeCoord, radius: uint,
+    scan: &Scan, outside: FovType, state: &mut ScanState,
+    start_ang: &mut [f64], end_ang: &mut [f64]) {
+    state.seen.clear();
+    state.wave.clear();
+    state.next_wave.clear();
+    start_scan(world, focus, radius, &mut state.seen, &mut state.wave, &mut state.next_wave);
+    while state.wave.len() > 0 {
+        for i in range(0, state.wave.len()) {
+            // the first pass also owns anything start_scan queued
+            let first = if i == 0 { 0 } else { state.next_wave.len() };
+            let pass = state.wave[i].clone();
+            run_pass(world, &pass, scan, outside, &mut state.pass, &mut state.cells,
+                     start_ang, end_ang, &mut state.seen, &mut state.next_wave, first);
+        }
+        mem::swap(&mut state.wave, &mut state.next_wave);
+        state.next_wave.clear();
+    }
+}
+
+// The first wave of a scan, the viewer's own zone, goes in `wave`. The
+// viewer's tile is always seen, and if it's a portal that's looked through
+// as well, so the pass through it is queued in `next_wave`.
+fn start_scan<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, focus: &RelativeCoord, radius: uint,
+    seen: &mut SeenTiles, wave: &mut Vec<ComputeOctantPendingZones>,
+    next_wave: &mut Vec<ComputeOctantPendingZones>) {
+    let curr_focus = (focus.lx, focus.ly);
+    let curr_offset = (focus.gx, focus.gy);
+    wave.push((focus.zone_id, curr_focus, curr_offset, radius, Uuid::nil(), NoDirection, 1.0));
+    let zone = world.get_zone(&focus.zone_id);
+    seen.insert(zone.size, RelativeCoord::new(focus.zone_id, curr_focus, curr_offset), 1.0);
+    match zone.get_tile(curr_focus).portal_id {
+        Some(pid) if !world.get_portal(pid).is_vertical() => {
+            next_wave.push(build_pending_zone_entry(
+                world, zone.id, pid, curr_focus, curr_offset, radius, 1.0));
+        },
+        _ => {}
+    }
+}
+
+// Whether a pass needs to scan an octant at all.
+fn wants_octant(pass: &ComputeOctantPendingZones, octant: ((int, int), bool),
+                scan: &Scan) -> bool {
+    let (_, _, _, _, _, from_dir, _) = pass.clone();
+    let ((x, y), is_vert) = octant;
+    // When processing a connected zone, we only do the half of the
+    // screen that we'll see based on the direction into which we
+    // arrived at the portal
+    let wanted = match from_dir {
+        // originating zone.. process all quads
+        NoDirection => true,
+        // North - NW and NE quads
+        North => y == -1,
+        // South - SW and SE quads
+        South => y == 1,
+        // West - NW and SW quads
+        West => x == -1,
+        // East - NE and SE quads
+        East => x == 1,
+        // stairs are never looked through, so never queued
+        Up | Down => panic!("fov::compute .. pending zone entered vertically")
+    };
+    let in_cone = match scan.cone {
+        Some(c) => c.overlaps_octant((x, y), is_vert),
+        None => true
+    };
+    wanted && in_cone
+}
+
+// Scan the octants of a pass, adding what they see to `seen` and the passes
+// they find to `found`, merged with those from index `first` on. The octants
+// share what's in view and the light let through, so a cell on the edge
+// between two of them is only worked out once.
+fn run_pass<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, pass: &ComputeOctantPendingZones,
+    scan: &Scan, outside: FovType, state: &mut PassState, cells: &mut CellTables,
+    start_ang: &mut [f64], end_ang: &mut [f64],
+    seen: &mut SeenTiles, found: &mut Vec<ComputeOctantPendingZones>, first: uint) {
+    let (curr_zid, curr_focus, curr_offset, max_radius, from_pid, from_dir, entry_light) =
+        pass.clone();
+    let zone = world.get_zone(&curr_zid);
+    let cells = match *cells {
+        Some(ref mut tables) => {
+            if !tables.contains_key(&curr_zid) {
+                tables.insert(curr_zid, ZoneCells::new(zone));
+            }
+            tables.find(&curr_zid)
+        },
+        None => None
+    };
+    // past a portal, the viewer stands where it would be if the zone went on
+    // past its edges
+    let (fx, fy) = curr_focus;
+    let (ox, oy) = curr_offset;
+    let viewer = match from_dir {
+        NoDirection => (fx as int, fy as int),
+        _ => (fx as int - ox, fy as int - oy)
+    };
+    state.reset(zone.size, viewer, entry_light);
+    for o in OCTANTS.iter() {
+        if !wants_octant(pass, *o, scan) { continue; }
+        let (quadrant, is_vert) = *o;
+        compute_octant(
+            world, zone, cells, curr_focus, curr_offset, max_radius, from_pid,
+            state, entry_light, start_ang, end_ang, quadrant, is_vert, from_dir, scan,
+            outside, seen, found, first);
+    }
+}
+
+// What a pass keeps about the cells its octants scan: a bitset of those in
+// view and the light each lets through. Both are laid out over the zone and
+// the padding around it, and only grow when a bigger zone comes along.
+struct PassState {
+    in_fov: Bitv,
+    light: Vec<f64>,
+    // the cells written in `light`, to be put back to 0.0 on reset
+    lit: Vec<uint>,
+    // the width of a row of cells
+    stride: uint,
+    // where the viewer, who may be well outside the zone, stands
+    viewer: (int, int),
+    entry_light: f64
+}
+
+impl PassState {
+    fn new() -> PassState {
+        PassState {
+            in_fov: Bitv::new(),
+            light: Vec::new(),
+            lit: Vec::new(),
+            stride: 0,
+            viewer: (0, 0),
+            entry_light: 0.0
+        }
+    }
+    // get ready to scan a pass over a zone `size` tiles across
+    fn reset(&mut self, size: uint, viewer: (int, int), entry_light: f64) {
+        let stride = size + 2 * PADDING as uint;
+        if stride > self.stride {
+            self.stride = stride;
+            self.in_fov = Bitv::with_capacity(stride * stride, false);
+            self.light = Vec::from_elem(stride * stride, 0.0);
+        } else {
+            self.in_fov.clear();
+            for c in self.lit.iter() { self.light[*c] = 0.0; }
+        }
+        self.lit.clear();
+        self.viewer = viewer;
+        self.entry_light = entry_light;
+    }
+    fn index(&self, cell: (int, int)) -> Option<uint> {
+        let (x, y) = cell;
+        let (x, y) = (x + PADDING, y + PADDING);
+        let stride = self.stride as int;
+        if x < 0 || y < 0 || x >= stride || y >= stride {
+            None
+        } else {
+            Some((x + y * stride) as uint)
+        }
+    }
+    fn in_fov(&self, cell: (int, int)) -> bool {
+        match self.index(cell) {
+            Some(c) => self.in_fov.get(c),
+            None => false
+        }
+    }
+    fn mark_in_fov(&mut self, cell: (int, int)) {
+        match self.index(cell) {
+            Some(c) => self.in_fov.set(c, true),
+            None => {}
+        }
+    }
+    // the light let through by a cell; none if it hasn't been seen.
+    // Light arrives at the viewer, projected or not, undimmed.
+    fn light_past(&self, cell: (int, int)) -> f64 {
+        if cell == self.viewer { return self.entry_light; }
+        match self.index(cell) {
+            Some(c) => self.light[c],
+            None => 0.0
+        }
+    }
+    fn set_light(&mut self, cell: (int, int), light: f64) {
+        match self.index(cell) {
+            Some(c) => {
+                self.light[c] = light;
+                self.lit.push(c);
+            },
+            None => {}
+        }
+    }
+}
+
+// The tiles a scan has seen, with the best light each was seen in. A zone
+// can be looked into more than once, from different places, so tiles are
+// kept in a grid per zone and offset. Grids are reused from scan to scan.
+struct SeenTiles {
+    grids: Vec<SeenGrid>,
+    // how many of `grids` the current scan is using
+    used: uint,
+    // (grid, cell) of every tile seen, in the order they were first seen
+    order: Vec<(uint, uint)>
+}
+
+struct SeenGrid {
+    zone_id: Uuid,
+    // the (gx, gy) of the zone's (0, 0)
+    offset: (int, int),
+    size: uint,
+    // the light each tile was seen in; negative if it wasn't
+    light: Vec<f64>
+}
+
+impl SeenTiles {
+    fn new() -> SeenTiles {
+        SeenTiles { grids: Vec::new(), used: 0, order: Vec::new() }
+    }
+    fn clear(&mut self) {
+        for &(g, c) in self.order.iter() {
+            self.grids[g].light[c] = -1.0;
+        }
+        self.order.clear();
+        self.used = 0;
+    }
+    fn len(&self) -> uint { self.order.len() }
+    // keep the best view of a tile, which can be reached more than once,
+    // e.g. at the edge of two octants. `size` is that of its zone.
+    fn insert(&mut self, size: uint, t: RelativeCoord, light: f64) {
+        let offset = (t.gx - t.lx as int, t.gy - t.ly as int);
+        let mut g = 0;
+        while g < self.used &&
+            !(self.grids[g].zone_id == t.zone_id && self.grids[g].offset == offset) {
+            g += 1;
+        }
+        if g == self.used {
+            if g == self.grids.len() {
+                self.grids.push(SeenGrid {
+                    zone_id: t.zone_id, offset: offset, size: 0, light: Vec::new()
+                });
+            }
+            let grid = &mut self.grids[g];
+            grid.zone_id = t.zone_id;
+            grid.offset = offset;
+            if grid.size != size {
+                grid.size = size;
+                grid.light.clear();
+                for _ in range(0, size * size) { grid.light.push(-1.0); }
+            }
+            self.used += 1;
+        }
+        let c = t.lx + t.ly * size;
+        let prev = self.grids[g].light[c];
+        if prev < 0.0 { self.order.push((g, c)); }
+        if light > prev { self.grids[g].light[c] = light; }
+    }
+    // the (gx, gy) of every place `target` was seen
+    fn sightings(&self, target: &GlobalCoord) -> Vec<(int, int)> {
+        let (lx, ly) = target.coords;
+        let mut found = Vec::new();
+        for grid in self.grids.slice_to(self.used).iter() {
+            if grid.zone_id != target.zone_id || lx >= grid.size || ly >= grid.size {
+                continue;
+            }
+            if grid.light[lx + ly * grid.size] >= 0.0 {
+                let (ox, oy) = grid.offset;
+                found.push((ox + lx as int, oy + ly as int));
+            }
+        }
+        found
+    }
+    fn tile(&self, i: uint) -> (RelativeCoord, f64) {
+        let (g, c) = self.order[i];
+        let grid = &self.grids[g];
+        let (lx, ly) = (c % grid.size, c / grid.size);
+        let (ox, oy) = grid.offset;
+        (RelativeCoord::new(grid.zone_id, (lx, ly), (ox + lx as int, oy + ly as int)),
+         grid.light[c])
+    }
+}
+
+/// The combined FOV of several viewers, each with their own radius. Every
+/// visible tile maps to the indices (into `viewers`) of those who see it.
+///
+/// Viewers standing on the same tile share a single scan, at the largest of
+/// their radii, which is then cut down to each one's radius. Viewers on
+/// different tiles are scanned one by one, as the shadows MRPAS works out
+/// depend on exactly where the viewer stands, but everything else about a
+/// zone's octants is shared: each cell's portal and FOV, layers and all,
+/// is looked up the first time any viewer's scan reaches the zone and read
+/// from a table by every scan after, along with the buffers the scans are
+/// done in.
+pub fn compute_many<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, viewers: &[(RelativeCoord, uint)],
+            metric: RadiusMetric, start_ang: &mut [f64], end_ang: &mut [f64])
+                -> HashMap<GlobalCoord, Vec<uint>> {
+    let groups = group_by_tile(viewers);
+    let scan = Scan { metric: metric, cone: None, threshold: 1.0 };
+    let stub: Tile<TPayload> = Tile::stub();
+    let mut state = ScanState::new();
+    state.cells = Some(HashMap::new());
+    let mut seen: HashMap<GlobalCoord, Vec<uint>> = HashMap::new();
+    for (_, members) in groups.iter() {
+        let (ref focus, _) = viewers[members[0]];
+        let radius = members.iter().map(|i| { let (_, r) = viewers[*i]; r }).max().unwrap();
+        scan_into(world, focus, radius, &scan, stub.payload.get_fov(), &mut state,
+                  start_ang, end_ang);
+        for n in range(0, state.seen.len()) {
+            let (t, _) = state.seen.tile(n);
+            let (dx, dy) = (t.gx - focus.gx, t.gy - focus.gy);
+            let gc = GlobalCoord::new(t.zone_id, (t.lx, t.ly));
+            for i in members.iter() {
+                let (_, r) = viewers[*i];
+                if !metric.within(dx, dy, r) { continue; }
+                let found = match seen.find_mut(&gc) {
+                    Some(seen_by) => {
+                        if !seen_by.contains(i) { seen_by.push(*i); }
+                        true
+                    },
+                    None => false
+                };
+                if !found { seen.insert(gc.clone(), vec!(*i)); }
+            }
+        }
+    }
+    for (_, seen_by) in seen.iter_mut() {
+        seen_by.sort();
+    }
+    seen
+}
+
+/// Which of `observers`, each with their own radius, have `target` in what
+/// `compute_with_metric` would give them, as indices into `observers` in
+/// ascending order. Every observer is still scanned, but the scans are run
+/// as `compute_many`'s are, sharing tiles, zone lookups and buffers, and
+/// nothing is kept of them but whether `target` turned up.
+pub fn observers_of<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, target: &GlobalCoord,
+            observers: &[(RelativeCoord, uint)], metric: RadiusMetric,
+            start_ang: &mut [f64], end_ang: &mut [f64]) -> Vec<uint> {
+    let scan = Scan { metric: metric, cone: None, threshold: 1.0 };
+    let stub: Tile<TPayload> = Tile::stub();
+    let mut state = ScanState::new();
+    state.cells = Some(HashMap::new());
+    let mut seen_by = Vec::new();
+    for (_, members) in group_by_tile(observers).iter() {
+        let (ref focus, _) = observers[members[0]];
+        let radius = members.iter().map(|i| { let (_, r) = observers[*i]; r }).max().unwrap();
+        scan_into(world, focus, radius, &scan, stub.payload.get_fov(), &mut state,
+                  start_ang, end_ang);
+        // a zone can be seen more than once, from different places
+        let sightings = state.seen.sightings(target);
+        for i in members.iter() {
+            let (_, r) = observers[*i];
+            if sightings.iter().any(|&(gx, gy)| metric.within(gx - focus.gx, gy - focus.gy, r)) {
+                seen_by.push(*i);
+            }
+        }
+    }
+    seen_by.sort();
+    seen_by
+}
+
+// the indices of `viewers`, grouped by the tile they stand on
+fn group_by_tile(viewers: &[(RelativeCoord, uint)]) -> HashMap<GlobalCoord, Vec<uint>> {
+    let mut groups: HashMap<GlobalCoord, Vec<uint>> = HashMap::new();
+    for (idx, &(ref focus, _)) in viewers.iter().enumerate() {
+        let gc = GlobalCoord::new(focus.zone_id, (focus.lx, focus.ly));
+        let found = match groups.find_mut(&gc) {
+            Some(members) => { members.push(idx); true },
+            None => false
         };
-        let zone = world.get_zone(&curr_zid);
-        // always insert focus pos, then check for portal at starting pos
-        if from_pid == Uuid::nil() {
-            visible_tiles.insert(RelativeCoord::new(curr_zid, curr_focus, curr_offset));
-            let curr_tile = zone.get_tile(curr_focus);
-            match curr_tile.portal_id {
-                Some(pid) => {
-                        pending_zones.push(build_pending_zone_entry(
-                            world, zone.id, pid, curr_offset, max_radius));
-                },
-                None => {}
+        if !found { groups.insert(gc, vec!(idx)); }
+    }
+    groups
+}
+
+/// A field of view where seeing is mutual: a tile is in the result exactly
+/// when the viewer would be in the result of the same call made from that
+/// tile. This is a different measure from `compute`'s, not a symmetric
+/// version of it; MRPAS doesn't promise to agree in both directions, so
+/// the two can disagree about any given tile.
+///
+/// Two tiles see each other if a line stepped from either one to the other,
+/// one row or column at a time, crosses nothing but `allow_los` tiles.
+/// Portals are looked through, and a portal's tiles, which overlap those on
+/// its other side, are one spot: only the tile in the zone with the lower
+/// id is listed. Where portals offer more than one way between two tiles,
+/// the one with the fewest steps is used, and tiles more than `2 * radius`
+/// steps away aren't looked for.
+pub fn compute_symmetric<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, focus: RelativeCoord, radius: uint,
+            metric: RadiusMetric) -> Vec<RelativeCoord> {
+    let origin = spot_of(world, &GlobalCoord::new(focus.zone_id, (focus.lx, focus.ly)));
+    let mut visible_tiles = Vec::new();
+    for (gc, &(dx, dy)) in offsets_within(world, &origin, radius, metric).iter() {
+        if *gc != origin && (is_void(world, gc) || !line_of_sight(world, &origin, gc, (dx, dy))) {
+            continue;
+        }
+        visible_tiles.push(RelativeCoord::new(gc.zone_id, gc.coords,
+                                              (focus.gx + dx, focus.gy + dy)));
+    }
+    visible_tiles
+}
+
+/// Which of `observers`, each with their own radius, can see `target` by
+/// the measure `compute_symmetric` uses, as indices into `observers` in
+/// ascending order. Agrees with `compute_symmetric` run from each observer,
+/// without having to run it; for what `compute` would show them, see
+/// `observers_of`.
+pub fn symmetric_observers_of<TWorldPayload, TZonePayload,
+                              TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, target: &GlobalCoord,
+            observers: &[(RelativeCoord, uint)], metric: RadiusMetric) -> Vec<uint> {
+    let mut seen_by = Vec::new();
+    let target = spot_of(world, target);
+    if observers.len() == 0 || is_void(world, &target) { return seen_by; }
+    let radius = observers.iter().map(|&(_, r)| r).max().unwrap();
+    let offsets = offsets_within(world, &target, radius, metric);
+    for (idx, &(ref focus, r)) in observers.iter().enumerate() {
+        let gc = spot_of(world, &GlobalCoord::new(focus.zone_id, (focus.lx, focus.ly)));
+        match offsets.find(&gc) {
+            Some(&(dx, dy)) if metric.within(dx, dy, r) => {
+                if gc == target || line_of_sight(world, &target, &gc, (dx, dy)) {
+                    seen_by.push(idx);
+                }
+            },
+            _ => {}
+        }
+    }
+    seen_by
+}
+
+// Every spot within `radius` of `origin`, walls and all, with its offset
+// from `origin`. Found breadth-first out to `2 * radius` steps, enough for
+// the corners of a Chebyshev square. Every step can be taken back, so
+// counting steps rather than distance finds `b` from `a` exactly when it
+// finds `a` from `b`.
+fn offsets_within<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, origin: &GlobalCoord,
+    radius: uint, metric: RadiusMetric) -> HashMap<GlobalCoord, (int, int)> {
+    let mut reached = HashMap::new();
+    let mut queue = RingBuf::new();
+    reached.insert(origin.clone(), (0i, 0i));
+    queue.push_back((origin.clone(), (0i, 0i), 0u));
+    loop {
+        let (gc, (ox, oy), steps) = match queue.pop_front() {
+            Some(next) => next,
+            None => break
+        };
+        if steps == 2 * radius { continue; }
+        for dir in [North, East, South, West].iter() {
+            let (dx, dy) = dir.delta();
+            let offset = (ox + dx, oy + dy);
+            for n in fov_steps(world, &gc, *dir).into_iter() {
+                if reached.contains_key(&n) { continue; }
+                reached.insert(n.clone(), offset);
+                queue.push_back((n, offset, steps + 1));
             }
         }
-        let mut in_fov: HashSet<int> = HashSet::new();
-        for o in octants_slice {
-            let (quadrant, is_vert) = *o;
-            let (tiles, zones) = compute_octant(
-                world, zone, curr_focus, curr_offset, max_radius, from_pid,
-                &mut in_fov, start_ang, end_ang, quadrant, is_vert, from_dir);
-            for t in tiles.into_iter() { visible_tiles.insert(t); }
-            for z in zones.into_iter() { pending_zones.push(z); }
+    }
+    reached.into_iter().filter(|&(_, (dx, dy))| metric.within(dx, dy, radius)).collect()
+}
+
+// Whether `a` and `b`, `delta` apart, can see each other. Lines stepped in
+// opposite directions can pass different cells, so either being clear will
+// do; that's what makes the answer the same both ways round.
+fn line_of_sight<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, a: &GlobalCoord, b: &GlobalCoord,
+    delta: (int, int)) -> bool {
+    let (dx, dy) = delta;
+    clear_line(world, a, b, delta) || clear_line(world, b, a, (-dx, -dy))
+}
+
+// Step from `from` towards `to`, one row or column at a time, keeping as
+// close as possible to the straight line between them. Clear if every cell
+// in between allows LOS and the line really does end up at `to`.
+fn clear_line<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, from: &GlobalCoord,
+    to: &GlobalCoord, delta: (int, int)) -> bool {
+    let (dx, dy) = delta;
+    let x_dir = if dx > 0 { East } else { West };
+    let y_dir = if dy > 0 { South } else { North };
+    clear_line_from(world, from, to, (dx.abs(), dy.abs()), (x_dir, y_dir), (0, 0))
+}
+
+// The rest of a line from `curr`, `done` of the way along. A step can lead
+// to more than one spot beside a portal, so each is followed in turn.
+fn clear_line_from<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, curr: &GlobalCoord,
+    to: &GlobalCoord, length: (int, int), dirs: (TraversalDirection, TraversalDirection),
+    done: (int, int)) -> bool {
+    let (ax, ay) = length;
+    let (x_dir, y_dir) = dirs;
+    let (ix, iy) = done;
+    if ix == ax && iy == ay { return *curr == *to; }
+    // step along x while the line's x progress lags its y progress,
+    // comparing the midpoints of the cells stepped through
+    let step_x = iy == ay || (ix < ax && (2 * ix + 1) * ay < (2 * iy + 1) * ax);
+    let (dir, next) = if step_x { (x_dir, (ix + 1, iy)) } else { (y_dir, (ix, iy + 1)) };
+    let last = next == length;
+    for n in fov_steps(world, curr, dir).iter() {
+        if !last && !get_fov_at(world, n).allow_los() { continue; }
+        if clear_line_from(world, n, to, length, dirs, next) { return true; }
+    }
+    false
+}
+
+// The tiles standing for the spot at `gc`, each with the direction a
+// portal leads in from it: just `gc` itself, unless it's under a portal,
+// whose tiles overlap those on its other side.
+fn sides_of<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, gc: &GlobalCoord)
+        -> Vec<(GlobalCoord, TraversalDirection)> {
+    match portal_exit(world, gc) {
+        Some((pid, exit)) => {
+            let (ozid, oc, _) = world.portal_destination(gc.zone_id, pid, gc.coords);
+            let (_, far_exit) = world.get_portal(pid).info_from(ozid);
+            vec!((gc.clone(), exit), (GlobalCoord::new(ozid, oc), far_exit))
+        },
+        None => vec!((gc.clone(), NoDirection))
+    }
+}
+
+// The tile a spot goes by: of those standing for it, the one in the zone
+// with the lower id.
+fn spot_of<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, gc: &GlobalCoord) -> GlobalCoord {
+    let mut spot = gc.clone();
+    for (side, _) in sides_of(world, gc).into_iter() {
+        if side.zone_id.as_bytes() < spot.zone_id.as_bytes() { spot = side; }
+    }
+    spot
+}
+
+// The portal `gc` is under, if it's one that can be looked through, and
+// the direction it leads in from there.
+fn portal_exit<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, gc: &GlobalCoord)
+        -> Option<(Uuid, TraversalDirection)> {
+    match world.get_zone(&gc.zone_id).get_tile(gc.coords).portal_id {
+        Some(pid) if !world.get_portal(pid).is_vertical() => {
+            let (_, exit) = world.get_portal(pid).info_from(gc.zone_id);
+            Some((pid, exit))
+        },
+        _ => None
+    }
+}
+
+// The spots one step from the spot at `gc` in `dir`. Stepping the way a
+// portal leads goes through to its other side, and a portal can't be
+// stepped onto from behind, which keeps every step one that can be taken
+// back. Where the two sides of a portal have different tiles beside them,
+// both are stepped onto.
+fn fov_steps<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, gc: &GlobalCoord,
+    dir: TraversalDirection) -> Vec<GlobalCoord> {
+    let (dx, dy) = dir.delta();
+    let mut steps = Vec::new();
+    for (side, exit) in sides_of(world, gc).into_iter() {
+        // that way lies the other side, which steps on from there itself
+        if exit == dir { continue; }
+        let zone = world.get_zone(&side.zone_id);
+        let (x, y) = side.coords;
+        let (nx, ny) = (x as int + dx, y as int + dy);
+        if nx < 0 || ny < 0 || !zone.coords_in_bounds((nx as uint, ny as uint)) {
+            continue;
+        }
+        let n = GlobalCoord::new(zone.id, (nx as uint, ny as uint));
+        match portal_exit(world, &n) {
+            Some((_, back)) if back == dir.opposite() => continue,
+            _ => {}
         }
-        let found = visible_tiles.len() - before_len;
+        let spot = spot_of(world, &n);
+        if !steps.contains(&spot) { steps.push(spot); }
     }
+    steps
+}
 
-    debug!("num of visible_tiles: {}", visible_tiles.len());
-    visible_tiles.into_iter().collect()
+fn get_fov_at<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, gc: &GlobalCoord) -> FovType {
+    world.get_zone(&gc.zone_id).fov_at(gc.coords)
+}
+
+// void tiles are never seen, as in `compute`
+fn is_void<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
+    world: &World<TWorldPayload, TZonePayload, TPayload>, gc: &GlobalCoord) -> bool {
+    match get_fov_at(world, gc) {
+        Void => true,
+        _ => false
+    }
 }
 
 fn min(a: int, b: int) -> int {
@@ -122,27 +1068,42 @@ fn max(a: int, b: int) -> int {
     if a > b { a } else { b }
 }
 
-type ComputeOctantPendingZones = (Uuid, (uint, uint), (int, int), uint, Uuid, TraversalDirection);
+// (zone, focus, offset, radius, portal entered through, direction entered
+// in, light arriving at the portal)
+type ComputeOctantPendingZones =
+    (Uuid, (uint, uint), (int, int), uint, Uuid, TraversalDirection, f64);
+
+// Every tile of a portal maps onto the other zone by the same translation, so
+// any entry for it gives the same view; keep the brightest. Only entries
+// from `first` on, those found by the same pass, are merged with.
+fn merge_pending_zone(pending: &mut Vec<ComputeOctantPendingZones>, first: uint,
+                      entry: ComputeOctantPendingZones) {
+    let (_, _, _, _, pid, _, light) = entry.clone();
+    for i in range(first, pending.len()) {
+        let (_, _, _, _, other, _, existing) = pending[i].clone();
+        if other == pid {
+            if light > existing { pending[i] = entry; }
+            return;
+        }
+    }
+    pending.push(entry);
+}
 
 fn compute_octant<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable + FovItem>(
                 world: &World<TWorldPayload, TZonePayload, TTilePayload>,
-                zone: &Zone<TZonePayload, TTilePayload>,
+                zone: &Zone<TZonePayload, TTilePayload>, cells: Option<&ZoneCells>,
                 position: (uint, uint),
                 offset: (int, int), max_radius: uint, from_pid: Uuid,
-                in_fov: &mut HashSet<int>,
+                state: &mut PassState, entry_light: f64,
                 start_angle: &mut [f64], end_angle: &mut [f64],
-                dn: (int, int), is_vert: bool, from_dir: TraversalDirection)
-        -> (Vec<RelativeCoord>,
-            Vec<ComputeOctantPendingZones>) {
-    let mut visible_tiles = HashSet::new();
-    let mut pending_zones = HashSet::new();
-    let stub_tile = Tile::stub();
-    let padding = 34 as int;
+                dn: (int, int), is_vert: bool, from_dir: TraversalDirection,
+                scan: &Scan, outside: FovType, seen: &mut SeenTiles,
+                found: &mut Vec<ComputeOctantPendingZones>, first: uint) {
+    let padding = PADDING;
     let (raw_px, raw_py) = position;
     let (raw_px, raw_py) = (raw_px as int, raw_py as int);
     let (in_ox, in_oy) = offset;
     let wsize = zone.size;
-    let wsize_sq = wsize * wsize;
     let (position_x, position_y) = match from_dir {
         NoDirection => (raw_px, raw_py),
         _ => {
@@ -150,6 +1111,18 @@ fn compute_octant<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable
             (px, py)
         }
     };
+    // the tiles along the edge covered by the portal we arrived through;
+    // the rest of that edge is treated as a wall
+    let (span_lo, span_hi) = if from_pid == Uuid::nil() {
+        (0, 0)
+    } else {
+        let width = world.get_portal(from_pid).width() as int;
+        let (sx, sy) = *zone.get_portal_coords(&from_pid);
+        match from_dir {
+            North | South => (sx as int, sx as int + width - 1),
+            _ => (sy as int, sy as int + width - 1)
+        }
+    };
     let (dx, dy) = dn;
     {
         let mut iteration = 1 as int;
@@ -208,22 +1181,42 @@ fn compute_octant<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable
                 y
             };
             while inner >= mini && inner <= maxi {
-                let c = x + (y * wsize as int);
                 let in_bounds = x >= 0 && y >= 0 && zone.coords_in_bounds((x as uint, y as uint));
-                let c_tile = if in_bounds {
-                    zone.tile_at_idx(c as uint)
+                let (c_portal, c_fov) = if in_bounds {
+                    let c = (x as uint, y as uint);
+                    match cells {
+                        Some(cells) => {
+                            let idx = coords_to_idx(c, wsize);
+                            (cells.portal[idx], cells.fov[idx])
+                        },
+                        None => (zone.get_tile(c).portal_id, zone.fov_at(c))
+                    }
                 } else {
-                    &stub_tile
+                    (None, outside)
                 };
-                let is_void = match c_tile.payload.get_fov() {
+                let is_void = match c_fov {
                     Void => true,
                     _ => false };
-                let mut allow_los = c_tile.payload.get_fov().allow_los();
+                let mut allow_los = c_fov.allow_los();
+                // the light reaching this cell: the best of what gets past
+                // the two cells between it and the viewer in the last line
+                let behind = if is_vert { (x, y - dy) } else { (x - dx, y) };
+                let incoming = state.light_past(behind).max(state.light_past((x - dx, y - dy)));
+                let passed = incoming * (1.0 - c_fov.opacity());
+                // only translucent cells build up opacity, so they're the
+                // only ones that can push it over the threshold; at 1.0 they
+                // never do, leaving the plain scans as they were
+                match c_fov {
+                    Translucent(_) if scan.threshold < 1.0 && 1.0 - passed >= scan.threshold =>
+                        allow_los = false,
+                    _ => {}
+                }
+                let mut light_out = None;
                 let mut visible = true;
                 let start_slope = processed_cell as f64 * slopes_per_cell;
                 let center_slope = start_slope + half_slopes;
                 let end_slope = start_slope + slopes_per_cell;
-                if obstacles_in_last_line > 0 && !in_fov.contains(&c) {
+                if obstacles_in_last_line > 0 && !state.in_fov((x, y)) {
                     let mut idx = 0;
                     while in_bounds && visible && idx < obstacles_in_last_line {
                         if allow_los {
@@ -236,29 +1229,19 @@ fn compute_octant<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable
                             visible = false;
                         }
                         // branch:2 zy vals +  n - dn bounds checks
-                        let zy = if is_vert {
-                            x + ((y-dy) * wsize as int)
-                        } else {
-                            x-dx + (y* wsize as int)
-                        };
+                        let zy = if is_vert { (x, y - dy) } else { (x - dx, y) };
                         // branch:2
                         let n_minus_dn_bounds_check = if is_vert {
                             (x - dx >= 0) && (x - dx < wsize as int)
                         } else {
                             (y - dy >= 0) && (y - dy < wsize as int)
                         };
-                        let zy_tile_trans = if zy >= 0 && zy < wsize_sq as int {
-                            let t = zone.tile_at_idx(zy as uint);
-                            t.payload.get_fov().allow_los()
-                        } else { true };
-                        let zyx = (x-dx) + ((y-dy) * wsize as int);
-                        let zyx_tile_trans = if zyx >= 0 && zyx < wsize_sq as int {
-                            let t = zone.tile_at_idx(zyx as uint);
-                            t.payload.get_fov().allow_los()
-                        } else { true };
+                        let zy_tile_trans = allows_los_at(zone, cells, zy);
+                        let zyx = (x - dx, y - dy);
+                        let zyx_tile_trans = allows_los_at(zone, cells, zyx);
                         if visible &&
-                            (!in_fov.contains(&zy) || !zy_tile_trans) &&
-                            (n_minus_dn_bounds_check && ((!in_fov.contains(&zyx)) ||
+                            (!state.in_fov(zy) || !zy_tile_trans) &&
+                            (n_minus_dn_bounds_check && ((!state.in_fov(zyx)) ||
                                 (!zyx_tile_trans))) {
                             visible = false;
                         }
@@ -268,88 +1251,106 @@ fn compute_octant<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable
                 if is_void {
                     visible = false;
                     done = false;
+                    light_out = Some(incoming);
                 }
                 let mut non_blocking_axis = true;
                 match from_dir {
                     North => {
-                        if y == raw_py && x != raw_px {
+                        if y == raw_py && (x < span_lo || x > span_hi) {
                             non_blocking_axis = false;
                             visible = true;
                             done = true;
                             allow_los = false;
                         } else if y > raw_py {
+                            // behind the portal, where the light came from
                             visible = false;
                             done = false;
+                            light_out = Some(entry_light);
                         }
                     },
                     South => {
-                        if y == raw_py && x != raw_px {
+                        if y == raw_py && (x < span_lo || x > span_hi) {
                             non_blocking_axis = false;
                             visible = true;
                             done = true;
                             allow_los = false;
                         } else if y < raw_py {
+                            // behind the portal, where the light came from
                             visible = false;
                             done = false;
+                            light_out = Some(entry_light);
                         }
                     },
                     East => {
-                        if x == raw_px && y != raw_py {
+                        if x == raw_px && (y < span_lo || y > span_hi) {
                             non_blocking_axis = false;
                             visible = true;
                             done = true;
                             allow_los = false;
                         } else if x < raw_px {
+                            // behind the portal, where the light came from
                             visible = false;
                             done = false;
+                            light_out = Some(entry_light);
                         }
                     },
                     West => {
-                        if x == raw_px && y != raw_py {
+                        if x == raw_px && (y < span_lo || y > span_hi) {
                             non_blocking_axis = false;
                             visible = true;
                             done = true;
                             allow_los = false;
                         } else if x > raw_px {
+                            // behind the portal, where the light came from
                             visible = false;
                             done = false;
+                            light_out = Some(entry_light);
                         }
                     },
                     _ => {}
                 }
+                // lines are only scanned out to the radius, which is all a
+                // Chebyshev square needs; the other shapes are trimmed here
+                if !scan.metric.within(x - position_x, y - position_y, max_radius) {
+                    visible = false;
+                }
                 if visible {
                     let (gx, gy) = offset;
                     let (ox, oy) = (x - raw_px, y - raw_py);
                     let this_gx = (ox+gx, oy+gy);
-                    let found_already = in_fov.contains(&c);
+                    let found_already = state.in_fov((x, y));
                     if non_blocking_axis {
-                        in_fov.insert(c);
+                        state.mark_in_fov((x, y));
                     }
-                    let add_this_tile = match c_tile.portal_id {
-                        Some(pid) => {
+                    let add_this_tile = match c_portal {
+                        Some(pid) if !world.get_portal(pid).is_vertical() => {
                             if pid != from_pid && !found_already {
-                                let iter = if iteration < 0 { 0 }
-                                else { iteration };
-                                let remaining_radius = max_radius as int - iter;
-                                let remaining_radius = if remaining_radius < 0 {
-                                    0 as uint
-                                } else { remaining_radius as uint };
-                                let remaining_radius = if from_pid == Uuid::nil() {
-                                    max_radius
-                                } else { remaining_radius };
+                                // the connected zone is scanned from the
+                                // viewer's position as projected into it, so
+                                // what's left of the radius past this portal
+                                // is enforced by measuring with the same
+                                // metric and the full radius there
                                 let pz = build_pending_zone_entry(
-                                    world, zone.id, pid, this_gx, remaining_radius
+                                    world, zone.id, pid, (x as uint, y as uint),
+                                    this_gx, max_radius, incoming
                                 );
-                                pending_zones.insert(pz);
+                                merge_pending_zone(found, first, pz);
                                 false
                             } else { true }
                         },
+                        _ => true
+                    };
+                    let in_cone = match scan.cone {
+                        Some(c) => c.contains(x - position_x, y - position_y),
                         None => true
                     };
-                    if non_blocking_axis && add_this_tile {
-                        visible_tiles.insert(RelativeCoord::new(
+                    if non_blocking_axis && add_this_tile && in_cone {
+                        seen.insert(wsize, RelativeCoord::new(
                             zone.id, (x as uint, y as uint),
-                            this_gx));
+                            this_gx), incoming);
+                    }
+                    if non_blocking_axis {
+                        light_out = Some(if allow_los { passed } else { 0.0 });
                     }
                     done = false;
                     if !allow_los {
@@ -362,6 +1363,10 @@ fn compute_octant<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable
                         }
                     }
                 }
+                match light_out {
+                    Some(l) => state.set_light((x, y), l),
+                    None => {}
+                }
                 processed_cell += 1;
                 // branch:3 update x||y and inner
                 if is_vert {
@@ -394,17 +1399,60 @@ fn compute_octant<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable
                 done = true; }
         }
     }
-    (visible_tiles.into_iter().collect(),
-     pending_zones.into_iter().collect())
+}
+
+// whether the tile at `cell` can be seen past; everything outside the zone
+// can, as it's void
+fn allows_los_at<TZonePayload, TTilePayload: Send + Payloadable + FovItem>(
+    zone: &Zone<TZonePayload, TTilePayload>, cells: Option<&ZoneCells>,
+    cell: (int, int)) -> bool {
+    let (x, y) = cell;
+    if x < 0 || y < 0 || !zone.coords_in_bounds((x as uint, y as uint)) {
+        return true;
+    }
+    match cells {
+        Some(cells) => cells.fov[coords_to_idx((x as uint, y as uint), zone.size)].allow_los(),
+        None => zone.fov_at((x as uint, y as uint)).allow_los()
+    }
 }
 
 fn build_pending_zone_entry<TWorldPayload, TZonePayload, TPayload: Send + Payloadable + FovItem>(
-    world: &World<TWorldPayload, TZonePayload, TPayload>, zid: Uuid, pid: Uuid, this_gx: (int, int),
-    remaining_radius: uint)
-        -> (Uuid, (uint, uint), (int, int), uint, Uuid, TraversalDirection) {
-    let portal = world.get_portal(pid);
-    let (ozid, from_dir) = portal.info_from(zid);
-    let other_zone = world.get_zone(&ozid);
-    let oc = other_zone.get_portal_coords(&pid);
-    (ozid, *oc, this_gx, remaining_radius, pid, from_dir)
+    world: &World<TWorldPayload, TZonePayload, TPayload>, zid: Uuid, pid: Uuid,
+    coords: (uint, uint), this_gx: (int, int), radius: uint, light: f64)
+        -> ComputeOctantPendingZones {
+    let (ozid, oc, from_dir) = world.portal_destination(zid, pid, coords);
+    (ozid, oc, this_gx, radius, pid, from_dir, light)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PassState, PADDING};
+
+    #[test]
+    fn padding_cells_dont_alias_zone_cells() {
+        // cells used to be indexed by x + y * size alone, which put (-1, 1)
+        // on top of (size - 1, 0), and so on down both sides of the zone
+        let size = 8u;
+        let viewer = (-3i, 3i);
+        let mut state = PassState::new();
+        state.reset(size, viewer, 1.0);
+        for y in range(0, size as int) {
+            for x in range(0, size as int) {
+                state.mark_in_fov((x, y));
+                state.set_light((x, y), 0.5);
+            }
+        }
+        for y in range(-PADDING, size as int + PADDING) {
+            for x in range(-PADDING, size as int + PADDING) {
+                let inside = x >= 0 && y >= 0 && x < size as int && y < size as int;
+                let light = if (x, y) == viewer { 1.0 } else if inside { 0.5 } else { 0.0 };
+                assert!(state.in_fov((x, y)) == inside, "{},{} in view: {}", x, y, !inside);
+                assert!(state.light_past((x, y)) == light, "{},{} lit wrongly", x, y);
+            }
+        }
+        // nothing carries over into the next pass
+        state.reset(size, (0, 0), 1.0);
+        assert!(!state.in_fov((size as int - 1, 0)));
+        assert!(state.light_past((size as int - 1, 0)) == 0.0);
+    }
 }
@@ -0,0 +1,273 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// undoable editing of a `World`, for editors. Edits are grouped into named
+// operations; each edit, once made, leaves behind the edit that reverses it,
+// so an operation can be undone by making those in reverse order (which in
+// turn leaves behind what's needed to redo it).
+
+use std::mem;
+use uuid::Uuid;
+
+use world::{World, GlobalCoord, Payloadable, TraversalDirection};
+use zone::{Zone, Tile};
+use layer::LayerCell;
+use portal::Portal;
+
+use self::Edit::*;
+
+/// A single change to a `World`.
+#[deriving(Clone)]
+pub enum Edit<TZonePayload, TTilePayload> {
+    /// Replace the tile at `0`. Whatever portal covers it still does;
+    /// portals are only changed by `AddPortal` and `RemovePortal`.
+    SetTile(GlobalCoord, Tile<TTilePayload>),
+    /// Replace the cell at `0` in the layer named `1`.
+    SetLayerCell(GlobalCoord, String, LayerCell<TTilePayload>),
+    AddZone(Zone<TZonePayload, TTilePayload>),
+    /// Take out a zone. Its portals have to go first, as
+    /// `History::remove_zone` sees to.
+    RemoveZone(Uuid),
+    /// Add a portal, with its origin in its `a` and `b` zones at `1` and `2`.
+    AddPortal(Portal, (uint, uint), (uint, uint)),
+    RemovePortal(Uuid)
+}
+
+impl<TZonePayload, TTilePayload: Send + Payloadable> Edit<TZonePayload, TTilePayload> {
+    /// Make the edit, returning the one that reverses it.
+    pub fn apply<TWorldPayload>(self, world: &mut World<TWorldPayload, TZonePayload, TTilePayload>)
+            -> Edit<TZonePayload, TTilePayload> {
+        match self {
+            SetTile(gc, tile) => {
+                let mut tile = tile;
+                let current = world.get_zone_mut(&gc.zone_id).get_tile_mut(gc.coords);
+                tile.portal_id = current.portal_id;
+                let old = mem::replace(current, tile);
+                SetTile(gc, old)
+            },
+            SetLayerCell(gc, layer, cell) => {
+                let old = mem::replace(world.get_zone_mut(&gc.zone_id)
+                                            .get_layer_cell_mut(layer.as_slice(), gc.coords),
+                                       cell);
+                SetLayerCell(gc, layer, old)
+            },
+            AddZone(zone) => {
+                let zid = zone.id;
+                world.insert_zone(zone);
+                RemoveZone(zid)
+            },
+            RemoveZone(zid) => {
+                let zone = world.remove_zone(&zid).expect(
+                    format!("Edit::apply: can't remove zone {}, it isn't there", zid).as_slice());
+                AddZone(zone)
+            },
+            AddPortal(portal, a_origin, b_origin) => {
+                let pid = portal.id();
+                let (az, bz, width) = (portal.a_zid(), portal.b_zid(), portal.width());
+                let (_, a_exit) = portal.info_from(az);
+                let (_, b_exit) = portal.info_from(bz);
+                // both sides are checked first, so a clash leaves nothing
+                // half-added
+                world.get_zone(&az).check_portal_span(&pid, a_origin, a_exit, width);
+                world.get_zone(&bz).check_portal_span(&pid, b_origin, b_exit, width);
+                world.insert_portal(portal);
+                world.get_zone_mut(&az).add_portal_span(pid, a_origin, a_exit, width);
+                world.get_zone_mut(&bz).add_portal_span(pid, b_origin, b_exit, width);
+                RemovePortal(pid)
+            },
+            RemovePortal(pid) => {
+                let (az, bz) = {
+                    let portal = world.get_portal(pid);
+                    (portal.a_zid(), portal.b_zid())
+                };
+                let a_origin = *world.get_zone(&az).get_portal_coords(&pid);
+                let b_origin = *world.get_zone(&bz).get_portal_coords(&pid);
+                let portal = world.remove_portal(&pid).unwrap();
+                AddPortal(portal, a_origin, b_origin)
+            }
+        }
+    }
+}
+
+/// A named group of edits, undone or redone together. Holds the edits that
+/// reverse it, in the order they were made.
+#[deriving(Clone)]
+pub struct Operation<TZonePayload, TTilePayload> {
+    pub name: String,
+    reverse: Vec<Edit<TZonePayload, TTilePayload>>
+}
+
+impl<TZonePayload, TTilePayload: Send + Payloadable> Operation<TZonePayload, TTilePayload> {
+    // make the reversing edits last-first, returning the operation that
+    // reverses this one's reversal
+    fn revert<TWorldPayload>(self, world: &mut World<TWorldPayload, TZonePayload, TTilePayload>)
+            -> Operation<TZonePayload, TTilePayload> {
+        let Operation { name, reverse } = self;
+        let mut redo = Vec::with_capacity(reverse.len());
+        for edit in reverse.into_iter().rev() {
+            redo.push(edit.apply(world));
+        }
+        Operation { name: name, reverse: redo }
+    }
+}
+
+/// The undo and redo stacks for a `World` being edited. Edits go through
+/// here, between `begin` and `commit`; changes made to the world some other
+/// way aren't tracked, and undoing past them may not give back what was
+/// there before.
+pub struct History<TZonePayload, TTilePayload> {
+    undo: Vec<Operation<TZonePayload, TTilePayload>>,
+    redo: Vec<Operation<TZonePayload, TTilePayload>>,
+    open: Option<Operation<TZonePayload, TTilePayload>>
+}
+
+impl<TZonePayload, TTilePayload: Clone + Send + Payloadable> History<TZonePayload, TTilePayload> {
+    pub fn new() -> History<TZonePayload, TTilePayload> {
+        History { undo: Vec::new(), redo: Vec::new(), open: None }
+    }
+
+    ///////////////////////
+    // operations
+    ///////////////////////
+    /// Start a new operation; edits made until `commit` are undone as one.
+    pub fn begin(&mut self, name: &str) {
+        match self.open {
+            Some(ref op) => panic!("History::begin: operation '{}' is still open", op.name),
+            None => {}
+        }
+        self.open = Some(Operation { name: name.to_string(), reverse: Vec::new() });
+    }
+    /// Finish the open operation, putting it on the undo stack. Anything that
+    /// could have been redone no longer can.
+    pub fn commit(&mut self) {
+        let op = self.open.take().expect("History::commit: no operation is open");
+        if !op.reverse.is_empty() {
+            self.undo.push(op);
+            self.redo.clear();
+        }
+    }
+    /// Throw away the open operation, undoing whatever it did.
+    pub fn abort<TWorldPayload>(&mut self,
+                                world: &mut World<TWorldPayload, TZonePayload, TTilePayload>) {
+        let op = self.open.take().expect("History::abort: no operation is open");
+        op.revert(world);
+    }
+    pub fn can_undo(&self) -> bool { !self.undo.is_empty() }
+    pub fn can_redo(&self) -> bool { !self.redo.is_empty() }
+    /// Undo the last operation, returning its name.
+    pub fn undo<TWorldPayload>(&mut self,
+                               world: &mut World<TWorldPayload, TZonePayload, TTilePayload>)
+            -> Option<String> {
+        if self.open.is_some() { panic!("History::undo: an operation is still open"); }
+        match self.undo.pop() {
+            Some(op) => {
+                let redo = op.revert(world);
+                let name = redo.name.clone();
+                self.redo.push(redo);
+                Some(name)
+            },
+            None => None
+        }
+    }
+    /// Redo the last operation undone, returning its name.
+    pub fn redo<TWorldPayload>(&mut self,
+                               world: &mut World<TWorldPayload, TZonePayload, TTilePayload>)
+            -> Option<String> {
+        if self.open.is_some() { panic!("History::redo: an operation is still open"); }
+        match self.redo.pop() {
+            Some(op) => {
+                let undo = op.revert(world);
+                let name = undo.name.clone();
+                self.undo.push(undo);
+                Some(name)
+            },
+            None => None
+        }
+    }
+
+    ///////////////////////
+    // edits
+    ///////////////////////
+    /// Make `edit` as part of the open operation.
+    pub fn apply<TWorldPayload>(&mut self,
+                                world: &mut World<TWorldPayload, TZonePayload, TTilePayload>,
+                                edit: Edit<TZonePayload, TTilePayload>) {
+        let op = self.open.as_mut().expect("History::apply: no operation is open");
+        op.reverse.push(edit.apply(world));
+    }
+    pub fn set_tile<TWorldPayload>(&mut self,
+                                   world: &mut World<TWorldPayload, TZonePayload, TTilePayload>,
+                                   gc: GlobalCoord, tile: Tile<TTilePayload>) {
+        self.apply(world, SetTile(gc, tile));
+    }
+    pub fn set_layer_cell<TWorldPayload>(
+            &mut self, world: &mut World<TWorldPayload, TZonePayload, TTilePayload>,
+            gc: GlobalCoord, layer: &str, cell: LayerCell<TTilePayload>) {
+        self.apply(world, SetLayerCell(gc, layer.to_string(), cell));
+    }
+    /// Change the tile at `gc` in place.
+    pub fn edit_tile<TWorldPayload>(&mut self,
+                                    world: &mut World<TWorldPayload, TZonePayload, TTilePayload>,
+                                    gc: GlobalCoord, cb: |&mut Tile<TTilePayload>|) {
+        let mut tile = world.get_zone(&gc.zone_id).get_tile(gc.coords).clone();
+        cb(&mut tile);
+        self.set_tile(world, gc, tile);
+    }
+    /// As `World::new_zone`, but undoable.
+    pub fn new_zone<TWorldPayload>(&mut self,
+                                   world: &mut World<TWorldPayload, TZonePayload, TTilePayload>,
+                                   size: uint, data: TZonePayload,
+                                   cb: |&mut Zone<TZonePayload, TTilePayload>|) -> Uuid {
+        let zone_id = Uuid::new_v4();
+        let mut zone = Zone::new(size, zone_id, data);
+        cb(&mut zone);
+        self.apply(world, AddZone(zone));
+        zone_id
+    }
+    /// As `World::insert_zone`, but undoable; for zones made some other way
+    /// than `new_zone` does, e.g. with `Zone::new_chunked`.
+    pub fn insert_zone<TWorldPayload>(&mut self,
+                                      world: &mut World<TWorldPayload, TZonePayload, TTilePayload>,
+                                      zone: Zone<TZonePayload, TTilePayload>) -> Uuid {
+        let zone_id = zone.id;
+        self.apply(world, AddZone(zone));
+        zone_id
+    }
+    /// Remove a zone along with every portal into it, so undoing puts the
+    /// portals back after the zone.
+    pub fn remove_zone<TWorldPayload>(&mut self,
+                                      world: &mut World<TWorldPayload, TZonePayload, TTilePayload>,
+                                      zid: Uuid) {
+        let portals: Vec<Uuid> = world.portals.iter()
+            .filter(|&(_, p)| p.a_zid() == zid || p.b_zid() == zid)
+            .map(|(pid, _)| *pid)
+            .collect();
+        for pid in portals.into_iter() {
+            self.apply(world, RemovePortal(pid));
+        }
+        self.apply(world, RemoveZone(zid));
+    }
+    /// As `World::new_portal_span`, but undoable.
+    pub fn new_portal<TWorldPayload>(&mut self,
+                                     world: &mut World<TWorldPayload, TZonePayload, TTilePayload>,
+                                     a: (Uuid, (uint, uint), TraversalDirection),
+                                     b: (Uuid, (uint, uint), TraversalDirection),
+                                     width: uint) -> Uuid {
+        let pid = Uuid::new_v4();
+        let (az, ac, ax) = a;
+        let (bz, bc, bx) = b;
+        let portal = Portal::with_width(pid, az, ax, bz, bx, width);
+        self.apply(world, AddPortal(portal, ac, bc));
+        pid
+    }
+    pub fn remove_portal<TWorldPayload>(&mut self,
+                                        world: &mut World<TWorldPayload, TZonePayload,
+                                                          TTilePayload>,
+                                        pid: Uuid) {
+        self.apply(world, RemovePortal(pid));
+    }
+}
@@ -0,0 +1,277 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// hierarchical pathfinding, using zones as the clusters and portals as the
+// entrances between them. A route is first planned over the portal graph,
+// using cached costs of crossing each zone from one portal to another, and
+// then only the zones along that route are searched tile-by-tile.
+
+use std::cmp::Ordering;
+use std::cmp::Ordering::Equal;
+use std::collections::{HashMap, BinaryHeap};
+use uuid::Uuid;
+
+use world::{Payloadable, World, GlobalCoord};
+use zone::ZoneTraversalResult::Destination;
+use path::{MovementCost, ZoneSearch, step_cost};
+
+use self::AbstractNode::*;
+
+// A single tile of a portal's span: the portal, and how far along the span
+// from its origin the tile is. Each open tile of a wide portal is its own
+// way in and out of a zone, so routes can cross wherever is cheapest.
+type Crossing = (Uuid, uint);
+
+// The cached crossing-to-crossing costs of a single zone.
+struct ZoneTable {
+    revision: uint,
+    // where crossing into this zone at each crossing lands
+    entrances: HashMap<Crossing, (uint, uint)>,
+    // the tile each crossing is left from
+    exits: HashMap<Crossing, (uint, uint)>,
+    // (crossing entered by, crossing left by) -> cost of crossing the zone
+    costs: HashMap<(Crossing, Crossing), f64>
+}
+
+impl ZoneTable {
+    fn new<TWorldPayload, TZonePayload, TMover,
+           TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+        world: &World<TWorldPayload, TZonePayload, TTilePayload>, zid: Uuid,
+        mover: &TMover) -> ZoneTable {
+        let zone = world.get_zone(&zid);
+        let mut entrances = HashMap::new();
+        let mut exits = HashMap::new();
+        for (pid, &(ox, oy)) in zone.portal_coords.iter() {
+            let portal = world.get_portal(*pid);
+            let (_, exit) = portal.info_from(zid);
+            let (ax, ay) = if portal.width() > 1 { exit.span_axis() } else { (0, 0) };
+            for i in range(0, portal.width()) {
+                let coords = (ox + ax * i, oy + ay * i);
+                exits.insert((*pid, i), coords);
+                let (ozid, oc, _) = world.portal_destination(zid, *pid, coords);
+                let (_, td) = portal.info_from(ozid);
+                match world.try_traversal_as(GlobalCoord::new(ozid, oc), td, mover) {
+                    Destination(gc) => { entrances.insert((*pid, i), gc.coords); },
+                    _ => {}
+                }
+            }
+        }
+        let mut costs = HashMap::new();
+        for (p, entrance) in entrances.iter() {
+            let search = ZoneSearch::new(world, &GlobalCoord::new(zid, *entrance), mover);
+            for (q, exit) in exits.iter() {
+                match search.cost_to(*exit) {
+                    Some(c) => { costs.insert((*p, *q), c); },
+                    None => {}
+                }
+            }
+        }
+        ZoneTable {
+            revision: zone.revision(),
+            entrances: entrances,
+            exits: exits,
+            costs: costs
+        }
+    }
+}
+
+#[deriving(Clone, PartialEq, Eq, Hash)]
+enum AbstractNode {
+    Start,
+    // arrived in a zone (first) through a crossing (second)
+    Entered(Uuid, Crossing),
+    Goal
+}
+
+#[deriving(PartialEq)]
+struct Frontier {
+    cost: f64,
+    node: AbstractNode
+}
+impl Eq for Frontier {}
+impl PartialOrd for Frontier {
+    fn partial_cmp(&self, other: &Frontier) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Frontier {
+    fn cmp(&self, other: &Frontier) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Equal)
+    }
+}
+
+/// Plans routes for one kind of mover; costs differ between movers, so each
+/// needs its own planner (and cache).
+pub struct HierarchicalPlanner<TMover> {
+    mover: TMover,
+    tables: HashMap<Uuid, ZoneTable>
+}
+
+impl<TMover> HierarchicalPlanner<TMover> {
+    pub fn new(mover: TMover) -> HierarchicalPlanner<TMover> {
+        HierarchicalPlanner { mover: mover, tables: HashMap::new() }
+    }
+
+    /// Drop the cached costs for a zone. This happens on its own when the
+    /// zone's `revision` moves on, so it's only needed when costs change
+    /// some other way, e.g. with what `TMover` is able to cross.
+    pub fn invalidate(&mut self, zid: &Uuid) {
+        self.tables.remove(zid);
+    }
+
+    /// Plan a route from `from` to `to`, returning the tiles stepped onto in
+    /// order (ending with `to`), or `None` if `to` can't be reached.
+    pub fn plan<TWorldPayload, TZonePayload,
+                TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+        &mut self, world: &World<TWorldPayload, TZonePayload, TTilePayload>,
+        from: &GlobalCoord, to: &GlobalCoord) -> Option<Vec<GlobalCoord>> {
+        if from == to { return Some(Vec::new()); }
+        let start_search = ZoneSearch::new(world, from, &self.mover);
+        // searches from each entrance of the goal's zone, made as needed
+        let mut goal_costs: HashMap<Crossing, Option<f64>> = HashMap::new();
+
+        let mut best: HashMap<AbstractNode, f64> = HashMap::new();
+        let mut parents: HashMap<AbstractNode, AbstractNode> = HashMap::new();
+        let mut frontier = BinaryHeap::new();
+        best.insert(Start, 0.0);
+        frontier.push(Frontier { cost: 0.0, node: Start });
+        let mut found = false;
+        loop {
+            let Frontier { cost, node } = match frontier.pop() {
+                Some(f) => f,
+                None => break
+            };
+            match best.find(&node) {
+                Some(b) if cost > *b => continue,
+                _ => {}
+            }
+            // the zone we're in, and what it costs to leave it by each
+            // crossing or to finish the route in it
+            let (zid, exit_costs, goal_cost) = match node {
+                Goal => { found = true; break; },
+                Start => {
+                    let exits: Vec<(Crossing, f64)> = self.table(world, from.zone_id).exits.iter()
+                        .filter_map(|(q, exit)| start_search.cost_to(*exit).map(|c| (*q, c)))
+                        .collect();
+                    let goal_cost = if from.zone_id == to.zone_id {
+                        start_search.cost_to(to.coords)
+                    } else { None };
+                    (from.zone_id, exits, goal_cost)
+                },
+                Entered(zid, p) => {
+                    let (exits, entrance) = {
+                        let table = self.table(world, zid);
+                        let exits: Vec<(Crossing, f64)> = table.exits.keys()
+                            .filter_map(|q| table.costs.find(&(p, *q)).map(|c| (*q, *c)))
+                            .collect();
+                        (exits, *table.entrances.find(&p).expect("hpa: missing entrance"))
+                    };
+                    let goal_cost = if zid == to.zone_id {
+                        let cached = goal_costs.find(&p).map(|c| *c);
+                        match cached {
+                            Some(c) => c,
+                            None => {
+                                let search = ZoneSearch::new(
+                                    world, &GlobalCoord::new(zid, entrance), &self.mover);
+                                let c = search.cost_to(to.coords);
+                                goal_costs.insert(p, c);
+                                c
+                            }
+                        }
+                    } else { None };
+                    (zid, exits, goal_cost)
+                }
+            };
+            match goal_cost {
+                Some(g) => relax(&mut best, &mut parents, &mut frontier,
+                                 node.clone(), Goal, cost + g),
+                None => {}
+            }
+            for (q, c) in exit_costs.into_iter() {
+                let (qpid, _) = q;
+                let (ozid, _) = world.get_portal(qpid).info_from(zid);
+                let entrance = match self.table(world, ozid).entrances.find(&q) {
+                    Some(e) => *e,
+                    None => continue
+                };
+                let step = step_cost(world, &GlobalCoord::new(ozid, entrance), &self.mover);
+                relax(&mut best, &mut parents, &mut frontier,
+                      node.clone(), Entered(ozid, q), cost + c + step);
+            }
+        }
+        if !found { return None; }
+
+        let mut nodes = vec!(Goal);
+        loop {
+            let prev = parents.find(nodes.last().unwrap()).map(|n| n.clone());
+            match prev {
+                Some(n) => nodes.push(n),
+                None => break
+            }
+        }
+        nodes.reverse();
+
+        // refine: search each zone on the route, from where we entered it to
+        // the crossing we leave it by
+        let mut route = Vec::new();
+        let mut search = start_search;
+        for node in nodes.into_iter() {
+            match node {
+                Start => {},
+                Entered(zid, q) => {
+                    let exit = *self.table(world, search.zone_id).exits.find(&q)
+                        .expect("hpa: missing exit");
+                    let entrance = *self.table(world, zid).entrances.find(&q)
+                        .expect("hpa: missing entrance");
+                    route.extend(search.route_to(exit)
+                                 .expect("hpa: planned exit isn't reachable").into_iter());
+                    let arrived = GlobalCoord::new(zid, entrance);
+                    search = ZoneSearch::new(world, &arrived, &self.mover);
+                    route.push(arrived);
+                },
+                Goal => {
+                    route.extend(search.route_to(to.coords)
+                                 .expect("hpa: planned goal isn't reachable").into_iter());
+                }
+            }
+        }
+        Some(route)
+    }
+
+    // The cached table for a zone, rebuilt if the zone has changed since.
+    fn table<'a, TWorldPayload, TZonePayload,
+             TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+        &'a mut self, world: &World<TWorldPayload, TZonePayload, TTilePayload>, zid: Uuid)
+            -> &'a ZoneTable {
+        let revision = world.get_zone(&zid).revision();
+        let stale = match self.tables.find(&zid) {
+            Some(t) => t.revision != revision,
+            None => true
+        };
+        if stale {
+            debug!("hpa: rebuilding table for zone {}", zid);
+            let table = ZoneTable::new(world, zid, &self.mover);
+            self.tables.insert(zid, table);
+        }
+        self.tables.find(&zid).unwrap()
+    }
+}
+
+fn relax(best: &mut HashMap<AbstractNode, f64>,
+         parents: &mut HashMap<AbstractNode, AbstractNode>,
+         frontier: &mut BinaryHeap<Frontier>,
+         from: AbstractNode, to: AbstractNode, cost: f64) {
+    let better = match best.find(&to) {
+        Some(b) => cost < *b,
+        None => true
+    };
+    if better {
+        best.insert(to.clone(), cost);
+        parents.insert(to.clone(), from);
+        frontier.push(Frontier { cost: cost, node: to });
+    }
+}
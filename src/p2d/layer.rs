@@ -0,0 +1,87 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// extra named grids of payloads stacked over a zone's own tiles (e.g.
+// walls, decoration, overlays), so one tile payload type doesn't have to
+// hold all of them. Each layer says whether it counts toward what can be
+// seen through and what can be walked on; the zone's own tiles always count.
+
+use world::Payloadable;
+use tiles::TileStorage;
+use fov::FovType;
+use fov::FovType::*;
+
+/// The name the zone's own tiles go by, beneath every added layer.
+pub static BASE_LAYER: &'static str = "base";
+
+/// What a layer holds at each spot: a payload, and whether it can be walked
+/// over. Portals only ever cover the zone's own tiles.
+#[deriving(Clone, PartialEq, Encodable, Decodable)]
+pub struct LayerCell<TTilePayload> {
+    pub passable: bool,
+    pub payload: TTilePayload
+}
+
+// unlike the zone's own stub tiles, an empty cell is passable, so an empty
+// layer doesn't block anything
+impl<TTilePayload: Payloadable> Payloadable for LayerCell<TTilePayload> {
+    fn stub() -> LayerCell<TTilePayload> {
+        LayerCell { passable: true, payload: Payloadable::stub() }
+    }
+}
+
+#[deriving(Clone, Encodable, Decodable)]
+pub struct Layer<TTilePayload> {
+    pub name: String,
+    /// Whether this layer's `FovType`s are combined into the zone's.
+    pub affects_fov: bool,
+    /// Whether an impassable cell in this layer blocks movement.
+    pub affects_movement: bool,
+    cells: TileStorage<LayerCell<TTilePayload>>
+}
+
+impl<TTilePayload: Payloadable> Layer<TTilePayload> {
+    /// A layer of empty cells, kept in `cells` (dense or chunked, usually to
+    /// match the zone's own tiles).
+    pub fn new(name: &str, cells: TileStorage<LayerCell<TTilePayload>>, affects_fov: bool,
+               affects_movement: bool) -> Layer<TTilePayload> {
+        Layer {
+            name: name.to_string(),
+            affects_fov: affects_fov,
+            affects_movement: affects_movement,
+            cells: cells
+        }
+    }
+    pub fn cell_at_idx<'a>(&'a self, idx: uint) -> &'a LayerCell<TTilePayload> {
+        self.cells.get(idx)
+    }
+    pub fn cell_at_idx_mut<'a>(&'a mut self, idx: uint) -> &'a mut LayerCell<TTilePayload> {
+        self.cells.get_mut(idx)
+    }
+}
+
+impl<TTilePayload: Payloadable + PartialEq> Layer<TTilePayload> {
+    pub fn set_cell_at_idx(&mut self, idx: uint, cell: LayerCell<TTilePayload>) {
+        self.cells.set(idx, cell)
+    }
+    /// See `TileStorage::compact`.
+    pub fn compact(&mut self) {
+        self.cells.compact()
+    }
+}
+
+/// What two stacked tiles let through together: anything blocking blocks,
+/// translucent tiles compound their opacity, and a void tile leaves the
+/// other as it was.
+pub fn combine_fov(a: FovType, b: FovType) -> FovType {
+    match (a, b) {
+        (Void, other) | (other, Void) => other,
+        (Blocking, _) | (_, Blocking) => Blocking,
+        (Transparent, other) | (other, Transparent) => other,
+        (Translucent(x), Translucent(y)) => Translucent(1.0 - (1.0 - x) * (1.0 - y))
+    }
+}
@@ -21,6 +21,18 @@ extern crate log;
 
 pub mod world;
 pub mod zone;
+pub mod tiles;
+pub mod layer;
 pub mod portal;
 pub mod sprite;
 pub mod fov;
+pub mod path;
+pub mod hpa;
+pub mod flow;
+pub mod propagation;
+pub mod spatial;
+pub mod entity;
+pub mod event;
+pub mod delta;
+pub mod history;
+pub mod store;
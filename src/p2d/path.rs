@@ -0,0 +1,315 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// movement-related searches over a `World`, following the same steps (and
+// portals) that `World::try_traversal` allows
+
+use std::f64;
+use std::cmp::Ordering;
+use std::cmp::Ordering::Equal;
+use std::collections::{HashMap, BinaryHeap};
+use uuid::Uuid;
+
+use world::{Payloadable, World, GlobalCoord, TraversalDirection};
+use world::TraversalDirection::*;
+use zone::ZoneTraversalResult::Destination;
+
+pub static DIRECTIONS: [TraversalDirection, ..6] = [North, East, South, West, Up, Down];
+
+/// Implemented by tile payloads to say how costly they are to cross for
+/// each kind of mover (walkers, swimmers, flyers..), where `TMover` is
+/// whatever type the game uses to tell those apart.
+pub trait MovementCost<TMover> {
+    /// The cost for `mover` to step onto this tile; `f64::INFINITY` if it
+    /// can't be entered at all.
+    fn movement_cost(&self, mover: &TMover) -> f64;
+}
+
+/// The cost for `mover` to step onto `gc`, taking passability (of the tile
+/// and any layers over it) into account.
+pub fn step_cost<TWorldPayload, TZonePayload, TMover,
+                 TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+    world: &World<TWorldPayload, TZonePayload, TTilePayload>, gc: &GlobalCoord,
+    mover: &TMover) -> f64 {
+    let zone = world.get_zone(&gc.zone_id);
+    if zone.passable_at(gc.coords) {
+        zone.get_tile(gc.coords).payload.movement_cost(mover)
+    } else {
+        f64::INFINITY
+    }
+}
+
+/// The tiles that can be stepped onto from `gc`, along with the direction
+/// taken to get there and the cost of doing so.
+pub fn neighbors<TWorldPayload, TZonePayload, TMover,
+                 TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+    world: &World<TWorldPayload, TZonePayload, TTilePayload>, gc: &GlobalCoord,
+    mover: &TMover) -> Vec<(TraversalDirection, GlobalCoord, f64)> {
+    let mut found = Vec::new();
+    for dir in DIRECTIONS.iter() {
+        match world.try_traversal_as(gc.clone(), *dir, mover) {
+            Destination(dest) => {
+                let cost = step_cost(world, &dest, mover);
+                found.push((*dir, dest, cost));
+            },
+            _ => {}
+        }
+    }
+    found
+}
+
+/// The tiles from which `gc` can be stepped onto, along with the direction
+/// that step is taken in. Crossing a portal isn't symmetric (you arrive
+/// beside the far portal tile, not on it), so this isn't just `neighbors`.
+pub fn predecessors<TWorldPayload, TZonePayload, TMover,
+                    TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+    world: &World<TWorldPayload, TZonePayload, TTilePayload>, gc: &GlobalCoord,
+    mover: &TMover) -> Vec<(TraversalDirection, GlobalCoord)> {
+    let zone = world.get_zone(&gc.zone_id);
+    let (x, y) = gc.coords;
+    let mut candidates = Vec::new();
+    for dir in DIRECTIONS.iter() {
+        if dir.is_vertical() { continue; }
+        let (dx, dy) = dir.delta();
+        let (nx, ny) = (x as int - dx, y as int - dy);
+        if nx < 0 || ny < 0 || !zone.coords_in_bounds((nx as uint, ny as uint)) {
+            continue;
+        }
+        let n = (nx as uint, ny as uint);
+        candidates.push((*dir, GlobalCoord::new(zone.id, n)));
+        // arriving from a portal means coming from its tile in the other zone
+        match zone.get_tile(n).portal_id {
+            Some(pid) if !world.get_portal(pid).is_vertical() => {
+                let (ozid, oc, _) = world.portal_destination(zone.id, pid, n);
+                candidates.push((*dir, GlobalCoord::new(ozid, oc)));
+            },
+            _ => {}
+        }
+    }
+    match zone.get_tile(gc.coords).portal_id {
+        Some(pid) if world.get_portal(pid).is_vertical() => {
+            let (ozid, oc, td) = world.portal_destination(zone.id, pid, gc.coords);
+            candidates.push((td.opposite(), GlobalCoord::new(ozid, oc)));
+        },
+        _ => {}
+    }
+    let mut found = Vec::new();
+    for (dir, n) in candidates.into_iter() {
+        if step_cost(world, &n, mover) == f64::INFINITY { continue; }
+        match world.try_traversal_as(n.clone(), dir, mover) {
+            Destination(ref dest) if dest == gc => found.push((dir, n)),
+            _ => {}
+        }
+    }
+    found
+}
+
+/// A tile waiting to be expanded in a search. `BinaryHeap` is a max-heap,
+/// so these order by lowest cost first.
+#[deriving(PartialEq)]
+pub struct Frontier {
+    pub cost: f64,
+    pub gc: GlobalCoord
+}
+impl Eq for Frontier {}
+impl PartialOrd for Frontier {
+    fn partial_cmp(&self, other: &Frontier) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Frontier {
+    fn cmp(&self, other: &Frontier) -> Ordering {
+        other.cost.partial_cmp(&self.cost).unwrap_or(Equal)
+    }
+}
+
+/// The cheapest routes from one tile to every other tile of its zone that
+/// can be reached without leaving it.
+pub struct ZoneSearch {
+    pub zone_id: Uuid,
+    pub origin: (uint, uint),
+    costs: HashMap<(uint, uint), f64>,
+    parents: HashMap<(uint, uint), (uint, uint)>
+}
+
+impl ZoneSearch {
+    pub fn new<TWorldPayload, TZonePayload, TMover,
+               TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+        world: &World<TWorldPayload, TZonePayload, TTilePayload>, from: &GlobalCoord,
+        mover: &TMover) -> ZoneSearch {
+        let zid = from.zone_id;
+        let mut costs = HashMap::new();
+        let mut parents = HashMap::new();
+        let mut frontier = BinaryHeap::new();
+        costs.insert(from.coords, 0.0);
+        frontier.push(Frontier { cost: 0.0, gc: from.clone() });
+        loop {
+            let Frontier { cost, gc } = match frontier.pop() {
+                Some(f) => f,
+                None => break
+            };
+            match costs.find(&gc.coords) {
+                Some(best) if cost > *best => continue,
+                _ => {}
+            }
+            for (_, n, step) in neighbors(world, &gc, mover).into_iter() {
+                if n.zone_id != zid { continue; }
+                let next = cost + step;
+                let better = match costs.find(&n.coords) {
+                    Some(v) => next < *v,
+                    None => true
+                };
+                if better {
+                    costs.insert(n.coords, next);
+                    parents.insert(n.coords, gc.coords);
+                    frontier.push(Frontier { cost: next, gc: n });
+                }
+            }
+        }
+        ZoneSearch { zone_id: zid, origin: from.coords, costs: costs, parents: parents }
+    }
+
+    pub fn cost_to(&self, coords: (uint, uint)) -> Option<f64> {
+        self.costs.find(&coords).map(|v| *v)
+    }
+
+    /// The tiles stepped onto, in order, to get from the origin to `coords`.
+    pub fn route_to(&self, coords: (uint, uint)) -> Option<Vec<GlobalCoord>> {
+        if self.costs.find(&coords).is_none() { return None; }
+        let mut route = Vec::new();
+        let mut curr = coords;
+        while curr != self.origin {
+            route.push(GlobalCoord::new(self.zone_id, curr));
+            curr = *self.parents.find(&curr).expect("ZoneSearch: missing parent");
+        }
+        route.reverse();
+        Some(route)
+    }
+}
+
+/// A "Dijkstra map": for every tile from which one of the goals can be
+/// reached, the cost of the cheapest route to the nearest goal. Spans
+/// every zone reachable through portals.
+pub struct DijkstraMap {
+    values: HashMap<GlobalCoord, f64>
+}
+
+impl DijkstraMap {
+    pub fn new<TWorldPayload, TZonePayload, TMover,
+               TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+        world: &World<TWorldPayload, TZonePayload, TTilePayload>, goals: &[GlobalCoord],
+        mover: &TMover) -> DijkstraMap {
+        let mut values = HashMap::new();
+        for g in goals.iter() {
+            values.insert(g.clone(), 0.0);
+        }
+        relax(world, &mut values, mover);
+        DijkstraMap { values: values }
+    }
+
+    /// The cost from `gc` to the nearest goal, or `None` if no goal can be
+    /// reached from it.
+    pub fn get(&self, gc: &GlobalCoord) -> Option<f64> {
+        self.values.find(gc).map(|v| *v)
+    }
+
+    /// A weighted sum of several maps, e.g. to chase the player while
+    /// favoring treasure. Only tiles present in every map are kept.
+    pub fn combine(maps: &[(&DijkstraMap, f64)]) -> DijkstraMap {
+        let mut values = HashMap::new();
+        if maps.len() > 0 {
+            let (first, _) = maps[0];
+            for gc in first.values.keys() {
+                let mut total = 0.0;
+                let mut in_all = true;
+                for &(map, weight) in maps.iter() {
+                    match map.values.find(gc) {
+                        Some(v) => total += *v * weight,
+                        None => { in_all = false; break; }
+                    }
+                }
+                if in_all {
+                    values.insert(gc.clone(), total);
+                }
+            }
+        }
+        DijkstraMap { values: values }
+    }
+
+    /// A map for running away from this map's goals. Values are scaled by
+    /// `-coefficient` (somewhere around 1.2 works well) and then rescanned,
+    /// so fleeing heads for open space instead of into the nearest corner.
+    pub fn flee<TWorldPayload, TZonePayload, TMover,
+                TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+        &self, world: &World<TWorldPayload, TZonePayload, TTilePayload>, coefficient: f64,
+        mover: &TMover) -> DijkstraMap {
+        let mut values = HashMap::new();
+        for (gc, v) in self.values.iter() {
+            values.insert(gc.clone(), *v * -coefficient);
+        }
+        relax(world, &mut values, mover);
+        DijkstraMap { values: values }
+    }
+
+    /// The step from `from` to its lowest-valued neighbor, or `None` when
+    /// nothing around it is lower (i.e. a goal or local minimum is reached).
+    pub fn roll_downhill<TWorldPayload, TZonePayload, TMover,
+                         TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+        &self, world: &World<TWorldPayload, TZonePayload, TTilePayload>, from: &GlobalCoord,
+        mover: &TMover) -> Option<(TraversalDirection, GlobalCoord)> {
+        let mut lowest = match self.get(from) {
+            Some(v) => v,
+            None => return None
+        };
+        let mut step = None;
+        for (dir, n, _) in neighbors(world, from, mover).into_iter() {
+            match self.get(&n) {
+                Some(v) if v < lowest => {
+                    lowest = v;
+                    step = Some((dir, n));
+                },
+                _ => {}
+            }
+        }
+        step
+    }
+}
+
+// Lower every tile's value to the cheapest it can get from the values
+// already present, walking outwards along predecessor steps.
+fn relax<TWorldPayload, TZonePayload, TMover,
+         TTilePayload: Send + Payloadable + MovementCost<TMover>>(
+    world: &World<TWorldPayload, TZonePayload, TTilePayload>,
+    values: &mut HashMap<GlobalCoord, f64>, mover: &TMover) {
+    let mut frontier = BinaryHeap::new();
+    for (gc, v) in values.iter() {
+        frontier.push(Frontier { cost: *v, gc: gc.clone() });
+    }
+    loop {
+        let Frontier { cost, gc } = match frontier.pop() {
+            Some(f) => f,
+            None => break
+        };
+        match values.find(&gc) {
+            Some(best) if cost > *best => continue,
+            _ => {}
+        }
+        let step = step_cost(world, &gc, mover);
+        if step == f64::INFINITY { continue; }
+        let next = cost + step;
+        for (_, prev) in predecessors(world, &gc, mover).into_iter() {
+            let better = match values.find(&prev) {
+                Some(v) => next < *v,
+                None => true
+            };
+            if better {
+                values.insert(prev.clone(), next);
+                frontier.push(Frontier { cost: next, gc: prev });
+            }
+        }
+    }
+}
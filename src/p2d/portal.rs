@@ -5,32 +5,71 @@
 // This file may not be copied, modified, or distributed
 // except according to those terms.
 
+use serialize::{Decodable, Decoder};
 use uuid::Uuid;
 
 use world::TraversalDirection;
 use world::TraversalDirection::*;
 
-#[deriving(Encodable, Decodable)]
+#[deriving(Clone, PartialEq, Encodable)]
 pub struct Portal {
     id: Uuid,
     a_zid: Uuid,
     a_exit: TraversalDirection,
     b_zid: Uuid,
-    b_exit: TraversalDirection
+    b_exit: TraversalDirection,
+    width: uint
 }
 
 impl Portal {
     pub fn new(id: Uuid, a_zid: Uuid, ae: TraversalDirection,
                b_zid: Uuid, bx: TraversalDirection) -> Portal {
+        Portal::with_width(id, a_zid, ae, b_zid, bx, 1)
+    }
+    /// A portal spanning `width` contiguous tiles along the edge it exits
+    /// through, mapped tile-for-tile onto the same number of tiles in the
+    /// other zone.
+    pub fn with_width(id: Uuid, a_zid: Uuid, ae: TraversalDirection,
+                      b_zid: Uuid, bx: TraversalDirection, width: uint) -> Portal {
+        if width == 0 { panic!("portal {} can't have a width of 0", id); }
         if ae == North && bx != South { panic!("bad portal dirs a:{} b:{}", ae, bx); }
         if ae == South && bx != North { panic!("bad portal dirs a:{} b:{}", ae, bx); }
         if ae == West && bx != East { panic!("bad portal dirs a:{} b:{}", ae, bx); }
         if ae == East && bx != West { panic!("bad portal dirs a:{} b:{}", ae, bx); }
-        Portal { id: id, a_zid: a_zid, a_exit: ae, b_zid: b_zid, b_exit: bx }
+        if ae == Up && bx != Down { panic!("bad portal dirs a:{} b:{}", ae, bx); }
+        if ae == Down && bx != Up { panic!("bad portal dirs a:{} b:{}", ae, bx); }
+        if ae.is_vertical() && width > 1 {
+            panic!("vertical portal {} can't have a width of {}", id, width);
+        }
+        Portal { id: id, a_zid: a_zid, a_exit: ae, b_zid: b_zid, b_exit: bx, width: width }
     }
+    pub fn id(&self) -> Uuid { self.id }
+    pub fn a_zid(&self) -> Uuid { self.a_zid }
+    pub fn b_zid(&self) -> Uuid { self.b_zid }
+    pub fn width(&self) -> uint { self.width }
+    /// Stairs and the like; these connect floors, not neighbouring areas.
+    pub fn is_vertical(&self) -> bool { self.a_exit.is_vertical() }
     pub fn info_from(&self, zid: Uuid) -> (Uuid, TraversalDirection) {
         if self.a_zid == zid { (self.b_zid, self.a_exit) }
         else if self.b_zid == zid { (self.a_zid, self.b_exit) }
         else { panic!("zid:{} isn't in this portal!", zid) }
     }
 }
+
+// portals saved before they had a width decode as single-tile ones, so the
+// `width` field is read as optional
+impl<E, D: Decoder<E>> Decodable<D, E> for Portal {
+    fn decode(d: &mut D) -> Result<Portal, E> {
+        d.read_struct("Portal", 6, |d| {
+            let width: Option<uint> = try!(d.read_struct_field("width", 5, Decodable::decode));
+            Ok(Portal {
+                id: try!(d.read_struct_field("id", 0, Decodable::decode)),
+                a_zid: try!(d.read_struct_field("a_zid", 1, Decodable::decode)),
+                a_exit: try!(d.read_struct_field("a_exit", 2, Decodable::decode)),
+                b_zid: try!(d.read_struct_field("b_zid", 3, Decodable::decode)),
+                b_exit: try!(d.read_struct_field("b_exit", 4, Decodable::decode)),
+                width: width.unwrap_or(1)
+            })
+        })
+    }
+}
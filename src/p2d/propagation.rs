@@ -0,0 +1,84 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// spreading a value (noise, scent..) out from a source, the way it would
+// travel: across passable tiles and through portals, fading as it goes
+
+use std::f64;
+use std::collections::{HashMap, BinaryHeap};
+
+use world::{Payloadable, World, GlobalCoord};
+use zone::Zone;
+use zone::ZoneTraversalResult::Destination;
+use path::{Frontier, DIRECTIONS};
+
+/// Implemented by tile payloads to say how much of what's spreading is lost
+/// getting into them, for each kind of thing spread (sound, scent..), where
+/// `TMedium` is whatever type the game uses to tell those apart. The
+/// payloads of a zone's layers take their share on top of the tile's own,
+/// so the stub an empty layer cell holds should take nothing.
+pub trait Attenuation<TMedium> {
+    /// The amount taken off on entering this tile, never negative;
+    /// `f64::INFINITY` if it can't pass at all.
+    fn attenuation(&self, medium: &TMedium) -> f64;
+}
+
+/// The intensity reaching every tile `strength` can spread to from
+/// `source`. Each tile keeps the strongest it's reached with, and tiles it
+/// fades out before reaching are left out.
+pub fn propagate<TWorldPayload, TZonePayload, TMedium,
+                 TTilePayload: Send + Payloadable + Attenuation<TMedium>>(
+    world: &World<TWorldPayload, TZonePayload, TTilePayload>, source: &GlobalCoord,
+    strength: f64, medium: &TMedium) -> HashMap<GlobalCoord, f64> {
+    // searched by how much has been lost so far, so the strongest is always
+    // taken first
+    let mut lost: HashMap<GlobalCoord, f64> = HashMap::new();
+    let mut frontier = BinaryHeap::new();
+    if strength > 0.0 {
+        lost.insert(source.clone(), 0.0);
+        frontier.push(Frontier { cost: 0.0, gc: source.clone() });
+    }
+    loop {
+        let Frontier { cost, gc } = match frontier.pop() {
+            Some(f) => f,
+            None => break
+        };
+        match lost.find(&gc) {
+            Some(best) if cost > *best => continue,
+            _ => {}
+        }
+        for dir in DIRECTIONS.iter() {
+            let n = match world.try_traversal(gc.clone(), *dir) {
+                Destination(n) => n,
+                _ => continue
+            };
+            let step = attenuation_at(world.get_zone(&n.zone_id), n.coords, medium);
+            if step == f64::INFINITY { continue; }
+            let next = cost + step;
+            if next >= strength { continue; }
+            let better = match lost.find(&n) {
+                Some(l) => next < *l,
+                None => true
+            };
+            if better {
+                lost.insert(n.clone(), next);
+                frontier.push(Frontier { cost: next, gc: n });
+            }
+        }
+    }
+    lost.into_iter().map(|(gc, l)| (gc, strength - l)).collect()
+}
+
+// What's lost getting into the tile at `coords`, through it and everything
+// layered over it. Tiles that can't be entered at all never get this far,
+// as `try_traversal` already checks `Zone::passable_at`.
+fn attenuation_at<TZonePayload, TMedium,
+                  TTilePayload: Send + Payloadable + Attenuation<TMedium>>(
+    zone: &Zone<TZonePayload, TTilePayload>, coords: (uint, uint), medium: &TMedium) -> f64 {
+    zone.payloads_at(coords).iter()
+        .fold(0.0, |total, &(_, payload)| total + payload.attenuation(medium))
+}
@@ -0,0 +1,189 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// a bucketed index of where entities stand within a zone, kept in step with
+// `Zone::payload_coords()`, so finding those near a spot doesn't mean looking
+// at every one of them
+
+use std::cmp::{min, max};
+use std::num::Float;
+use std::collections::{HashSet, RingBuf};
+use uuid::Uuid;
+
+use world::{Payloadable, World, RelativeCoord};
+use fov::{RadiusMetric, Chebyshev};
+
+// the width, in tiles, of a bucket
+static BUCKET: uint = 8;
+
+#[deriving(Clone, Encodable, Decodable)]
+pub struct SpatialIndex {
+    // how many buckets there are across (and down) the zone
+    side: uint,
+    buckets: Vec<Vec<(Uuid, (uint, uint))>>
+}
+
+impl SpatialIndex {
+    pub fn new(size: uint) -> SpatialIndex {
+        let side = (size + BUCKET - 1) / BUCKET;
+        SpatialIndex {
+            side: side,
+            buckets: range(0, side * side).map(|_| Vec::new()).collect()
+        }
+    }
+
+    pub fn insert(&mut self, id: Uuid, coords: (uint, uint)) {
+        let b = self.bucket(coords);
+        self.buckets[b].push((id, coords));
+    }
+
+    /// Returns `false` if `id` wasn't indexed at `coords`.
+    pub fn remove(&mut self, id: &Uuid, coords: (uint, uint)) -> bool {
+        let b = self.bucket(coords);
+        match self.buckets[b].iter().position(|&(e, c)| e == *id && c == coords) {
+            Some(i) => {
+                self.buckets[b].swap_remove(i);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Every entity from `min` to `max`, inclusive.
+    pub fn in_rect(&self, min: (uint, uint), max: (uint, uint)) -> Vec<(Uuid, (uint, uint))> {
+        let ((x0, y0), (x1, y1)) = (min, max);
+        let mut found = Vec::new();
+        if x0 > x1 || y0 > y1 || self.side == 0 { return found; }
+        let last = self.side - 1;
+        let (bx0, by0) = (x0 / BUCKET, y0 / BUCKET);
+        let (bx1, by1) = (min(x1 / BUCKET, last), min(y1 / BUCKET, last));
+        if bx0 > last || by0 > last { return found; }
+        for by in range(by0, by1 + 1) {
+            for bx in range(bx0, bx1 + 1) {
+                for &(id, (x, y)) in self.buckets[bx + by * self.side].iter() {
+                    if x >= x0 && x <= x1 && y >= y0 && y <= y1 {
+                        found.push((id, (x, y)));
+                    }
+                }
+            }
+        }
+        found
+    }
+
+    /// Every entity within `radius` of `center`, as measured by `metric`.
+    pub fn within(&self, center: (uint, uint), radius: uint,
+                  metric: RadiusMetric) -> Vec<(Uuid, (uint, uint))> {
+        let (cx, cy) = center;
+        let low = (if cx > radius { cx - radius } else { 0 },
+                   if cy > radius { cy - radius } else { 0 });
+        self.in_rect(low, (cx + radius, cy + radius)).into_iter().filter(|&(_, (x, y))| {
+            metric.within(x as int - cx as int, y as int - cy as int, radius)
+        }).collect()
+    }
+
+    /// The `k` entities closest to `center`, nearest first.
+    pub fn nearest(&self, center: (uint, uint), k: uint,
+                   metric: RadiusMetric) -> Vec<(Uuid, (uint, uint))> {
+        if k == 0 { return Vec::new(); }
+        // widen the search a bucket at a time until there are enough
+        // candidates. Every metric is at least as far as the square being
+        // searched, but the k-th candidate might be further still, so
+        // search out to it before trusting the answer.
+        let furthest = self.side * BUCKET;
+        let mut radius = BUCKET;
+        let mut found = self.within(center, radius, Chebyshev);
+        while found.len() < k && radius < furthest {
+            radius += BUCKET;
+            found = self.within(center, radius, Chebyshev);
+        }
+        sort_by_distance(found.as_mut_slice(), center, metric);
+        if found.len() >= k {
+            let (_, kth) = found[k - 1];
+            let reach = distance(center, kth, metric).ceil() as uint;
+            if reach > radius {
+                found = self.within(center, reach, Chebyshev);
+                sort_by_distance(found.as_mut_slice(), center, metric);
+            }
+        }
+        found.truncate(k);
+        found
+    }
+
+    fn bucket(&self, coords: (uint, uint)) -> uint {
+        let (x, y) = coords;
+        x / BUCKET + (y / BUCKET) * self.side
+    }
+}
+
+/// Every entity within `radius` of `focus`, in its zone or any zone whose
+/// portals come within that distance, each placed relative to `focus` the
+/// same way `fov::compute` places tiles. Only distance limits the search;
+/// nothing blocks it. Where portals loop back on themselves an entity can
+/// be found more than once, at different places.
+pub fn payloads_near<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>(
+    world: &World<TWorldPayload, TZonePayload, TTilePayload>, focus: &RelativeCoord,
+    radius: uint, metric: RadiusMetric) -> Vec<(Uuid, RelativeCoord)> {
+    let mut found = Vec::new();
+    let r = radius as int;
+    // each zone to search, along with where its (0, 0) lies relative to
+    // the focus
+    let start = (focus.zone_id, (focus.gx - focus.lx as int, focus.gy - focus.ly as int));
+    let mut visited = HashSet::new();
+    let mut queue = RingBuf::new();
+    visited.insert(start.clone());
+    queue.push_back(start);
+    loop {
+        let (zid, (ox, oy)) = match queue.pop_front() {
+            Some(next) => next,
+            None => break
+        };
+        let zone = world.get_zone(&zid);
+        let last = zone.size as int - 1;
+        // the square around the focus, in this zone's coords
+        let (x0, y0) = (max(focus.gx - r - ox, 0), max(focus.gy - r - oy, 0));
+        let (x1, y1) = (min(focus.gx + r - ox, last), min(focus.gy + r - oy, last));
+        if x0 > x1 || y0 > y1 { continue; }
+        let hits = zone.spatial().in_rect((x0 as uint, y0 as uint), (x1 as uint, y1 as uint));
+        for (id, (x, y)) in hits.into_iter() {
+            let (gx, gy) = (ox + x as int, oy + y as int);
+            if metric.within(gx - focus.gx, gy - focus.gy, radius) {
+                found.push((id, RelativeCoord::new(zid, (x, y), (gx, gy))));
+            }
+        }
+        for (pid, &(px, py)) in zone.portal_coords.iter() {
+            let portal = world.get_portal(*pid);
+            if portal.is_vertical() { continue; }
+            let (ozid, exit) = portal.info_from(zid);
+            let (ax, ay) = exit.span_axis();
+            let near = range(0, portal.width()).any(|i| {
+                let (gx, gy) = (ox + (px + ax * i) as int, oy + (py + ay * i) as int);
+                metric.within(gx - focus.gx, gy - focus.gy, radius)
+            });
+            if !near { continue; }
+            // the two sides of a portal overlap, tile for tile
+            let (_, (qx, qy), _) = world.portal_destination(zid, *pid, (px, py));
+            let next = (ozid, (ox + px as int - qx as int, oy + py as int - qy as int));
+            if !visited.contains(&next) {
+                visited.insert(next.clone());
+                queue.push_back(next);
+            }
+        }
+    }
+    found
+}
+
+fn distance(from: (uint, uint), to: (uint, uint), metric: RadiusMetric) -> f64 {
+    let ((fx, fy), (tx, ty)) = (from, to);
+    metric.distance(tx as int - fx as int, ty as int - fy as int)
+}
+
+fn sort_by_distance(found: &mut [(Uuid, (uint, uint))], center: (uint, uint),
+                    metric: RadiusMetric) {
+    found.sort_by(|&(_, a), &(_, b)| {
+        distance(center, a, metric).partial_cmp(&distance(center, b, metric)).unwrap()
+    });
+}
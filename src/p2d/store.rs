@@ -0,0 +1,417 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// keeping only some of a `World`'s zones in memory. Zones live in a
+// `ZoneStore` (e.g. a directory with a file per zone) and a `ZoneCache` pages
+// them in and out of `World::zones`, least recently used out first, writing
+// back any that have changed.
+//
+// A world handed a cache with `World::page_through` pages zones in itself,
+// as `get_zone` (and so `try_traversal`, `fov::compute` and everything else
+// built on it) comes across ones that aren't loaded; only reading the world
+// never pages anything out, so call `World::settle` every so often. A cache
+// can also be driven by hand, against a world that hasn't been given it,
+// through its own `get_zone`, `try_traversal` and `fov`.
+//
+// A zone read back in starts again at revision 0, so the cache remembers
+// the revision each zone was paged out at and sets it back past that,
+// leaving anything keyed on revisions (`fov::FovCache`,
+// `hpa::HierarchicalPlanner`, `flow::FlowField`) to see it as changed.
+
+use std::cmp::max;
+use std::cell::UnsafeCell;
+use std::collections::{HashMap, HashSet, RingBuf};
+use std::io::File;
+use std::sync::Mutex;
+use uuid::Uuid;
+
+use world::{World, Payloadable, GlobalCoord, RelativeCoord, TraversalDirection};
+use zone::{Zone, ZoneTraversalResult};
+use zone::ZoneTraversalResult::DestinationOutsideBounds;
+use fov;
+use fov::{FovItem, RadiusMetric};
+
+/// Somewhere zones are kept while they're not in memory.
+pub trait ZoneStore<TZonePayload, TTilePayload> {
+    /// Read a zone back in, if the store has it.
+    fn load(&mut self, zid: &Uuid) -> Option<Zone<TZonePayload, TTilePayload>>;
+    fn save(&mut self, zone: &Zone<TZonePayload, TTilePayload>);
+}
+
+/// What a `World` pages zones in and out through, once it's been handed
+/// one with `World::page_through`. `ZoneCache` is one.
+pub trait Pager<TZonePayload, TTilePayload> {
+    /// Read back `zid`, which isn't loaded; `None` if there's no such zone.
+    fn fetch(&mut self, zid: &Uuid) -> Option<Zone<TZonePayload, TTilePayload>>;
+    /// `zid`, which is loaded, has just been used.
+    fn used(&mut self, zid: &Uuid);
+    /// The zones to page out, least recently used first, to get back under
+    /// capacity without paging out any of `keep`.
+    fn evictions(&mut self, keep: &[Uuid]) -> Vec<Uuid>;
+    /// Take a zone that's been paged out, writing it back if it's changed.
+    fn put_away(&mut self, zone: Zone<TZonePayload, TTilePayload>);
+    /// Write `zone`, which stays loaded, back if it's changed since it was
+    /// last saved.
+    fn write_back(&mut self, zone: &Zone<TZonePayload, TTilePayload>);
+}
+
+/// A `World`'s pager, and the zones it's read in while the world was only
+/// borrowed. Those are moved into `World::zones` by `World::settle`.
+pub struct Paging<TZonePayload, TTilePayload> {
+    pub pager: Box<Pager<TZonePayload, TTilePayload> + Send>,
+    // boxed, so they stay put while the map grows
+    pub fetched: HashMap<Uuid, Box<Zone<TZonePayload, TTilePayload>>>
+}
+
+/// `Paging` behind a lock, so a world shared between tasks (as by
+/// `fov::compute_parallel`) can page in from any of them.
+pub struct PagingState<TZonePayload, TTilePayload> {
+    lock: Mutex<()>,
+    paging: UnsafeCell<Paging<TZonePayload, TTilePayload>>
+}
+
+// everything in `paging` is only touched under `lock`, or through `&mut`
+unsafe impl<TZonePayload, TTilePayload> Sync for PagingState<TZonePayload, TTilePayload> {}
+
+impl<TZonePayload, TTilePayload> PagingState<TZonePayload, TTilePayload> {
+    pub fn new(pager: Box<Pager<TZonePayload, TTilePayload> + Send>)
+            -> PagingState<TZonePayload, TTilePayload> {
+        PagingState {
+            lock: Mutex::new(()),
+            paging: UnsafeCell::new(Paging { pager: pager, fetched: HashMap::new() })
+        }
+    }
+    pub fn get_mut<'a>(&'a mut self) -> &'a mut Paging<TZonePayload, TTilePayload> {
+        unsafe { &mut *self.paging.get() }
+    }
+    pub fn into_inner(self) -> Paging<TZonePayload, TTilePayload> {
+        unsafe { self.paging.into_inner() }
+    }
+    /// Tell the pager `zid` has just been used.
+    pub fn used(&self, zid: &Uuid) {
+        let _guard = self.lock.lock();
+        unsafe { (*self.paging.get()).pager.used(zid); }
+    }
+    /// `zid`, read in through the pager if it hasn't been already.
+    pub fn fetch<'a>(&'a self, zid: &Uuid) -> Option<&'a Zone<TZonePayload, TTilePayload>> {
+        let _guard = self.lock.lock();
+        let paging = unsafe { &mut *self.paging.get() };
+        if !paging.fetched.contains_key(zid) {
+            match paging.pager.fetch(zid) {
+                Some(zone) => { paging.fetched.insert(*zid, box zone); },
+                None => return None
+            }
+        }
+        paging.pager.used(zid);
+        let zone: *const Zone<TZonePayload, TTilePayload> = &**paging.fetched.find(zid).unwrap();
+        // a fetched zone is only moved or dropped through `get_mut` or
+        // `into_inner`, neither of which can be called while `self` is
+        // borrowed, so it outlives the borrow
+        Some(unsafe { &*zone })
+    }
+}
+
+/// Zones kept one to a file in a directory, named by id. How zones are
+/// turned into text and back (e.g. with `serialize::json`) is up to the
+/// caller.
+pub struct DirectoryStore<TZonePayload, TTilePayload> {
+    dir: Path,
+    encode: fn(&Zone<TZonePayload, TTilePayload>) -> String,
+    decode: fn(&str) -> Option<Zone<TZonePayload, TTilePayload>>
+}
+
+impl<TZonePayload, TTilePayload> DirectoryStore<TZonePayload, TTilePayload> {
+    pub fn new(dir: Path, encode: fn(&Zone<TZonePayload, TTilePayload>) -> String,
+               decode: fn(&str) -> Option<Zone<TZonePayload, TTilePayload>>)
+            -> DirectoryStore<TZonePayload, TTilePayload> {
+        DirectoryStore { dir: dir, encode: encode, decode: decode }
+    }
+    pub fn path_for(&self, zid: &Uuid) -> Path {
+        self.dir.join(format!("{}.zone", zid))
+    }
+}
+
+impl<TZonePayload, TTilePayload> ZoneStore<TZonePayload, TTilePayload>
+        for DirectoryStore<TZonePayload, TTilePayload> {
+    fn load(&mut self, zid: &Uuid) -> Option<Zone<TZonePayload, TTilePayload>> {
+        let path = self.path_for(zid);
+        if !path.exists() { return None; }
+        let text = match File::open(&path).read_to_string() {
+            Ok(text) => text,
+            Err(e) => panic!("DirectoryStore::load: can't read {}: {}", path.display(), e)
+        };
+        let zone = (self.decode)(text.as_slice());
+        if zone.is_none() {
+            panic!("DirectoryStore::load: can't decode {}", path.display());
+        }
+        zone
+    }
+    fn save(&mut self, zone: &Zone<TZonePayload, TTilePayload>) {
+        let path = self.path_for(&zone.id);
+        let text = (self.encode)(zone);
+        match File::create(&path).write_str(text.as_slice()) {
+            Ok(()) => {},
+            Err(e) => panic!("DirectoryStore::save: can't write {}: {}", path.display(), e)
+        }
+    }
+}
+
+/// Pages zones between a `ZoneStore` and a `World`, keeping no more than
+/// `capacity` of them loaded. Zones the world had before the cache saw them
+/// are left alone until they're paged in (or `adopt`ed), after which they're
+/// managed like any other.
+pub struct ZoneCache<TStore> {
+    pub store: TStore,
+    capacity: uint,
+    // loaded zones, least recently used first
+    order: Vec<Uuid>,
+    // each loaded zone's `changes()` as of its last load or save, or `None`
+    // if it's never been saved
+    saved: HashMap<Uuid, Option<uint>>,
+    // the revision each zone was at when it was last paged out
+    revisions: HashMap<Uuid, uint>
+}
+
+impl<TStore> ZoneCache<TStore> {
+    pub fn new(store: TStore, capacity: uint) -> ZoneCache<TStore> {
+        ZoneCache {
+            store: store,
+            capacity: capacity,
+            order: Vec::new(),
+            saved: HashMap::new(),
+            revisions: HashMap::new()
+        }
+    }
+    pub fn capacity(&self) -> uint { self.capacity }
+    pub fn len(&self) -> uint { self.order.len() }
+
+    // move `zid` to the most recently used end
+    fn touch(&mut self, zid: &Uuid) {
+        match self.order.iter().position(|z| z == zid) {
+            Some(i) => { self.order.remove(i); },
+            None => {}
+        }
+        self.order.push(*zid);
+    }
+}
+
+impl<TZonePayload, TTilePayload: Send + Payloadable,
+     TStore: ZoneStore<TZonePayload, TTilePayload>> ZoneCache<TStore> {
+    /// Start managing a zone that's already in the world; it'll be written
+    /// to the store when it's paged out.
+    pub fn adopt<TWorldPayload>(&mut self,
+                                world: &World<TWorldPayload, TZonePayload, TTilePayload>,
+                                zid: &Uuid) {
+        if !world.zones.contains_key(zid) {
+            panic!("ZoneCache::adopt: zone {} isn't in the world", zid);
+        }
+        self.used(zid);
+    }
+
+    /// Make sure `zid` is loaded, reading it from the store if need be, then
+    /// page out whatever's gone over capacity. Returns false if the store
+    /// doesn't have it either.
+    pub fn page_in<TWorldPayload>(&mut self,
+                                  world: &mut World<TWorldPayload, TZonePayload, TTilePayload>,
+                                  zid: &Uuid) -> bool {
+        let loaded = self.load(world, zid);
+        self.page_out_over(world, &[*zid]);
+        loaded
+    }
+
+    /// Load every zone that can be reached through portals within `radius`
+    /// tiles of `focus` (the zones `fov::compute` or a walk of that length
+    /// could need), then page out whatever's gone over capacity, other than
+    /// those.
+    pub fn page_in_reachable<TWorldPayload>(
+            &mut self, world: &mut World<TWorldPayload, TZonePayload, TTilePayload>,
+            zid: &Uuid, focus: (uint, uint), radius: uint) {
+        let r = radius as int;
+        let (fx, fy) = focus;
+        let mut needed = vec!(*zid);
+        if !self.load(world, zid) {
+            panic!("ZoneCache::page_in_reachable: zone {} isn't in the store", zid);
+        }
+        // each zone to search, along with where its (0, 0) lies relative to
+        // the focus
+        let start = (*zid, (-(fx as int), -(fy as int)));
+        let mut visited = HashSet::new();
+        let mut queue = RingBuf::new();
+        visited.insert(start.clone());
+        queue.push_back(start);
+        loop {
+            let (zid, (ox, oy)) = match queue.pop_front() {
+                Some(next) => next,
+                None => break
+            };
+            let mut portals = Vec::new();
+            {
+                let zone = world.get_zone(&zid);
+                for (pid, &(px, py)) in zone.portal_coords.iter() {
+                    let portal = world.get_portal(*pid);
+                    let (ozid, exit) = portal.info_from(zid);
+                    let (ax, ay) = if portal.width() > 1 { exit.span_axis() } else { (0, 0) };
+                    let near = range(0, portal.width()).any(|i| {
+                        let (gx, gy) = (ox + (px + ax * i) as int, oy + (py + ay * i) as int);
+                        max(gx.abs(), gy.abs()) <= r
+                    });
+                    if near { portals.push((*pid, (px, py), ozid, portal.is_vertical())); }
+                }
+            }
+            for (pid, (px, py), ozid, vertical) in portals.into_iter() {
+                if !self.load(world, &ozid) { continue; }
+                if !needed.contains(&ozid) { needed.push(ozid); }
+                // there's no offset to carry across floors, so stop there
+                if vertical { continue; }
+                // the two sides of a portal overlap, tile for tile
+                let (_, (qx, qy), _) = world.portal_destination(zid, pid, (px, py));
+                let next = (ozid, (ox + px as int - qx as int, oy + py as int - qy as int));
+                if !visited.contains(&next) {
+                    visited.insert(next.clone());
+                    queue.push_back(next);
+                }
+            }
+        }
+        self.page_out_over(world, needed.as_slice());
+    }
+
+    /// The zone `zid`, paged in if need be; `None` if neither the world nor
+    /// the store has it.
+    pub fn get_zone<'a, TWorldPayload>(
+            &mut self, world: &'a mut World<TWorldPayload, TZonePayload, TTilePayload>,
+            zid: &Uuid) -> Option<&'a Zone<TZonePayload, TTilePayload>> {
+        if !self.page_in(world, zid) { return None; }
+        Some(world.get_zone(zid))
+    }
+    /// As `get_zone`, but mutable.
+    pub fn get_zone_mut<'a, TWorldPayload>(
+            &mut self, world: &'a mut World<TWorldPayload, TZonePayload, TTilePayload>,
+            zid: &Uuid) -> Option<&'a mut Zone<TZonePayload, TTilePayload>> {
+        if !self.page_in(world, zid) { return None; }
+        Some(world.get_zone_mut(zid))
+    }
+
+    /// As `World::try_traversal`, paging in the zone `src` is in and any it
+    /// could step into first. A portal into a zone the store doesn't have
+    /// leads nowhere, as `DestinationOutsideBounds`.
+    pub fn try_traversal<TWorldPayload>(
+            &mut self, world: &mut World<TWorldPayload, TZonePayload, TTilePayload>,
+            src: GlobalCoord, dir: TraversalDirection) -> ZoneTraversalResult {
+        self.page_in_reachable(world, &src.zone_id, src.coords, 1);
+        let dangling = match world.get_zone(&src.zone_id).get_tile(src.coords).portal_id {
+            Some(pid) => {
+                let (ozid, _) = world.get_portal(pid).info_from(src.zone_id);
+                !world.zones.contains_key(&ozid)
+            },
+            None => false
+        };
+        if dangling { return DestinationOutsideBounds; }
+        world.try_traversal(src, dir)
+    }
+
+    /// Write back every loaded zone that's changed since it was last saved.
+    pub fn flush<TWorldPayload>(&mut self,
+                                world: &World<TWorldPayload, TZonePayload, TTilePayload>) {
+        let loaded = self.order.clone();
+        for zid in loaded.iter() {
+            self.write_back(world.get_zone(zid));
+        }
+    }
+
+    // load `zid` if it isn't already, marking it as just used
+    fn load<TWorldPayload>(&mut self,
+                           world: &mut World<TWorldPayload, TZonePayload, TTilePayload>,
+                           zid: &Uuid) -> bool {
+        if world.zones.contains_key(zid) {
+            self.used(zid);
+            return true;
+        }
+        match self.fetch(zid) {
+            Some(zone) => {
+                world.insert_zone(zone);
+                true
+            },
+            None => false
+        }
+    }
+
+    // page out the least recently used zones, other than those in `keep`,
+    // until no more than `capacity` are loaded
+    fn page_out_over<TWorldPayload>(&mut self,
+                                    world: &mut World<TWorldPayload, TZonePayload, TTilePayload>,
+                                    keep: &[Uuid]) {
+        for zid in self.evictions(keep).iter() {
+            match world.remove_zone(zid) {
+                Some(zone) => self.put_away(zone),
+                None => { self.saved.remove(zid); }
+            }
+        }
+    }
+}
+
+impl<TZonePayload, TTilePayload: Send + Payloadable,
+     TStore: ZoneStore<TZonePayload, TTilePayload>>
+        Pager<TZonePayload, TTilePayload> for ZoneCache<TStore> {
+    fn fetch(&mut self, zid: &Uuid) -> Option<Zone<TZonePayload, TTilePayload>> {
+        let mut zone = match self.store.load(zid) {
+            Some(zone) => zone,
+            None => return None
+        };
+        match self.revisions.find(zid) {
+            Some(revision) => zone.touch_past(*revision),
+            None => {}
+        }
+        self.saved.insert(*zid, Some(zone.changes()));
+        self.touch(zid);
+        Some(zone)
+    }
+
+    fn used(&mut self, zid: &Uuid) {
+        if !self.saved.contains_key(zid) { self.saved.insert(*zid, None); }
+        self.touch(zid);
+    }
+
+    fn evictions(&mut self, keep: &[Uuid]) -> Vec<Uuid> {
+        let mut evicted = Vec::new();
+        let mut i = 0;
+        while self.order.len() > self.capacity && i < self.order.len() {
+            let zid = self.order[i];
+            if keep.contains(&zid) { i += 1; continue; }
+            self.order.remove(i);
+            evicted.push(zid);
+        }
+        evicted
+    }
+
+    fn put_away(&mut self, zone: Zone<TZonePayload, TTilePayload>) {
+        let saved = self.saved.pop(&zone.id);
+        if saved != Some(Some(zone.changes())) { self.store.save(&zone); }
+        self.revisions.insert(zone.id, zone.revision());
+    }
+
+    fn write_back(&mut self, zone: &Zone<TZonePayload, TTilePayload>) {
+        let saved = self.saved.find(&zone.id).map(|s| *s);
+        if saved != Some(Some(zone.changes())) {
+            self.store.save(zone);
+            self.saved.insert(zone.id, Some(zone.changes()));
+        }
+    }
+}
+
+impl<TZonePayload, TTilePayload: Send + Payloadable + FovItem,
+     TStore: ZoneStore<TZonePayload, TTilePayload>> ZoneCache<TStore> {
+    /// As `fov::compute_with_metric`, paging in every zone the view could
+    /// reach first.
+    pub fn fov<TWorldPayload>(&mut self,
+                              world: &mut World<TWorldPayload, TZonePayload, TTilePayload>,
+                              focus: RelativeCoord, radius: uint, metric: RadiusMetric,
+                              start_ang: &mut [f64], end_ang: &mut [f64])
+            -> Vec<RelativeCoord> {
+        self.page_in_reachable(world, &focus.zone_id, (focus.lx, focus.ly), radius);
+        fov::compute_with_metric(&*world, focus, radius, metric, start_ang, end_ang)
+    }
+}
@@ -0,0 +1,169 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// how a zone's tiles (and its layers' cells) are laid out in memory: either
+// one dense grid, or square chunks that are only allocated once something in
+// them is written, for big zones that are mostly empty. Unwritten cells read
+// as `Payloadable::stub()`.
+
+use world::Payloadable;
+
+use self::TileStorage::*;
+
+// tiles along each side of a chunk
+static CHUNK: uint = 16;
+
+#[deriving(Clone, Encodable, Decodable)]
+pub enum TileStorage<TCell> {
+    /// Every tile, row by row.
+    Dense(Vec<TCell>),
+    Chunked(ChunkedTiles<TCell>)
+}
+
+impl<TCell: Payloadable> TileStorage<TCell> {
+    /// A dense grid of `size*size` stub tiles.
+    pub fn dense(size: uint) -> TileStorage<TCell> {
+        let limit = size*size;
+        let mut tiles = Vec::with_capacity(limit);
+        for _ in range(0, limit) {
+            tiles.push(Payloadable::stub());
+        }
+        Dense(tiles)
+    }
+    /// A grid of `size*size` stub tiles, none of them allocated yet.
+    pub fn chunked(size: uint) -> TileStorage<TCell> {
+        Chunked(ChunkedTiles::new(size))
+    }
+    /// A grid of `size*size` stubs of another kind, laid out the same way
+    /// (dense or chunked) as this one.
+    pub fn new_like<TOther: Payloadable>(&self, size: uint) -> TileStorage<TOther> {
+        match self {
+            &Dense(_) => TileStorage::dense(size),
+            &Chunked(_) => TileStorage::chunked(size)
+        }
+    }
+    pub fn get<'a>(&'a self, idx: uint) -> &'a TCell {
+        match self {
+            &Dense(ref tiles) => &tiles[idx],
+            &Chunked(ref chunks) => chunks.get(idx)
+        }
+    }
+    pub fn get_mut<'a>(&'a mut self, idx: uint) -> &'a mut TCell {
+        match self {
+            &Dense(ref mut tiles) => &mut tiles[idx],
+            &Chunked(ref mut chunks) => chunks.get_mut(idx)
+        }
+    }
+}
+
+impl<TCell: Payloadable + PartialEq> TileStorage<TCell> {
+    /// Write a whole cell; unlike `get_mut`, writing a stub into a chunk
+    /// that hasn't been allocated leaves it that way.
+    pub fn set(&mut self, idx: uint, cell: TCell) {
+        match self {
+            &Dense(ref mut tiles) => tiles[idx] = cell,
+            &Chunked(ref mut chunks) => chunks.set(idx, cell)
+        }
+    }
+    /// Free whatever has gone back to being stubs; dense grids have nothing
+    /// to free.
+    pub fn compact(&mut self) {
+        match self {
+            &Dense(_) => {},
+            &Chunked(ref mut chunks) => chunks.compact()
+        }
+    }
+}
+
+/// A zone's tiles in `CHUNK`-square chunks, row by row. A chunk is only
+/// allocated when one of its tiles is borrowed mutably, or `set` to
+/// something other than a stub; until then all of its tiles read as stubs.
+/// Chunks written back to stubs stay allocated until `compact`ed. Where the
+/// zone's size isn't a multiple of `CHUNK`, the chunks along its right and
+/// bottom edges hang over it.
+#[deriving(Clone, Encodable, Decodable)]
+pub struct ChunkedTiles<TCell> {
+    size: uint,
+    side: uint,
+    // how many chunks there are across (and down) the zone
+    per_row: uint,
+    chunks: Vec<Option<Vec<TCell>>>,
+    // what every tile in an unallocated chunk reads as
+    stub: TCell
+}
+
+impl<TCell: Payloadable> ChunkedTiles<TCell> {
+    pub fn new(size: uint) -> ChunkedTiles<TCell> {
+        let side = if size < CHUNK { size } else { CHUNK };
+        let per_row = if side == 0 { 0 } else { (size + side - 1) / side };
+        let mut chunks = Vec::with_capacity(per_row * per_row);
+        for _ in range(0, per_row * per_row) {
+            chunks.push(None);
+        }
+        ChunkedTiles {
+            size: size,
+            side: side,
+            per_row: per_row,
+            chunks: chunks,
+            stub: Payloadable::stub()
+        }
+    }
+    /// How many chunks have been allocated.
+    pub fn allocated(&self) -> uint {
+        self.chunks.iter().filter(|c| c.is_some()).count()
+    }
+    // the chunk holding tile `idx`, and the tile's index within it
+    fn locate(&self, idx: uint) -> (uint, uint) {
+        if idx >= self.size * self.size {
+            panic!("ChunkedTiles: tile {} is out of bounds for size {}", idx, self.size);
+        }
+        let (x, y) = (idx % self.size, idx / self.size);
+        let chunk = (x / self.side) + (y / self.side) * self.per_row;
+        (chunk, (x % self.side) + (y % self.side) * self.side)
+    }
+    pub fn get<'a>(&'a self, idx: uint) -> &'a TCell {
+        let (chunk, i) = self.locate(idx);
+        match self.chunks[chunk] {
+            Some(ref tiles) => &tiles[i],
+            None => &self.stub
+        }
+    }
+    pub fn get_mut<'a>(&'a mut self, idx: uint) -> &'a mut TCell {
+        let (chunk, i) = self.locate(idx);
+        if self.chunks[chunk].is_none() {
+            let limit = self.side * self.side;
+            let mut tiles = Vec::with_capacity(limit);
+            for _ in range(0, limit) {
+                tiles.push(Payloadable::stub());
+            }
+            self.chunks[chunk] = Some(tiles);
+        }
+        match self.chunks[chunk] {
+            Some(ref mut tiles) => &mut tiles[i],
+            None => unreachable!()
+        }
+    }
+}
+
+impl<TCell: Payloadable + PartialEq> ChunkedTiles<TCell> {
+    pub fn set(&mut self, idx: uint, cell: TCell) {
+        let (chunk, _) = self.locate(idx);
+        if self.chunks[chunk].is_none() && cell == self.stub { return; }
+        *self.get_mut(idx) = cell;
+    }
+    /// Free every chunk whose tiles have all gone back to being stubs.
+    pub fn compact(&mut self) {
+        let stub = &self.stub;
+        for chunk in self.chunks.iter_mut() {
+            let empty = match chunk {
+                &Some(ref tiles) => tiles.iter().all(|t| t == stub),
+                &None => false
+            };
+            if empty { *chunk = None; }
+        }
+    }
+}
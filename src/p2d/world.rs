@@ -5,23 +5,34 @@
 // This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use std::cmp::{PartialEq, Eq};
+use std::cmp::{PartialEq, Eq, max};
 use std::hash::Hash;
 use std::collections::HashMap;
+use std::f64;
+use std::mem;
+use serialize::{Encodable, Decodable, Encoder, Decoder};
 use uuid::Uuid;
 
 use zone::{Zone, ZoneTraversalResult};
 use zone::ZoneTraversalResult::*;
 use portal;
+use path::MovementCost;
+use event::{EventBuffer, Subscribers, SubscriberId, WorldEvent};
+use event::WorldEvent::{ZoneAdded, ZoneRemoved, PortalAdded, PortalRemoved, PayloadMoved};
+use store::{Pager, PagingState};
 
 use self::TraversalDirection::*;
 
-#[deriving(Decodable, Encodable, Eq, PartialEq, Hash, Show)]
+#[deriving(Clone, Copy, Decodable, Encodable, Eq, PartialEq, Hash, Show)]
 pub enum TraversalDirection {
     North,
     East,
     South,
     West,
+    /// Climbing to the floor above, e.g. via stairs or a ladder. Vertical
+    /// directions only ever lead through portals; there is no planar step.
+    Up,
+    Down,
     NoDirection
 }
 impl TraversalDirection {
@@ -31,20 +42,87 @@ impl TraversalDirection {
             &South => North,
             &West => East,
             &East => West,
+            &Up => Down,
+            &Down => Up,
             &NoDirection => NoDirection
         }
     }
+    /// The planar step taken when moving in this direction; vertical
+    /// directions don't move within a zone at all.
+    pub fn delta(&self) -> (int, int) {
+        match self {
+            &North => (0, -1),
+            &West => (-1, 0),
+            &South => (0, 1),
+            &East => (1, 0),
+            &Up | &Down => (0, 0),
+            &NoDirection => panic!("NoDirection has no delta")
+        }
+    }
+    pub fn is_vertical(&self) -> bool {
+        match self {
+            &Up | &Down => true,
+            _ => false
+        }
+    }
+    /// The unit step along which a portal exiting in this direction lays out
+    /// its tiles, e.g. a portal exiting `North` spans a row of tiles.
+    pub fn span_axis(&self) -> (uint, uint) {
+        match self {
+            &North | &South => (1, 0),
+            &East | &West => (0, 1),
+            &Up | &Down => panic!("vertical portals don't span an edge"),
+            &NoDirection => panic!("NoDirection has no span axis")
+        }
+    }
 }
 
 pub trait Payloadable {
     fn stub() -> Self;
 }
 
-#[deriving(Decodable, Encodable)]
 pub struct World<TWorldPayload, TZonePayload, TTilePayload> {
     pub data: TWorldPayload,
     pub zones: HashMap<Uuid, Zone<TZonePayload, TTilePayload>>,
     pub portals: HashMap<Uuid, portal::Portal>,
+    // changes made at the world level, e.g. new zones and portals. Neither
+    // this nor `subscribers` is encoded; they belong to whoever's running
+    // the world, not to the world itself
+    events: EventBuffer,
+    subscribers: Subscribers,
+    // where zones that aren't loaded are read in from, once there's
+    // somewhere (see `page_through`); not encoded either
+    paging: Option<PagingState<TZonePayload, TTilePayload>>
+}
+
+impl<E, S: Encoder<E>, TWorldPayload: Encodable<S, E>, TZonePayload: Encodable<S, E>,
+     TTilePayload: Encodable<S, E>> Encodable<S, E>
+        for World<TWorldPayload, TZonePayload, TTilePayload> {
+    fn encode(&self, s: &mut S) -> Result<(), E> {
+        s.emit_struct("World", 3, |s| {
+            try!(s.emit_struct_field("data", 0, |s| self.data.encode(s)));
+            try!(s.emit_struct_field("zones", 1, |s| self.zones.encode(s)));
+            s.emit_struct_field("portals", 2, |s| self.portals.encode(s))
+        })
+    }
+}
+
+// a decoded world starts out recording nothing, with no subscribers
+impl<E, D: Decoder<E>, TWorldPayload: Decodable<D, E>, TZonePayload: Decodable<D, E>,
+     TTilePayload: Decodable<D, E>> Decodable<D, E>
+        for World<TWorldPayload, TZonePayload, TTilePayload> {
+    fn decode(d: &mut D) -> Result<World<TWorldPayload, TZonePayload, TTilePayload>, E> {
+        d.read_struct("World", 3, |d| {
+            Ok(World {
+                data: try!(d.read_struct_field("data", 0, Decodable::decode)),
+                zones: try!(d.read_struct_field("zones", 1, Decodable::decode)),
+                portals: try!(d.read_struct_field("portals", 2, Decodable::decode)),
+                events: EventBuffer::new(),
+                subscribers: Subscribers::new(),
+                paging: None
+            })
+        })
+    }
 }
 
 #[deriving(Eq, PartialEq, Hash, Clone, Encodable, Decodable, Show)]
@@ -62,7 +140,7 @@ impl GlobalCoord {
     }
 }
 
-#[deriving(Hash, Eq, PartialEq)]
+#[deriving(Hash, Eq, PartialEq, Clone, Show)]
 pub struct RelativeCoord {
     pub zone_id: Uuid,
     pub lx: uint,
@@ -92,36 +170,279 @@ impl<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>
             data: data,
             zones: HashMap::new(),
             portals: HashMap::new(),
+            events: EventBuffer::new(),
+            subscribers: Subscribers::new(),
+            paging: None
         }
     }
 
     pub fn new_zone(&mut self, size: uint, data: TZonePayload,
                     cb: |&mut Zone<TZonePayload, TTilePayload>|) -> Uuid {
         let zone_id = Uuid::new_v4();
-        let z = Zone::<TZonePayload, TTilePayload>::new(size, zone_id, data);
+        let mut z = Zone::<TZonePayload, TTilePayload>::new(size, zone_id, data);
+        z.record_events(self.events.is_recording());
+        z.limit_events(self.events.limit());
         self.zones.insert(zone_id, z);
+        self.events.push(ZoneAdded(zone_id));
+        self.used(&zone_id);
         cb(self.zones.get_mut(&zone_id).unwrap());
         zone_id
     }
 
+    /// Put an existing zone into the world, e.g. one received from a server,
+    /// replacing any zone with the same id.
+    pub fn insert_zone(&mut self, zone: Zone<TZonePayload, TTilePayload>) {
+        let mut zone = zone;
+        let zone_id = zone.id;
+        // whatever the zone had recorded belonged to wherever it came from
+        zone.record_events(false);
+        zone.record_events(self.events.is_recording());
+        zone.limit_events(self.events.limit());
+        match self.zones.find(&zone_id) {
+            Some(old) => zone.touch_past(old.revision()),
+            None => {}
+        }
+        self.zones.insert(zone_id, zone);
+        self.events.push(ZoneAdded(zone_id));
+        self.used(&zone_id);
+    }
+
+    /// Take a zone out of the world. Portals leading into it are left alone;
+    /// it's up to the caller to remove them too.
+    pub fn remove_zone(&mut self, id: &Uuid) -> Option<Zone<TZonePayload, TTilePayload>> {
+        if !self.zones.contains_key(id) { return None; }
+        // hand out what the zone has recorded before it goes
+        self.gather_events();
+        let removed = self.zones.pop(id);
+        self.events.push(ZoneRemoved(*id));
+        removed
+    }
+
     pub fn new_portal(&mut self, a: (Uuid, (uint, uint), TraversalDirection),
                       b: (Uuid, (uint, uint), TraversalDirection)) -> Uuid {
+        self.new_portal_span(a, b, 1)
+    }
+
+    /// Create a portal covering `width` contiguous tiles on each side,
+    /// starting at the given coords and running along the exit edge (rows
+    /// for `North`/`South` exits, columns for `East`/`West`). Tiles are
+    /// mapped one-for-one, in order, between the two sides.
+    pub fn new_portal_span(&mut self, a: (Uuid, (uint, uint), TraversalDirection),
+                           b: (Uuid, (uint, uint), TraversalDirection),
+                           width: uint) -> Uuid {
         let next_id = Uuid::new_v4();
         let (az, ac, ax) = a;
         let (bz, bc, bx) = b;
-        let portal = portal::Portal::new(next_id, az, ax, bz, bx);
+        let portal = portal::Portal::with_width(next_id, az, ax, bz, bx, width);
+        // both sides are checked first, so a clash leaves nothing half-added
+        self.get_zone(&az).check_portal_span(&next_id, ac, ax, width);
+        self.get_zone(&bz).check_portal_span(&next_id, bc, bx, width);
         self.portals.insert(next_id, portal);
+        self.events.push(PortalAdded(next_id));
         {
             let zone_a = self.get_zone_mut(&az);
-            zone_a.add_portal(next_id, ac);
+            zone_a.add_portal_span(next_id, ac, ax, width);
         }
         {
             let zone_b = self.get_zone_mut(&bz);
-            zone_b.add_portal(next_id, bc);
+            zone_b.add_portal_span(next_id, bc, bx, width);
         }
         next_id
     }
 
+    ///////////////////////
+    // paging
+    ///////////////////////
+    /// Read zones in through `pager` from now on, whenever one that isn't
+    /// loaded is looked up, and page out whatever it says to on `settle`.
+    /// The zones already in the world are handed to it as loaded.
+    pub fn page_through(&mut self, pager: Box<Pager<TZonePayload, TTilePayload> + Send>) {
+        let mut pager = pager;
+        for zid in self.zones.keys() {
+            pager.used(zid);
+        }
+        self.paging = Some(PagingState::new(pager));
+        self.settle(&[]);
+    }
+    /// Stop paging, writing back every loaded zone that's changed, and hand
+    /// the pager back.
+    pub fn stop_paging(&mut self) -> Option<Box<Pager<TZonePayload, TTilePayload> + Send>> {
+        self.settle(&[]);
+        self.flush_pages();
+        self.paging.take().map(|state| state.into_inner().pager)
+    }
+    pub fn is_paging(&self) -> bool { self.paging.is_some() }
+    /// Move zones read in while the world was only borrowed into `zones`,
+    /// then page out whatever's gone over the pager's capacity, other than
+    /// `keep`. `get_zone_mut` settles on its own; otherwise call this every
+    /// so often (e.g. once a tick), as only reading the world never pages
+    /// anything out.
+    pub fn settle(&mut self, keep: &[Uuid]) {
+        let fetched = match self.paging {
+            Some(ref mut state) => mem::replace(&mut state.get_mut().fetched, HashMap::new()),
+            None => return
+        };
+        for (_, zone) in fetched.into_iter() {
+            self.insert_zone(*zone);
+        }
+        let evicted = match self.paging {
+            Some(ref mut state) => state.get_mut().pager.evictions(keep),
+            None => return
+        };
+        for zid in evicted.iter() {
+            match self.remove_zone(zid) {
+                Some(zone) => self.paging.as_mut().unwrap().get_mut().pager.put_away(zone),
+                None => {}
+            }
+        }
+    }
+    /// Write back every loaded zone that's changed since the pager last
+    /// saved it.
+    pub fn flush_pages(&mut self) {
+        match self.paging {
+            Some(ref mut state) => {
+                let pager = &mut state.get_mut().pager;
+                for (_, zone) in self.zones.iter() {
+                    pager.write_back(zone);
+                }
+            },
+            None => {}
+        }
+    }
+    // with a pager, make sure `zid` is loaded, if it can be, then settle
+    fn page_in(&mut self, zid: &Uuid) {
+        if self.paging.is_none() { return; }
+        if self.zones.contains_key(zid) {
+            self.used(zid);
+        } else {
+            let fetched = {
+                let paging = self.paging.as_mut().unwrap().get_mut();
+                match paging.fetched.pop(zid) {
+                    Some(zone) => Some(*zone),
+                    None => paging.pager.fetch(zid)
+                }
+            };
+            match fetched {
+                Some(zone) => self.insert_zone(zone),
+                None => {}
+            }
+        }
+        self.settle(&[*zid]);
+    }
+    // let the pager know `zid` has just been used
+    fn used(&self, zid: &Uuid) {
+        match self.paging {
+            Some(ref state) => state.used(zid),
+            None => {}
+        }
+    }
+
+    ///////////////////////
+    // change events
+    ///////////////////////
+    /// Start or stop recording `WorldEvent`s, in the world and all of its
+    /// zones. Stopping throws away anything not yet handed out.
+    pub fn record_events(&mut self, on: bool) {
+        self.events.set_recording(on);
+        for (_, zone) in self.zones.iter_mut() {
+            zone.record_events(on);
+        }
+    }
+    pub fn is_recording_events(&self) -> bool { self.events.is_recording() }
+    /// Hold at most `limit` events at a time, in the world, in each zone and
+    /// for each subscriber (`DEFAULT_EVENT_LIMIT` until set). Past that the
+    /// oldest are dropped, and the next drain starts with `EventsDropped`.
+    pub fn limit_events(&mut self, limit: uint) {
+        self.events.set_limit(limit);
+        for (_, zone) in self.zones.iter_mut() {
+            zone.limit_events(limit);
+        }
+        self.subscribers.set_limit(limit);
+    }
+    /// Register a new subscriber; it sees every event recorded from now on.
+    pub fn subscribe(&mut self) -> SubscriberId {
+        // what's pending was recorded before it subscribed
+        self.gather_events();
+        self.subscribers.subscribe()
+    }
+    pub fn unsubscribe(&mut self, id: SubscriberId) -> bool {
+        self.subscribers.unsubscribe(id)
+    }
+    /// Everything recorded since `id` last drained, e.g. once a tick. Events
+    /// from the world itself come first, then each zone's in the order they
+    /// happened there; order between zones isn't kept, so a payload moving
+    /// between zones should go through `move_payload`.
+    pub fn drain_events(&mut self, id: SubscriberId) -> Vec<WorldEvent> {
+        self.gather_events();
+        self.subscribers.drain(id)
+    }
+
+    // pull pending events out of the world and its zones and hand them to
+    // every subscriber, dropping them if there's no one to hand them to
+    fn gather_events(&mut self) {
+        let events = self.events.take();
+        self.subscribers.publish(events.as_slice());
+        for (_, zone) in self.zones.iter_mut() {
+            let events = zone.take_events();
+            self.subscribers.publish(events.as_slice());
+        }
+    }
+
+    /// Move payload `plid` out of zone `from` to `to`, which may be in
+    /// another zone. A move between zones is recorded as one `PayloadMoved`,
+    /// after everything recorded before it, so a subscriber never sees the
+    /// payload in both zones or in neither.
+    pub fn move_payload(&mut self, plid: &Uuid, from: &Uuid, to: GlobalCoord) {
+        if *from == to.zone_id {
+            self.get_zone_mut(from).move_payload(plid, to.coords);
+            return;
+        }
+        if !self.zones.contains_key(&to.zone_id) {
+            panic!("World::move_payload: there's no zone {} to move {} to", to.zone_id, plid);
+        }
+        let recording = self.events.is_recording();
+        if recording { self.gather_events(); }
+        let coords = match self.get_zone_mut(from).remove_payload(plid) {
+            Some(c) => c,
+            None => panic!("World::move_payload: payload {} isn't in zone {}", plid, from)
+        };
+        self.get_zone_mut(&to.zone_id).add_payload(*plid, to.coords);
+        if recording {
+            // each zone saw half of the move; record it whole instead
+            self.get_zone_mut(from).take_events();
+            self.get_zone_mut(&to.zone_id).take_events();
+            self.events.push(PayloadMoved(*plid, GlobalCoord::new(*from, coords), to));
+        }
+    }
+
+    /// Put an existing portal into the world. Only the portal itself is
+    /// added; the tiles it covers in each zone are left as they are.
+    pub fn insert_portal(&mut self, portal: portal::Portal) {
+        let pid = portal.id();
+        self.portals.insert(pid, portal);
+        self.events.push(PortalAdded(pid));
+    }
+
+    /// Take a portal out of the world, clearing it from the tiles it covers
+    /// in whichever of its zones are still around.
+    pub fn remove_portal(&mut self, pid: &Uuid) -> Option<portal::Portal> {
+        let portal = match self.portals.pop(pid) {
+            Some(p) => p,
+            None => return None
+        };
+        for zid in [portal.a_zid(), portal.b_zid()].iter() {
+            let (_, exit) = portal.info_from(*zid);
+            // a paged out zone would come back with the portal still in it
+            self.page_in(zid);
+            match self.zones.find_mut(zid) {
+                Some(zone) => { zone.remove_portal_span(pid, exit, portal.width()); },
+                None => {}
+            }
+        }
+        self.events.push(PortalRemoved(*pid));
+        Some(portal)
+    }
+
     // Entity lookup
     pub fn get_payload<'a>(&'a self, gc: &GlobalCoord) -> &'a TTilePayload {
         let zone = self.get_zone(&gc.zone_id);
@@ -131,27 +452,121 @@ impl<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>
         let zone = self.get_zone_mut(&gc.zone_id);
         &mut zone.get_tile_mut(gc.coords).payload
     }
+    /// Where payload `plid` stands, in whichever zone has it. Looks through
+    /// every zone, so check the zone it was last known in first.
+    pub fn find_payload(&self, plid: &Uuid) -> Option<GlobalCoord> {
+        for (zid, zone) in self.zones.iter() {
+            match zone.find_payload(plid) {
+                Some(coords) => return Some(GlobalCoord::new(*zid, coords)),
+                None => {}
+            }
+        }
+        None
+    }
+    // these are looked up on every step of a scan or search, so the message
+    // is only put together once a lookup has failed. With a pager, zones
+    // that aren't loaded are read in; see `page_through`.
     pub fn get_zone<'a>(&'a self, id: &Uuid) -> &'a Zone<TZonePayload, TTilePayload> {
-        self.zones.find(id).expect(format!("Cannot find zone with id {}", id).as_slice())
+        match self.find_zone(id) {
+            Some(zone) => zone,
+            None => panic!("Cannot find zone with id {}", id)
+        }
+    }
+    /// As `get_zone`, but `None` if there's no such zone.
+    pub fn find_zone<'a>(&'a self, id: &Uuid) -> Option<&'a Zone<TZonePayload, TTilePayload>> {
+        match self.zones.find(id) {
+            Some(zone) => {
+                self.used(id);
+                Some(zone)
+            },
+            None => match self.paging {
+                Some(ref state) => state.fetch(id),
+                None => None
+            }
+        }
     }
     pub fn get_zone_mut<'a>(&'a mut self, id: &Uuid) -> &'a mut Zone<TZonePayload, TTilePayload> {
-        self.zones.find_mut(id).expect(format!("Cannot find_mut zone with id {}", id).as_slice())
+        self.page_in(id);
+        match self.zones.find_mut(id) {
+            Some(zone) => zone,
+            None => panic!("Cannot find_mut zone with id {}", id)
+        }
     }
 
     pub fn get_portal<'a>(&'a self, id: Uuid) -> &'a portal::Portal {
-        self.portals.find(&id).expect(format!("Cannot find portal with id {}", id).as_slice())
+        match self.portals.find(&id) {
+            Some(portal) => portal,
+            None => panic!("Cannot find portal with id {}", id)
+        }
+    }
 
+    /// Given a tile covered by portal `pid` in zone `zid`, find the zone and
+    /// matching tile on the other side, along with the direction the portal
+    /// exits in from `zid`.
+    pub fn portal_destination(&self, zid: Uuid, pid: Uuid, coords: (uint, uint))
+            -> (Uuid, (uint, uint), TraversalDirection) {
+        let portal = self.get_portal(pid);
+        let (ozid, td) = portal.info_from(zid);
+        let (sx, sy) = *self.get_zone(&zid).get_portal_coords(&pid);
+        let (ox, oy) = *self.get_zone(&ozid).get_portal_coords(&pid);
+        let (cx, cy) = coords;
+        // one of these is always 0, as the span runs along a single axis
+        let offset = (cx - sx) + (cy - sy);
+        let dest = if offset == 0 {
+            (ox, oy)
+        } else {
+            let (ax, ay) = td.span_axis();
+            (ox + ax * offset, oy + ay * offset)
+        };
+        (ozid, dest, td)
+    }
+
+    /// The tile on the floor above `gc`, if any.
+    pub fn level_above(&self, gc: &GlobalCoord) -> Option<GlobalCoord> {
+        self.find_level(gc, Up)
+    }
+    /// The tile on the floor below `gc`, if any.
+    pub fn level_below(&self, gc: &GlobalCoord) -> Option<GlobalCoord> {
+        self.find_level(gc, Down)
+    }
+
+    // Floors are lined up by the stairs between them: a tile's counterpart
+    // sits at the same offset from the far end of the stairs as it does from
+    // the near end. When a zone has several stairs going the same way, the
+    // closest one to `gc` is used.
+    fn find_level(&self, gc: &GlobalCoord, dir: TraversalDirection) -> Option<GlobalCoord> {
+        let zone = self.get_zone(&gc.zone_id);
+        let (gx, gy) = gc.coords;
+        let mut best: Option<(uint, GlobalCoord)> = None;
+        for (pid, coords) in zone.portal_coords.iter() {
+            let (ozid, (ox, oy), td) = self.portal_destination(zone.id, *pid, *coords);
+            if td != dir { continue; }
+            let (sx, sy) = *coords;
+            let (dx, dy) = (gx as int - sx as int, gy as int - sy as int);
+            let (tx, ty) = (ox as int + dx, oy as int + dy);
+            let other_zone = self.get_zone(&ozid);
+            if tx < 0 || ty < 0 || !other_zone.coords_in_bounds((tx as uint, ty as uint)) {
+                continue;
+            }
+            let dist = max(dx.abs(), dy.abs()) as uint;
+            let closer = match best {
+                Some((best_dist, _)) => dist < best_dist,
+                None => true
+            };
+            if closer {
+                best = Some((dist, GlobalCoord::new(ozid, (tx as uint, ty as uint))));
+            }
+        }
+        best.map(|(_, gc)| gc)
     }
 
     /// Try traversing from one `GlobalCoord` to another.
     pub fn try_traversal(&self, src: GlobalCoord, dir: TraversalDirection) -> ZoneTraversalResult {
-        let delta: (int, int) = match dir {
-            North => (0, -1),
-            West => (-1, 0),
-            South => (0, 1),
-            East => (1, 0),
-            NoDirection => panic!("NoDirection not allowed in traverse()")
-        };
+        if dir == NoDirection {
+            panic!("NoDirection not allowed in traverse()");
+        }
+        // vertical moves are only possible through a portal, checked below
+        let delta = dir.delta();
         let curr_zone_id = src.zone_id;
         let (dest_zone_id, dest_coords) = {
             let curr_zone = self.get_zone(&curr_zone_id);
@@ -164,7 +579,7 @@ impl<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>
                     Some(pid) => {
                         let portal = self.get_portal(pid);
                         let (_, td) = portal.info_from(curr_zone.id);
-                        println!("portal dir: {} traversing dir: {}", td, dir);
+                        debug!("portal dir: {} traversing dir: {}", td, dir);
                         td == dir
                     },
                     None => false
@@ -173,19 +588,22 @@ impl<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>
             if traversing_portal {
                 let curr_tile = curr_zone.get_tile(curr_coords);
                 let pid = curr_tile.portal_id.expect("None for portal_id.. shouldn't happen.");
-                let portal = self.get_portal(pid);
-                let (ozid, td) = portal.info_from(curr_zone.id);
-                let other_zone = self.get_zone(&ozid);
-                let (ocx, ocy) = *other_zone.get_portal_coords(&pid);
+                let (ozid, (ocx, ocy), td) =
+                    self.portal_destination(curr_zone.id, pid, curr_coords);
                 let oc = match td {
                     North => (ocx as int, ocy as int-1 as int),
                     East => (ocx as int+1 as int, ocy as int),
                     South => (ocx as int, ocy as int+1 as int),
                     West => (ocx as int-1 as int, ocy as int),
+                    // arrive on the stairs themselves
+                    Up | Down => (ocx as int, ocy as int),
                     NoDirection => panic!("NoDirection not allowed in traverse()")
                 };
-                println!("other zone: {}, this zone: {}", ozid, curr_zone_id);
+                debug!("other zone: {}, this zone: {}", ozid, curr_zone_id);
                 (ozid, oc)
+            } else if dir.is_vertical() {
+                // no stairs here, so there's nowhere to go
+                return DestinationOutsideBounds;
             } else {
                 let dest_coords = (curr_x as int + d_x, curr_y as int + d_y);
                 (curr_zone_id, dest_coords)
@@ -193,13 +611,12 @@ impl<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>
         };
         let dest_zone = self.get_zone(&dest_zone_id);
         let (dx, dy) = dest_coords;
-        println!("Dir {} Delta {} src: {} dest: {}",dir,delta,src.coords, dest_coords);
+        debug!("Dir {} Delta {} src: {} dest: {}",dir,delta,src.coords, dest_coords);
         if dx < 0 || dy < 0 || dx >= dest_zone.size as int|| dy >= dest_zone.size as int{
             DestinationOutsideBounds
         } else {
             let clean_dc = (dx as uint, dy as uint);
-            let dest_tile = dest_zone.get_tile(clean_dc);
-            if !dest_tile.passable {
+            if !dest_zone.passable_at(clean_dc) {
                 DestinationBlocked
             }
             else {
@@ -207,4 +624,23 @@ impl<TWorldPayload, TZonePayload, TTilePayload: Send + Payloadable>
             }
         }
     }
+    /// Like `try_traversal`, but the destination also has to be enterable by
+    /// `mover`, according to its tile's `MovementCost`. Only the destination
+    /// is checked, as a step costs what the tile it enters does (see
+    /// `path::step_cost`), so a mover standing somewhere it couldn't have
+    /// entered can still step off it.
+    pub fn try_traversal_as<TMover>(&self, src: GlobalCoord, dir: TraversalDirection,
+                                    mover: &TMover) -> ZoneTraversalResult
+            where TTilePayload: MovementCost<TMover> {
+        match self.try_traversal(src, dir) {
+            Destination(gc) => {
+                if self.get_payload(&gc).movement_cost(mover) == f64::INFINITY {
+                    DestinationBlocked
+                } else {
+                    Destination(gc)
+                }
+            },
+            other => other
+        }
+    }
 }
@@ -12,18 +12,28 @@
 
 use std::option::{None, Some};
 use std::vec::Vec;
+use std::cmp::max;
 use std::collections::HashMap;
 
+use serialize::{Encodable, Decodable, Encoder, Decoder};
 use uuid::Uuid;
 
-use world::GlobalCoord;
+use world::{GlobalCoord, TraversalDirection};
 use world::Payloadable;
+use spatial::SpatialIndex;
+use tiles::TileStorage;
+use tiles::TileStorage::{Dense, Chunked};
+use layer::{Layer, LayerCell, BASE_LAYER, combine_fov};
+use fov::{FovItem, FovType};
+use event::{EventBuffer, WorldEvent};
+use event::WorldEvent::*;
 
 pub fn coords_to_idx(coords: (uint, uint), size: uint) -> uint {
     let (x, y) = coords;
     x + (y * size)
 }
 
+#[deriving(Clone, PartialEq, Show)]
 pub enum ZoneTraversalResult {
     Destination(GlobalCoord),
     DestinationBlocked,
@@ -31,7 +41,7 @@ pub enum ZoneTraversalResult {
     DestinationOutsideBounds,
 }
 
-#[deriving(Encodable, Decodable)]
+#[deriving(Clone, PartialEq, Encodable, Decodable)]
 pub struct Tile<TTilePayload> {
     pub passable: bool,
     pub payload: TTilePayload,
@@ -56,58 +66,222 @@ impl<TTilePayload: Send + Payloadable> Tile<TTilePayload> {
     }
 }
 
-#[deriving(Encodable, Decodable)]
+// so tiles can be kept in a `TileStorage`
+impl<TTilePayload: Send + Payloadable> Payloadable for Tile<TTilePayload> {
+    fn stub() -> Tile<TTilePayload> { Tile::stub() }
+}
+
+#[deriving(Clone)]
 pub struct Zone<TZonePayload, TTilePayload> {
     pub id: Uuid,
     pub data: TZonePayload,
     pub size: uint,
-    pub all_tiles: Vec<Tile<TTilePayload>>,
-    pub payload_coords: HashMap<Uuid, (uint, uint)>,
-    pub portal_coords: HashMap<Uuid, (uint, uint)>
+    // only changed through the zone's methods, so every change is seen;
+    // read it through `tiles`
+    all_tiles: TileStorage<Tile<TTilePayload>>,
+    // only changed through `add_payload`, `move_payload` and `remove_payload`,
+    // which keep `spatial` in step with it
+    payload_coords: HashMap<Uuid, (uint, uint)>,
+    pub portal_coords: HashMap<Uuid, (uint, uint)>,
+    revision: uint,
+    // bumped by every change made through the zone's methods, tiles or not
+    changes: uint,
+    // where everything in `payload_coords` stands, bucketed for lookups
+    spatial: SpatialIndex,
+    // changes waiting to be gathered up by `World::drain_events`
+    events: EventBuffer,
+    // extra layers over `all_tiles`, bottom first
+    layers: Vec<Layer<TTilePayload>>
+}
+
+// only what makes up the zone is encoded; `revision`, `changes`, `spatial`
+// and `events` belong to whoever holds it, and start afresh when it's
+// decoded (the index rebuilt from `payload_coords`)
+//
+// Zones saved before they had layers have no `layers` field, and decode
+// with none. Those saved before tiles could be chunked have no
+// `tiles_version`, and hold their tiles as a plain list. That's still how dense zones write
+// them; chunked zones write their `ChunkedTiles` in its place.
+static DENSE_TILES: uint = 1;
+static CHUNKED_TILES: uint = 2;
+
+impl<E, S: Encoder<E>, TZonePayload: Encodable<S, E>, TTilePayload: Encodable<S, E>>
+        Encodable<S, E> for Zone<TZonePayload, TTilePayload> {
+    fn encode(&self, s: &mut S) -> Result<(), E> {
+        s.emit_struct("Zone", 8, |s| {
+            try!(s.emit_struct_field("id", 0, |s| self.id.encode(s)));
+            try!(s.emit_struct_field("data", 1, |s| self.data.encode(s)));
+            try!(s.emit_struct_field("size", 2, |s| self.size.encode(s)));
+            try!(s.emit_struct_field("all_tiles", 3, |s| match self.all_tiles {
+                Dense(ref tiles) => tiles.encode(s),
+                Chunked(ref chunks) => chunks.encode(s)
+            }));
+            try!(s.emit_struct_field("payload_coords", 4, |s| self.payload_coords.encode(s)));
+            try!(s.emit_struct_field("portal_coords", 5, |s| self.portal_coords.encode(s)));
+            try!(s.emit_struct_field("layers", 6, |s| self.layers.encode(s)));
+            let version = match self.all_tiles {
+                Dense(_) => DENSE_TILES,
+                Chunked(_) => CHUNKED_TILES
+            };
+            s.emit_struct_field("tiles_version", 7, |s| version.encode(s))
+        })
+    }
+}
+
+impl<E, D: Decoder<E>, TZonePayload: Decodable<D, E>, TTilePayload: Decodable<D, E>>
+        Decodable<D, E> for Zone<TZonePayload, TTilePayload> {
+    fn decode(d: &mut D) -> Result<Zone<TZonePayload, TTilePayload>, E> {
+        d.read_struct("Zone", 8, |d| {
+            let size: uint = try!(d.read_struct_field("size", 2, Decodable::decode));
+            let layers: Option<Vec<Layer<TTilePayload>>> =
+                try!(d.read_struct_field("layers", 6, Decodable::decode));
+            let version: Option<uint> =
+                try!(d.read_struct_field("tiles_version", 7, Decodable::decode));
+            let all_tiles = match version.unwrap_or(DENSE_TILES) {
+                v if v == DENSE_TILES =>
+                    Dense(try!(d.read_struct_field("all_tiles", 3, Decodable::decode))),
+                v if v == CHUNKED_TILES =>
+                    Chunked(try!(d.read_struct_field("all_tiles", 3, Decodable::decode))),
+                v => return Err(d.error(format!("Zone: unknown tiles_version {}", v)
+                                        .as_slice()))
+            };
+            let payload_coords: HashMap<Uuid, (uint, uint)> =
+                try!(d.read_struct_field("payload_coords", 4, Decodable::decode));
+            let mut spatial = SpatialIndex::new(size);
+            for (plid, coords) in payload_coords.iter() {
+                spatial.insert(*plid, *coords);
+            }
+            Ok(Zone {
+                id: try!(d.read_struct_field("id", 0, Decodable::decode)),
+                data: try!(d.read_struct_field("data", 1, Decodable::decode)),
+                size: size,
+                all_tiles: all_tiles,
+                payload_coords: payload_coords,
+                portal_coords: try!(d.read_struct_field("portal_coords", 5, Decodable::decode)),
+                revision: 0,
+                changes: 0,
+                spatial: spatial,
+                events: EventBuffer::new(),
+                layers: layers.unwrap_or(Vec::new())
+            })
+        })
+    }
 }
 
 impl<TZonePayload, TTilePayload: Send + Payloadable> Zone<TZonePayload, TTilePayload> {
     pub fn new(size: uint, id: Uuid, data: TZonePayload) -> Zone<TZonePayload, TTilePayload> {
+        Zone::with_tiles(size, id, data, TileStorage::dense(size))
+    }
+    /// A zone whose tiles are only allocated, a chunk at a time, once
+    /// they're written to; for large zones that are mostly stubs.
+    pub fn new_chunked(size: uint, id: Uuid, data: TZonePayload)
+            -> Zone<TZonePayload, TTilePayload> {
+        Zone::with_tiles(size, id, data, TileStorage::chunked(size))
+    }
+    fn with_tiles(size: uint, id: Uuid, data: TZonePayload,
+                  tiles: TileStorage<Tile<TTilePayload>>)
+            -> Zone<TZonePayload, TTilePayload> {
         // size must be a power of 2
-        let mut z = Zone {
+        Zone {
             id: id,
             data: data,
             size: size,
-            all_tiles: Vec::with_capacity(size*size),
+            all_tiles: tiles,
             payload_coords: HashMap::new(),
-            portal_coords: HashMap::new()
-        };
-        let limit = size*size;
-        let mut ctr = 0;
-        while ctr < limit {
-            let tile: Tile<TTilePayload> = Tile::<TTilePayload>::stub();
-            z.all_tiles.push(tile);
-            ctr += 1;
+            portal_coords: HashMap::new(),
+            revision: 0,
+            changes: 0,
+            spatial: SpatialIndex::new(size),
+            events: EventBuffer::new(),
+            layers: Vec::new()
         }
-        z
     }
     ///////////////////////
     // coordinate information for things within the Zone
     ///////////////////////
     pub fn get_payload_coords<'a>(&'a self, plid: &Uuid) -> &'a (uint, uint) {
-        self.payload_coords.find(plid).expect(
-            format!("Unable to find coords for payload {}", plid).as_slice())
+        match self.payload_coords.find(plid) {
+            Some(coords) => coords,
+            None => panic!("Unable to find coords for payload {}", plid)
+        }
+    }
+    /// Where each entity in the zone stands.
+    pub fn payload_coords<'a>(&'a self) -> &'a HashMap<Uuid, (uint, uint)> {
+        &self.payload_coords
+    }
+    /// Where `plid` stands, if it's in the zone.
+    pub fn find_payload(&self, plid: &Uuid) -> Option<(uint, uint)> {
+        self.payload_coords.find(plid).map(|c| *c)
     }
     pub fn get_portal_coords<'a>(&'a self, pid: &Uuid) -> &'a (uint, uint) {
-        self.portal_coords.find(pid).expect(format!("Unable to find coords for portal {}", pid).as_slice())
+        match self.portal_coords.find(pid) {
+            Some(coords) => coords,
+            None => panic!("Unable to find coords for portal {}", pid)
+        }
     }
+    /// The index of the entities in `payload_coords`, for finding those
+    /// near a spot.
+    pub fn spatial<'a>(&'a self) -> &'a SpatialIndex { &self.spatial }
     pub fn coords_in_bounds(&self, coords: (uint, uint)) -> bool {
         let (x, y) = coords;
         return x < self.size && y < self.size
     }
     ///////////////////////
+    // change tracking
+    ///////////////////////
+    /// Bumped whenever a tile is borrowed mutably (and so might have changed
+    /// its passability, `FovType`, etc), so anything cached from this zone's
+    /// tiles, like paths or FOV, can tell when it's gone stale.
+    pub fn revision(&self) -> uint { self.revision }
+    /// Bump the revision by hand, so anything cached from the zone is
+    /// rebuilt.
+    pub fn touch(&mut self) {
+        self.revision += 1;
+        self.changes += 1;
+    }
+    /// Bumped by every change made through the zone's methods: tiles and
+    /// layers as for `revision`, but also payloads, portals and `data_mut`.
+    /// Anything that has to know when the zone needs saving, like a
+    /// `ZoneCache`, goes by this.
+    pub fn changes(&self) -> uint { self.changes }
+    /// `data`, counted as changed.
+    pub fn data_mut<'a>(&'a mut self) -> &'a mut TZonePayload {
+        self.changes += 1;
+        &mut self.data
+    }
+    /// Bump the revision past `revision`, e.g. that of a zone this one is
+    /// replacing, so nothing cached from the other looks current.
+    pub fn touch_past(&mut self, revision: uint) {
+        self.revision = max(self.revision, revision) + 1;
+    }
+    /// Start or stop recording `WorldEvent`s for this zone. Usually left to
+    /// `World::record_events`.
+    pub fn record_events(&mut self, on: bool) { self.events.set_recording(on); }
+    /// Hold at most `limit` events, as `World::limit_events`.
+    pub fn limit_events(&mut self, limit: uint) { self.events.set_limit(limit); }
+    /// Hand over the events recorded since last asked, oldest first.
+    pub fn take_events(&mut self) -> Vec<WorldEvent> { self.events.take() }
+    ///////////////////////
     // Tile related
     ///////////////////////
+    /// How the zone's tiles are laid out, e.g. to see how many chunks a
+    /// chunked zone has allocated.
+    pub fn tiles<'a>(&'a self) -> &'a TileStorage<Tile<TTilePayload>> {
+        &self.all_tiles
+    }
     pub fn tile_at_idx<'a>(&'a self, idx: uint) -> &'a Tile<TTilePayload> {
-        &self.all_tiles[idx]
+        self.all_tiles.get(idx)
     }
     pub fn tile_at_idx_mut<'a>(&'a mut self, idx: uint) -> &'a mut Tile<TTilePayload> {
-        &mut self.all_tiles[idx]
+        self.tile_changed(idx);
+        self.all_tiles.get_mut(idx)
+    }
+    fn tile_changed(&mut self, idx: uint) {
+        self.touch();
+        if self.events.is_recording() {
+            let coords = (idx % self.size, idx / self.size);
+            self.events.push(TileChanged(GlobalCoord::new(self.id, coords)));
+        }
     }
     pub fn get_tile<'a>(&'a self, coords: (uint, uint)) -> &'a Tile<TTilePayload> {
         let idx = coords_to_idx(coords, self.size);
@@ -117,6 +291,67 @@ impl<TZonePayload, TTilePayload: Send + Payloadable> Zone<TZonePayload, TTilePay
         let idx = coords_to_idx(coords, self.size);
         self.tile_at_idx_mut(idx)
     }
+    /// Whether the tile at `coords` can be walked on: it has to be passable
+    /// in the zone's own tiles and in every layer that `affects_movement`.
+    pub fn passable_at(&self, coords: (uint, uint)) -> bool {
+        let idx = coords_to_idx(coords, self.size);
+        self.tile_at_idx(idx).passable && self.layers.iter().all(|l| {
+            !l.affects_movement || l.cell_at_idx(idx).passable
+        })
+    }
+    ///////////////////////
+    // layers
+    ///////////////////////
+    /// Add a layer on top of the others, laid out like the zone's tiles (so
+    /// a chunked zone's layers are chunked too). Layers are drawn bottom
+    /// first, over the tiles.
+    pub fn add_layer(&mut self, name: &str, affects_fov: bool, affects_movement: bool) {
+        if name == BASE_LAYER || self.layer_idx(name).is_some() {
+            panic!("add_layer: zone {} already has a layer named {}", self.id, name);
+        }
+        let cells = self.all_tiles.new_like(self.size);
+        self.layers.push(Layer::new(name, cells, affects_fov, affects_movement));
+        self.touch();
+    }
+    pub fn layers<'a>(&'a self) -> &'a [Layer<TTilePayload>] { self.layers.as_slice() }
+    /// Change whether a layer counts toward FOV and movement.
+    pub fn set_layer_rules(&mut self, name: &str, affects_fov: bool, affects_movement: bool) {
+        let i = self.expect_layer(name);
+        self.layers[i].affects_fov = affects_fov;
+        self.layers[i].affects_movement = affects_movement;
+        self.touch();
+    }
+    pub fn get_layer_cell<'a>(&'a self, name: &str, coords: (uint, uint))
+            -> &'a LayerCell<TTilePayload> {
+        let i = self.expect_layer(name);
+        self.layers[i].cell_at_idx(coords_to_idx(coords, self.size))
+    }
+    /// As `get_tile_mut`, for a layer's cell.
+    pub fn get_layer_cell_mut<'a>(&'a mut self, name: &str, coords: (uint, uint))
+            -> &'a mut LayerCell<TTilePayload> {
+        let i = self.expect_layer(name);
+        self.touch();
+        self.events.push(TileChanged(GlobalCoord::new(self.id, coords)));
+        let idx = coords_to_idx(coords, self.size);
+        self.layers[i].cell_at_idx_mut(idx)
+    }
+    /// The payloads stacked at `coords`, bottom first along with the name
+    /// of the layer each is from, for drawing a sprite per layer in order.
+    pub fn payloads_at<'a>(&'a self, coords: (uint, uint)) -> Vec<(&'a str, &'a TTilePayload)> {
+        let idx = coords_to_idx(coords, self.size);
+        let mut stack = vec!((BASE_LAYER, &self.tile_at_idx(idx).payload));
+        for l in self.layers.iter() {
+            stack.push((l.name.as_slice(), &l.cell_at_idx(idx).payload));
+        }
+        stack
+    }
+    fn layer_idx(&self, name: &str) -> Option<uint> {
+        self.layers.iter().position(|l| l.name.as_slice() == name)
+    }
+    fn expect_layer(&self, name: &str) -> uint {
+        self.layer_idx(name).expect(
+            format!("Zone {} has no layer named {}", self.id, name).as_slice())
+    }
     ///////////////////////
     // adding/moving entities
     ///////////////////////
@@ -134,5 +369,154 @@ impl<TZonePayload, TTilePayload: Send + Payloadable> Zone<TZonePayload, TTilePay
             t.portal_id = Some(pid);
         }
         self.portal_coords.insert(pid, coords);
+        self.changes += 1;
+        self.events.push(ZonePortalAdded(self.id, pid, coords));
+    }
+    pub fn add_payload(&mut self, plid: Uuid, coords: (uint, uint)) {
+        if !self.coords_in_bounds(coords) {
+            let (x, y) = coords;
+            panic!("add_payload: coords {},{} aren't in bounds!", x, y);
+        }
+        if self.payload_coords.find(&plid).is_some() {
+            panic!("add_payload: payload {} already added to zone {}!", plid, self.id);
+        }
+        self.payload_coords.insert(plid, coords);
+        self.spatial.insert(plid, coords);
+        self.changes += 1;
+        self.events.push(PayloadAdded(plid, GlobalCoord::new(self.id, coords)));
+    }
+    pub fn move_payload(&mut self, plid: &Uuid, coords: (uint, uint)) {
+        if !self.coords_in_bounds(coords) {
+            let (x, y) = coords;
+            panic!("move_payload: coords {},{} aren't in bounds!", x, y);
+        }
+        let from = *self.get_payload_coords(plid);
+        self.spatial.remove(plid, from);
+        self.spatial.insert(*plid, coords);
+        self.payload_coords.insert(*plid, coords);
+        self.changes += 1;
+        self.events.push(PayloadMoved(*plid, GlobalCoord::new(self.id, from),
+                                      GlobalCoord::new(self.id, coords)));
+    }
+    /// Take a payload out of the zone, e.g. as it leaves for another,
+    /// returning where it stood.
+    pub fn remove_payload(&mut self, plid: &Uuid) -> Option<(uint, uint)> {
+        let removed = self.payload_coords.find(plid).map(|c| *c);
+        match removed {
+            Some(coords) => {
+                self.payload_coords.remove(plid);
+                self.spatial.remove(plid, coords);
+                self.changes += 1;
+                self.events.push(PayloadRemoved(*plid, GlobalCoord::new(self.id, coords)));
+            },
+            None => {}
+        }
+        removed
+    }
+    /// Add a portal covering `width` tiles, starting at `origin` and running
+    /// along the edge that `exit` leaves through. Only the origin is kept in
+    /// `portal_coords`; the rest of the span is found via the `Portal`'s width.
+    pub fn add_portal_span(&mut self, pid: Uuid, origin: (uint, uint),
+                           exit: TraversalDirection, width: uint) {
+        self.check_portal_span(&pid, origin, exit, width);
+        let (ox, oy) = origin;
+        let (ax, ay) = if width > 1 { exit.span_axis() } else { (0, 0) };
+        self.add_portal(pid, origin);
+        for i in range(1, width) {
+            let t = self.get_tile_mut((ox + ax * i, oy + ay * i));
+            t.portal_id = Some(pid);
+        }
+    }
+    /// Panic if `add_portal_span` would, without changing anything; so a
+    /// portal's two sides can both be checked before either is added.
+    pub fn check_portal_span(&self, pid: &Uuid, origin: (uint, uint),
+                             exit: TraversalDirection, width: uint) {
+        if width == 0 {
+            panic!("add_portal_span: portal {} can't have a width of 0", pid);
+        }
+        let (ox, oy) = origin;
+        let (ax, ay) = if width > 1 { exit.span_axis() } else { (0, 0) };
+        let last = (ox + ax * (width - 1), oy + ay * (width - 1));
+        if !self.coords_in_bounds(last) {
+            let (x, y) = last;
+            panic!("add_portal_span: coords {},{} aren't in bounds!", x, y);
+        }
+        if self.portal_coords.find(pid).is_some() {
+            panic!("add_portal_span: portal {} already added to zone {}!", pid, self.id);
+        }
+        for i in range(0, width) {
+            let (x, y) = (ox + ax * i, oy + ay * i);
+            match self.get_tile((x, y)).portal_id {
+                Some(other) => panic!("add_portal_span: tile {},{} in zone {} already \
+                                       belongs to portal {}", x, y, self.id, other),
+                None => {}
+            }
+        }
+    }
+    /// Undo `add_portal_span`, clearing the portal from the tiles it covers.
+    /// Returns the origin it had, if the zone had the portal at all.
+    pub fn remove_portal_span(&mut self, pid: &Uuid, exit: TraversalDirection,
+                              width: uint) -> Option<(uint, uint)> {
+        let origin = match self.portal_coords.find(pid) {
+            Some(c) => *c,
+            None => return None
+        };
+        let (ox, oy) = origin;
+        let (ax, ay) = if width > 1 { exit.span_axis() } else { (0, 0) };
+        for i in range(0, width) {
+            let t = self.get_tile_mut((ox + ax * i, oy + ay * i));
+            if t.portal_id == Some(*pid) { t.portal_id = None; }
+        }
+        self.portal_coords.remove(pid);
+        self.changes += 1;
+        self.events.push(ZonePortalRemoved(self.id, *pid, origin));
+        Some(origin)
+    }
+}
+
+impl<TZonePayload, TTilePayload: Send + Payloadable + PartialEq>
+        Zone<TZonePayload, TTilePayload> {
+    /// Replace a whole tile. Unlike writing through `tile_at_idx_mut`,
+    /// writing a stub into a chunk that isn't allocated doesn't allocate it.
+    pub fn set_tile_at_idx(&mut self, idx: uint, tile: Tile<TTilePayload>) {
+        self.tile_changed(idx);
+        self.all_tiles.set(idx, tile);
+    }
+    pub fn set_tile(&mut self, coords: (uint, uint), tile: Tile<TTilePayload>) {
+        let idx = coords_to_idx(coords, self.size);
+        self.set_tile_at_idx(idx, tile)
+    }
+    /// As `set_tile`, for a layer's cell.
+    pub fn set_layer_cell(&mut self, name: &str, coords: (uint, uint),
+                          cell: LayerCell<TTilePayload>) {
+        let i = self.expect_layer(name);
+        self.touch();
+        self.events.push(TileChanged(GlobalCoord::new(self.id, coords)));
+        let idx = coords_to_idx(coords, self.size);
+        self.layers[i].set_cell_at_idx(idx, cell)
+    }
+    /// Free the chunks, of the tiles and of every layer, that have gone back
+    /// to being all stubs. Nothing the zone holds changes, so neither does
+    /// its revision.
+    pub fn compact(&mut self) {
+        self.all_tiles.compact();
+        for l in self.layers.iter_mut() {
+            l.compact();
+        }
+    }
+}
+
+impl<TZonePayload, TTilePayload: Send + Payloadable + FovItem> Zone<TZonePayload, TTilePayload> {
+    /// What the tile at `coords` lets the viewer see through, combining
+    /// the zone's own tile with every layer that `affects_fov`.
+    pub fn fov_at(&self, coords: (uint, uint)) -> FovType {
+        let idx = coords_to_idx(coords, self.size);
+        let mut fov = self.tile_at_idx(idx).payload.get_fov();
+        for l in self.layers.iter() {
+            if l.affects_fov {
+                fov = combine_fov(fov, l.cell_at_idx(idx).payload.get_fov());
+            }
+        }
+        fov
     }
 }
@@ -0,0 +1,198 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// entities run on `ecs` against a `World`: placing and stepping them,
+// keeping their positions in step with the zones, and what they can see
+
+extern crate libc;
+extern crate serialize;
+extern crate uuid;
+extern crate ecs;
+extern crate p2d;
+
+use std::rc::Rc;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use uuid::Uuid;
+use ecs::{Components, Entity};
+use ecs::system::EntitySystem;
+
+use p2d::world::{GlobalCoord, RelativeCoord};
+use p2d::world::TraversalDirection::*;
+use p2d::zone::ZoneTraversalResult::*;
+use p2d::entity::{Body, Position, Heading, LastStep, Sight, Visible, SharedWorld};
+use p2d::entity::{SyncProcess, MovementProcess, VisionProcess};
+use p2d::entity::{register_components, place, step};
+use p2d::fov;
+use p2d::fov::{Chebyshev, Blocking};
+
+use support::{TestWorld, Cell, portal_grid, set_cell, angle_buffers};
+
+mod support;
+
+type Shared = SharedWorld<(), (), Cell>;
+
+// an `ecs::World` running the sync, movement and vision systems against
+// `world`, and where the vision system leaves what it sees
+fn systems_for(world: &Shared) -> (ecs::World, Visible) {
+    let mut ecs = ecs::World::new();
+    register_components(&mut ecs);
+    let sync = SyncProcess::new(world.clone());
+    let aspect = sync.aspect();
+    ecs.register_system(box EntitySystem::new(sync, aspect));
+    let movement = MovementProcess::new(world.clone());
+    let aspect = movement.aspect();
+    ecs.register_system(box EntitySystem::new(movement, aspect));
+    let vision = VisionProcess::new(world.clone(), Chebyshev);
+    let (aspect, visible) = (vision.aspect(), vision.visible());
+    ecs.register_system(box EntitySystem::new(vision, aspect));
+    ecs.finalise();
+    (ecs, visible)
+}
+
+fn shared(world: TestWorld) -> Shared {
+    Rc::new(RefCell::new(world))
+}
+
+fn spawn(ecs: &mut ecs::World, world: &Shared, id: Uuid, at: GlobalCoord) -> Entity {
+    ecs.build_entity(|c: &mut Components, e: Entity| {
+        place(&mut *world.borrow_mut(), c, e, id, at.clone());
+    })
+}
+
+fn position_of(ecs: &mut ecs::World, entity: Entity) -> Option<Position> {
+    let mut found = None;
+    ecs.modify_entity(entity, |c: &mut Components, e: Entity| {
+        found = c.get::<Position>(&e);
+    });
+    found
+}
+
+fn last_step_of(ecs: &mut ecs::World, entity: Entity) -> Option<LastStep> {
+    let mut found = None;
+    ecs.modify_entity(entity, |c: &mut Components, e: Entity| {
+        found = c.get::<LastStep>(&e);
+    });
+    found
+}
+
+// the lower id first
+fn by_id(a: Uuid, b: Uuid) -> (Uuid, Uuid) {
+    if a.as_bytes() < b.as_bytes() { (a, b) } else { (b, a) }
+}
+
+#[test]
+fn placing_and_stepping_move_the_payload_too() {
+    let (grid, zones) = portal_grid(2, 12);
+    let world = shared(grid);
+    let (mut ecs, _) = systems_for(&world);
+    let id = Uuid::new_v4();
+    let walker = spawn(&mut ecs, &world, id, GlobalCoord::new(zones[0], (10, 5)));
+    assert!(world.borrow().get_zone(&zones[0]).find_payload(&id) == Some((10, 5)));
+
+    // onto the portal, then through it into the zone to the east
+    for expected in [GlobalCoord::new(zones[0], (11, 5)),
+                     GlobalCoord::new(zones[1], (1, 5))].iter() {
+        let mut result = None;
+        ecs.modify_entity(walker, |c: &mut Components, e: Entity| {
+            result = Some(step(&mut *world.borrow_mut(), c, e, East));
+        });
+        assert!(result == Some(Destination(expected.clone())), "stepped to {}", result);
+        assert!(position_of(&mut ecs, walker) == Some(Position(expected.clone())));
+        assert!(world.borrow().find_payload(&id) == Some(expected.clone()));
+    }
+    assert!(world.borrow().get_zone(&zones[0]).find_payload(&id).is_none());
+
+    // a wall stops it where it stands
+    set_cell(world.borrow_mut().get_zone_mut(&zones[1]), (2, 5), Blocking);
+    let mut result = None;
+    ecs.modify_entity(walker, |c: &mut Components, e: Entity| {
+        result = Some(step(&mut *world.borrow_mut(), c, e, East));
+    });
+    assert!(result == Some(DestinationBlocked));
+    assert!(position_of(&mut ecs, walker) == Some(Position(GlobalCoord::new(zones[1], (1, 5)))));
+}
+
+#[test]
+fn an_occupied_tile_goes_to_the_lower_id() {
+    let (grid, zones) = portal_grid(1, 12);
+    let world = shared(grid);
+    let (mut ecs, _) = systems_for(&world);
+    let (first, second) = by_id(Uuid::new_v4(), Uuid::new_v4());
+    // both make for (4, 3), from either side
+    let a = spawn(&mut ecs, &world, second, GlobalCoord::new(zones[0], (3, 3)));
+    let b = spawn(&mut ecs, &world, first, GlobalCoord::new(zones[0], (5, 3)));
+    ecs.modify_entity(a, |c: &mut Components, e: Entity| { c.add(&e, Heading(East)); });
+    ecs.modify_entity(b, |c: &mut Components, e: Entity| { c.add(&e, Heading(West)); });
+    ecs.update();
+
+    let goal = GlobalCoord::new(zones[0], (4, 3));
+    assert!(last_step_of(&mut ecs, b) == Some(LastStep(Destination(goal.clone()))));
+    assert!(last_step_of(&mut ecs, a) == Some(LastStep(DestinationOccupied(first))));
+    assert!(position_of(&mut ecs, b) == Some(Position(goal)));
+    assert!(position_of(&mut ecs, a) == Some(Position(GlobalCoord::new(zones[0], (3, 3)))));
+
+    // the heading's used up; another run leaves both where they are
+    ecs.update();
+    assert!(position_of(&mut ecs, a) == Some(Position(GlobalCoord::new(zones[0], (3, 3)))));
+}
+
+#[test]
+fn positions_catch_up_with_changes_made_to_the_world() {
+    let (grid, zones) = portal_grid(2, 12);
+    let world = shared(grid);
+    let (mut ecs, _) = systems_for(&world);
+    let id = Uuid::new_v4();
+    let entity = spawn(&mut ecs, &world, id, GlobalCoord::new(zones[0], (2, 2)));
+
+    world.borrow_mut().get_zone_mut(&zones[0]).move_payload(&id, (7, 8));
+    ecs.update();
+    assert!(position_of(&mut ecs, entity) == Some(Position(GlobalCoord::new(zones[0], (7, 8)))));
+
+    let elsewhere = GlobalCoord::new(zones[3], (4, 4));
+    world.borrow_mut().move_payload(&id, &zones[0], elsewhere.clone());
+    ecs.update();
+    assert!(position_of(&mut ecs, entity) == Some(Position(elsewhere)));
+
+    world.borrow_mut().remove_zone(&zones[3]);
+    ecs.update();
+    assert!(position_of(&mut ecs, entity).is_none());
+}
+
+#[test]
+fn vision_sees_what_compute_does() {
+    let (mut grid, zones) = portal_grid(2, 12);
+    for &c in [(6u, 4u), (9, 7), (10, 2)].iter() {
+        set_cell(grid.get_zone_mut(&zones[0]), c, Blocking);
+    }
+    let world = shared(grid);
+    let (mut ecs, visible) = systems_for(&world);
+    let id = Uuid::new_v4();
+    let entity = spawn(&mut ecs, &world, id, GlobalCoord::new(zones[0], (8, 5)));
+    ecs.modify_entity(entity, |c: &mut Components, e: Entity| { c.add(&e, Sight(6)); });
+
+    for &(zid, coords) in [(zones[0], (8u, 5u)), (zones[1], (3, 9))].iter() {
+        {
+            let mut w = world.borrow_mut();
+            let from = w.find_payload(&id).unwrap().zone_id;
+            w.move_payload(&id, &from, GlobalCoord::new(zid, coords));
+        }
+        ecs.update();
+        let (mut start_ang, mut end_ang) = angle_buffers(6);
+        let expected: HashSet<RelativeCoord> = fov::compute_with_metric(
+            &*world.borrow(), RelativeCoord::new(zid, coords, (0, 0)), 6, Chebyshev,
+            start_ang.as_mut_slice(), end_ang.as_mut_slice()).into_iter().collect();
+        let seen: HashSet<RelativeCoord> =
+            visible.borrow().find(&id).expect("nothing seen").iter().map(|c| c.clone()).collect();
+        assert!(seen == expected, "from {} in {}", coords, zid);
+    }
+
+    // without sight, it's dropped from the results
+    ecs.modify_entity(entity, |c: &mut Components, e: Entity| { c.remove::<Sight>(&e); });
+    ecs.update();
+    assert!(visible.borrow().find(&id).is_none());
+}
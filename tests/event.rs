@@ -0,0 +1,120 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// what subscribers are handed by `drain_events`, in what order, and what
+// happens when they don't keep up
+
+extern crate libc;
+extern crate serialize;
+extern crate uuid;
+extern crate p2d;
+
+use uuid::Uuid;
+
+use p2d::world::{World, GlobalCoord};
+use p2d::event::WorldEvent;
+use p2d::event::WorldEvent::*;
+use p2d::fov::Blocking;
+
+use support::{TestWorld, set_cell};
+
+mod support;
+
+fn recording_world() -> (TestWorld, Uuid, Uuid) {
+    let mut world = World::new(());
+    let a = world.new_zone(8, (), |_| {});
+    let b = world.new_zone(8, (), |_| {});
+    world.record_events(true);
+    (world, a, b)
+}
+
+#[test]
+fn subscribers_only_see_what_happens_after_they_subscribe() {
+    let (mut world, a, _) = recording_world();
+    let early = world.subscribe();
+    let c = world.new_zone(8, (), |_| {});
+    let late = world.subscribe();
+    let plid = Uuid::new_v4();
+    world.get_zone_mut(&a).add_payload(plid, (1, 1));
+
+    let added = PayloadAdded(plid, GlobalCoord::new(a, (1, 1)));
+    assert!(world.drain_events(early) == vec![ZoneAdded(c), added.clone()]);
+    assert!(world.drain_events(late) == vec![added]);
+}
+
+#[test]
+fn each_subscriber_drains_its_own_queue() {
+    let (mut world, a, _) = recording_world();
+    let first = world.subscribe();
+    let second = world.subscribe();
+    set_cell(world.get_zone_mut(&a), (2, 3), Blocking);
+
+    let changed = vec![TileChanged(GlobalCoord::new(a, (2, 3)))];
+    assert!(world.drain_events(first) == changed);
+    assert!(world.drain_events(first).is_empty());
+    assert!(world.drain_events(second) == changed);
+
+    assert!(world.unsubscribe(second));
+    assert!(!world.unsubscribe(second));
+    set_cell(world.get_zone_mut(&a), (2, 4), Blocking);
+    assert!(world.drain_events(first) == vec![TileChanged(GlobalCoord::new(a, (2, 4)))]);
+}
+
+#[test]
+fn events_come_in_the_order_they_happened() {
+    let (mut world, a, b) = recording_world();
+    let sub = world.subscribe();
+    let plid = Uuid::new_v4();
+    {
+        let zone = world.get_zone_mut(&a);
+        zone.add_payload(plid, (1, 1));
+        zone.move_payload(&plid, (2, 1));
+    }
+    set_cell(world.get_zone_mut(&b), (4, 4), Blocking);
+    let c = world.new_zone(8, (), |_| {});
+    // moved between zones, so recorded as one move after all of the above
+    world.move_payload(&plid, &a, GlobalCoord::new(b, (3, 3)));
+
+    let events = world.drain_events(sub);
+    assert!(events.len() == 5, "{}", events);
+    // the world's own come first, then each zone's in turn
+    assert!(events[0] == ZoneAdded(c));
+    let in_a: Vec<WorldEvent> = events.iter()
+        .filter(|e| e.zone_id() == Some(a)).map(|e| e.clone()).collect();
+    assert!(in_a == vec![PayloadAdded(plid, GlobalCoord::new(a, (1, 1))),
+                         PayloadMoved(plid, GlobalCoord::new(a, (1, 1)),
+                                      GlobalCoord::new(a, (2, 1)))]);
+    assert!(events.contains(&TileChanged(GlobalCoord::new(b, (4, 4)))));
+    assert!(events[4] == PayloadMoved(plid, GlobalCoord::new(a, (2, 1)),
+                                      GlobalCoord::new(b, (3, 3))));
+}
+
+#[test]
+fn past_the_limit_the_oldest_are_dropped_and_counted() {
+    let (mut world, a, _) = recording_world();
+    world.limit_events(3);
+    let quick = world.subscribe();
+    let slow = world.subscribe();
+    let plids: Vec<Uuid> = range(0u, 5).map(|_| Uuid::new_v4()).collect();
+    let added = |i: uint| PayloadAdded(plids[i], GlobalCoord::new(a, (i, 0)));
+
+    // more than the zone holds before anyone drains
+    for i in range(0u, 5) { world.get_zone_mut(&a).add_payload(plids[i], (i, 0)); }
+    assert!(world.drain_events(quick) == vec![EventsDropped(2), added(2), added(3), added(4)]);
+    assert!(world.drain_events(quick).is_empty());
+
+    // and more than a subscriber holds, for one that doesn't keep up: two
+    // more are dropped from its queue, on top of the two it was told of
+    world.get_zone_mut(&a).remove_payload(&plids[0]);
+    world.drain_events(quick);
+    world.get_zone_mut(&a).remove_payload(&plids[1]);
+    let slow_events = world.drain_events(slow);
+    assert!(slow_events.len() == 4, "{}", slow_events);
+    assert!(slow_events[0] == EventsDropped(4));
+    assert!(slow_events[1] == added(4));
+    assert!(slow_events[3] == PayloadRemoved(plids[1], GlobalCoord::new(a, (1, 0))));
+}
@@ -0,0 +1,135 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// flow fields brought up to date by `refresh`, checked against ones built
+// from scratch
+
+extern crate libc;
+extern crate serialize;
+extern crate uuid;
+extern crate p2d;
+
+use uuid::Uuid;
+
+use p2d::world::GlobalCoord;
+use p2d::world::TraversalDirection::NoDirection;
+use p2d::zone::ZoneTraversalResult::Destination;
+use p2d::path;
+use p2d::flow::FlowField;
+use p2d::fov::{Blocking, Translucent, Transparent};
+
+use support::{TestWorld, Walker, Rng, set_cell, portal_grid};
+
+mod support;
+
+static SEEDS: uint = 10;
+static SIZE: uint = 8;
+
+fn every_tile(world: &TestWorld) -> Vec<GlobalCoord> {
+    let mut tiles = Vec::new();
+    for (zid, zone) in world.zones.iter() {
+        for y in range(0, zone.size) {
+            for x in range(0, zone.size) {
+                tiles.push(GlobalCoord::new(*zid, (x, y)));
+            }
+        }
+    }
+    tiles
+}
+
+// Scatter walls and brush over a zone, and clear some of what was there.
+fn scribble(world: &mut TestWorld, zid: &Uuid, rng: &mut Rng) {
+    let zone = world.get_zone_mut(zid);
+    for _ in range(0u, SIZE * 2) {
+        let c = (rng.below(SIZE), rng.below(SIZE));
+        let fov = match rng.below(3) {
+            0 => Blocking,
+            1 => Translucent(0.5),
+            _ => Transparent
+        };
+        set_cell(zone, c, fov);
+    }
+}
+
+// `field` should agree with one built from scratch on what every tile
+// costs, and following it should always lead somewhere that costs that
+// much less.
+fn assert_up_to_date(world: &TestWorld, field: &FlowField<Walker>, label: &str) {
+    let fresh = FlowField::new(world, field.target.clone(), Walker);
+    for gc in every_tile(world).iter() {
+        match (field.cost(gc), fresh.cost(gc)) {
+            (Some(a), Some(b)) => assert!((a - b).abs() < 1e-9,
+                                          "{}: {} costs {}, rebuilt {}", label, gc, a, b),
+            (None, None) => continue,
+            (a, b) => panic!("{}: {} costs {}, rebuilt {}", label, gc, a, b)
+        }
+        let cost = field.cost(gc).unwrap();
+        match field.direction(gc).unwrap() {
+            NoDirection => assert!(*gc == field.target, "{}: {} goes nowhere", label, gc),
+            dir => match world.try_traversal_as(gc.clone(), dir, &Walker) {
+                Destination(next) => {
+                    let rest = field.cost(&next).expect("flow leads off the field");
+                    let step = path::step_cost(world, &next, &Walker);
+                    assert!((rest + step - cost).abs() < 1e-9,
+                            "{}: {} leads {} to {}, which doesn't add up", label, gc, dir, next);
+                },
+                other => panic!("{}: {} leads {}, to {}", label, gc, dir, other)
+            }
+        }
+    }
+}
+
+#[test]
+fn refreshing_after_edits_matches_a_rebuild() {
+    for seed in range(1, SEEDS as u64) {
+        let mut rng = Rng::new(seed);
+        let (mut world, zones) = portal_grid(3, SIZE);
+        for zid in zones.iter() { scribble(&mut world, zid, &mut rng); }
+        let target = GlobalCoord::new(zones[4], (rng.below(SIZE), rng.below(SIZE)));
+        set_cell(world.get_zone_mut(&zones[4]), target.coords, Transparent);
+        let mut field = FlowField::new(&world, target, Walker);
+        assert!(!field.refresh(&world));
+
+        for round in range(0u, 6) {
+            // usually a zone away from the target's, often one the field
+            // only reaches through others
+            let zid = zones[rng.below(zones.len())];
+            scribble(&mut world, &zid, &mut rng);
+            if zid == zones[4] {
+                set_cell(world.get_zone_mut(&zid), field.target.coords, Transparent);
+            }
+            field.refresh(&world);
+            assert_up_to_date(&world, &field,
+                              format!("seed {}, round {}", seed, round).as_slice());
+        }
+    }
+}
+
+#[test]
+fn refreshing_drops_routes_closed_by_an_edit_and_finds_reopened_ones() {
+    let (mut world, zones) = portal_grid(2, SIZE);
+    // wall the target's zone off from the one to its left, so the way there
+    // is round through the two below
+    for y in range(0, SIZE) { set_cell(world.get_zone_mut(&zones[1]), (1, y), Blocking); }
+    let target = GlobalCoord::new(zones[1], (SIZE - 2, 3));
+    let mut field = FlowField::new(&world, target, Walker);
+    let from = GlobalCoord::new(zones[0], (SIZE - 2, 3));
+    let around = field.cost(&from).unwrap();
+
+    // then cut that way off, in the zone below `from`
+    for y in range(0, SIZE) { set_cell(world.get_zone_mut(&zones[2]), (SIZE - 2, y), Blocking); }
+    assert!(field.refresh(&world));
+    assert_up_to_date(&world, &field, "cut off");
+    assert!(field.cost(&from).is_none());
+
+    for y in range(0, SIZE) {
+        set_cell(world.get_zone_mut(&zones[2]), (SIZE - 2, y), Transparent);
+    }
+    assert!(field.refresh(&world));
+    assert_up_to_date(&world, &field, "reopened");
+    assert!(field.cost(&from) == Some(around));
+}
@@ -0,0 +1,699 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// properties every FOV result should have, checked over generated worlds,
+// and a brute-force ray caster to compare `fov::compute` against
+
+extern crate libc;
+extern crate serialize;
+extern crate uuid;
+extern crate p2d;
+
+use std::f64::consts::PI;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use p2d::world::{World, GlobalCoord, RelativeCoord};
+use p2d::world::TraversalDirection::*;
+use p2d::zone::Zone;
+use p2d::zone::ZoneTraversalResult::Destination;
+use p2d::fov;
+use p2d::fov::{Cone, FovCache, RadiusMetric, Chebyshev, Euclidean, Manhattan, Blocking,
+               Translucent, Transparent};
+use p2d::fov::FovAlgorithm::Mrpas;
+
+use support::alloc;
+use support::{TestWorld, Rng, open_field, scattered, maze, portal_grid, mirrored, viewer,
+              angle_buffers, set_cell};
+
+mod support;
+
+static SEEDS: uint = 20;
+
+fn compute(world: &TestWorld, focus: RelativeCoord, radius: uint,
+           metric: RadiusMetric) -> HashSet<RelativeCoord> {
+    let (mut start_ang, mut end_ang) = angle_buffers(radius);
+    fov::compute_with_metric(world, focus, radius, metric,
+                             start_ang.as_mut_slice(), end_ang.as_mut_slice())
+        .into_iter().collect()
+}
+
+// a random open tile to stand on
+fn random_open(world: &TestWorld, zid: Uuid, rng: &mut Rng) -> (uint, uint) {
+    let zone = world.get_zone(&zid);
+    loop {
+        let c = (rng.below(zone.size), rng.below(zone.size));
+        if zone.get_tile(c).payload.fov == Transparent { return c; }
+    }
+}
+
+#[test]
+fn focus_is_always_visible() {
+    for seed in range(1, SEEDS as u64) {
+        let mut rng = Rng::new(seed);
+        let (world, zid) = scattered(32, 4, seed);
+        let c = random_open(&world, zid, &mut rng);
+        let seen = compute(&world, viewer(zid, c), rng.below(12), Chebyshev);
+        assert!(seen.contains(&viewer(zid, c)), "seed {}: focus {} not seen", seed, c);
+    }
+}
+
+#[test]
+fn results_are_within_radius() {
+    for seed in range(1, SEEDS as u64) {
+        let mut rng = Rng::new(seed);
+        let (world, zid) = scattered(48, 6, seed);
+        let c = random_open(&world, zid, &mut rng);
+        let radius = 1 + rng.below(16);
+        for metric in [Chebyshev, Euclidean, Manhattan].iter() {
+            for t in compute(&world, viewer(zid, c), radius, *metric).iter() {
+                assert!(metric.within(t.gx, t.gy, radius),
+                        "seed {}: {} is outside {} radius {}", seed, t, metric, radius);
+            }
+        }
+    }
+}
+
+#[test]
+fn results_are_within_radius_across_portals() {
+    let (world, zones) = portal_grid(3, 12);
+    for radius in range(1u, 20) {
+        for t in compute(&world, viewer(zones[4], (6, 6)), radius, Euclidean).iter() {
+            assert!(Euclidean.within(t.gx, t.gy, radius),
+                    "{} is outside radius {}", t, radius);
+        }
+    }
+}
+
+#[test]
+fn nothing_is_seen_past_a_wall_in_a_corridor() {
+    let mut world: TestWorld = World::new(());
+    let zid = world.new_zone(32, (), |z| {
+        for y in range(0, 32u) {
+            for x in range(0, 32u) {
+                let open = y == 16 && x > 0 && x < 31 && x != 20;
+                set_cell(z, (x, y), if open { Transparent } else { Blocking });
+            }
+        }
+    });
+    for x in range(1u, 20) {
+        let seen = compute(&world, viewer(zid, (x, 16)), 30, Chebyshev);
+        assert!(seen.iter().any(|t| t.lx == 20 && t.ly == 16),
+                "from {}: the wall itself should be seen", x);
+        for t in seen.iter() {
+            assert!(!(t.ly == 16 && t.lx > 20), "from {}: saw {} past the wall", x, t);
+        }
+    }
+}
+
+#[test]
+fn mirrored_maps_give_mirrored_results() {
+    for seed in range(1, SEEDS as u64) {
+        let mut rng = Rng::new(seed);
+        let (world, zid) = if seed % 2 == 0 { scattered(32, 5, seed) } else { maze(33, seed) };
+        let size = world.get_zone(&zid).size;
+        let (x, y) = random_open(&world, zid, &mut rng);
+        let radius = 4 + rng.below(12);
+        let seen = compute(&world, viewer(zid, (x, y)), radius, Chebyshev);
+        for &(flip_x, flip_y) in [(true, false), (false, true), (true, true)].iter() {
+            let (mirror, mid) = mirrored(&world, zid, flip_x, flip_y);
+            let mx = if flip_x { size - 1 - x } else { x };
+            let my = if flip_y { size - 1 - y } else { y };
+            let expected: HashSet<RelativeCoord> = seen.iter().map(|t| {
+                let lx = if flip_x { size - 1 - t.lx } else { t.lx };
+                let ly = if flip_y { size - 1 - t.ly } else { t.ly };
+                let gx = if flip_x { -t.gx } else { t.gx };
+                let gy = if flip_y { -t.gy } else { t.gy };
+                RelativeCoord::new(mid, (lx, ly), (gx, gy))
+            }).collect();
+            let actual = compute(&mirror, viewer(mid, (mx, my)), radius, Chebyshev);
+            assert!(actual == expected,
+                    "seed {}, flipped {},{}: {} tiles seen, expected {}",
+                    seed, flip_x, flip_y, actual.len(), expected.len());
+        }
+    }
+}
+
+#[test]
+fn seeing_through_a_portal_matches_walking_through_it() {
+    // from the middle of the middle zone, walk straight out in each
+    // direction; every tile stepped onto should be seen where the walk puts it
+    let (world, zones) = portal_grid(3, 12);
+    let radius = 14;
+    let focus = viewer(zones[4], (6, 6));
+    let seen = compute(&world, focus.clone(), radius, Chebyshev);
+    for dir in [North, East, South, West].iter() {
+        let (dx, dy) = dir.delta();
+        let mut curr = GlobalCoord::new(zones[4], (6, 6));
+        for step in range(1, radius as int + 1) {
+            curr = match world.try_traversal(curr.clone(), *dir) {
+                Destination(gc) => gc,
+                _ => break
+            };
+            let g = (dx * step, dy * step);
+            // a portal's tiles are seen as those on its far side, which
+            // overlap them
+            let expected = match world.get_zone(&curr.zone_id).get_tile(curr.coords).portal_id {
+                Some(pid) => {
+                    let (ozid, oc, _) = world.portal_destination(curr.zone_id, pid, curr.coords);
+                    RelativeCoord::new(ozid, oc, g)
+                },
+                None => RelativeCoord::new(curr.zone_id, curr.coords, g)
+            };
+            assert!(seen.contains(&expected),
+                    "walking {} for {} steps reached {}, which wasn't seen", dir, step, expected);
+        }
+    }
+}
+
+// Ground truth for a single zone: a tile is visible if a straight line
+// from the viewer's center to its center, or to a point just inside any of
+// its corners, crosses no Blocking tile along the way.
+fn ray_cast(world: &TestWorld, zid: Uuid, focus: (uint, uint), radius: uint)
+        -> HashSet<(uint, uint)> {
+    let zone = world.get_zone(&zid);
+    let (fx, fy) = focus;
+    let mut seen = HashSet::new();
+    for y in range(0, zone.size) {
+        for x in range(0, zone.size) {
+            let (dx, dy) = (x as int - fx as int, y as int - fy as int);
+            if !Chebyshev.within(dx, dy, radius) { continue; }
+            let targets = [(0.5, 0.5), (0.1, 0.1), (0.9, 0.1), (0.1, 0.9), (0.9, 0.9)];
+            let clear = targets.iter().any(|&(ox, oy)| {
+                let (sx, sy) = (fx as f64 + 0.5, fy as f64 + 0.5);
+                let (ex, ey) = (x as f64 + ox, y as f64 + oy);
+                let steps = 64 * (radius + 1);
+                range(0, steps).all(|i| {
+                    let f = i as f64 / steps as f64;
+                    let (cx, cy) = ((sx + (ex - sx) * f) as uint, (sy + (ey - sy) * f) as uint);
+                    (cx, cy) == (x, y) || (cx, cy) == focus ||
+                        zone.get_tile((cx, cy)).payload.fov != Blocking
+                })
+            });
+            if clear { seen.insert((x, y)); }
+        }
+    }
+    seen
+}
+
+#[test]
+fn open_ground_matches_ray_casting_exactly() {
+    let (world, zid) = open_field(32);
+    for radius in range(0u, 12) {
+        let seen: HashSet<(uint, uint)> = compute(&world, viewer(zid, (16, 16)), radius, Chebyshev)
+            .iter().map(|t| (t.lx, t.ly)).collect();
+        assert!(seen == ray_cast(&world, zid, (16, 16), radius), "radius {}", radius);
+    }
+}
+
+#[test]
+fn a_pillar_casts_exactly_its_shadow() {
+    // worked through by hand: the pillar hides the tiles straight behind
+    // it, and by the edge of the radius its shadow has spread a tile to
+    // either side. MRPAS lets the view through at (18, 15) and (18, 17),
+    // which a ray caster would count as hidden.
+    let (mut world, zid) = open_field(32);
+    set_cell(world.get_zone_mut(&zid), (17, 16), Blocking);
+    let seen: HashSet<(uint, uint)> = compute(&world, viewer(zid, (16, 16)), 3, Chebyshev)
+        .iter().map(|t| (t.lx, t.ly)).collect();
+    let shadow = [(18u, 16u), (19, 16), (19, 15), (19, 17)];
+    let mut expected = HashSet::new();
+    for y in range(13u, 20) {
+        for x in range(13u, 20) {
+            if !shadow.iter().any(|&c| c == (x, y)) { expected.insert((x, y)); }
+        }
+    }
+    let mut missing: Vec<&(uint, uint)> = expected.difference(&seen).collect();
+    let mut extra: Vec<&(uint, uint)> = seen.difference(&expected).collect();
+    missing.sort();
+    extra.sort();
+    assert!(seen == expected, "missing {}, extra {}", missing, extra);
+}
+
+#[test]
+fn symmetric_fov_is_symmetric() {
+    for seed in range(1, 8u64) {
+        let mut rng = Rng::new(seed);
+        let (world, zid) = scattered(16, 4, seed);
+        let radius = 6;
+        let viewers: Vec<(uint, uint)> = range(0u, 12).map(|_| random_open(&world, zid, &mut rng))
+            .collect();
+        for a in viewers.iter() {
+            let from_a: HashSet<(uint, uint)> =
+                fov::compute_symmetric(&world, viewer(zid, *a), radius, Chebyshev)
+                    .iter().map(|t| (t.lx, t.ly)).collect();
+            for b in viewers.iter() {
+                let from_b: HashSet<(uint, uint)> =
+                    fov::compute_symmetric(&world, viewer(zid, *b), radius, Chebyshev)
+                        .iter().map(|t| (t.lx, t.ly)).collect();
+                assert!(from_a.contains(b) == from_b.contains(a),
+                        "seed {}: {} and {} disagree", seed, a, b);
+            }
+        }
+    }
+}
+
+#[test]
+fn symmetric_observers_of_matches_each_observers_view() {
+    for seed in range(1, 8u64) {
+        let mut rng = Rng::new(seed);
+        let (world, zid) = scattered(16, 4, seed);
+        let observers: Vec<(RelativeCoord, uint)> = range(0u, 12)
+            .map(|_| (viewer(zid, random_open(&world, zid, &mut rng)), 2 + rng.below(6)))
+            .collect();
+        let target = GlobalCoord::new(zid, random_open(&world, zid, &mut rng));
+        let expected: Vec<uint> = observers.iter().enumerate().filter(|&(_, &(ref o, r))| {
+            fov::compute_symmetric(&world, o.clone(), r, Chebyshev).iter()
+                .any(|t| t.zone_id == zid && (t.lx, t.ly) == target.coords)
+        }).map(|(i, _)| i).collect();
+        assert_eq!(fov::symmetric_observers_of(&world, &target, observers.as_slice(),
+                                               Chebyshev),
+                   expected);
+    }
+}
+
+// two 8x8 zones side by side, with pillars scattered over both, joined by a
+// portal down most of their shared edge; nothing but the portal tiles is on
+// the edges (x = 7 in the first zone, x = 0 in the second)
+fn portal_pair(seed: u64) -> (TestWorld, Uuid, Uuid) {
+    let mut rng = Rng::new(seed);
+    let mut world: TestWorld = World::new(());
+    let mut zids = Vec::new();
+    for _ in range(0u, 2) {
+        zids.push(world.new_zone(8, (), |z| {
+            for y in range(0, 8u) {
+                for x in range(0, 8u) {
+                    let pillar = x > 0 && x < 7 && rng.one_in(6);
+                    set_cell(z, (x, y), if pillar { Blocking } else { Transparent });
+                }
+            }
+        }));
+    }
+    world.new_portal_span((zids[0], (7, 1), East), (zids[1], (0, 1), West), 6);
+    (world, zids[0], zids[1])
+}
+
+#[test]
+fn symmetric_fov_is_symmetric_across_a_portal() {
+    for seed in range(1, 8u64) {
+        let mut rng = Rng::new(seed);
+        let (world, a, b) = portal_pair(seed);
+        let radius = 6;
+        // open tiles off the edges, so none of them shares a spot with
+        // a tile on the other side
+        let mut tiles = Vec::new();
+        while tiles.len() < 12 {
+            let zid = if tiles.len() % 2 == 0 { a } else { b };
+            let (x, y) = random_open(&world, zid, &mut rng);
+            if x > 0 && x < 7 { tiles.push(GlobalCoord::new(zid, (x, y))); }
+        }
+        let views: Vec<HashSet<GlobalCoord>> = tiles.iter().map(|gc| {
+            fov::compute_symmetric(&world, viewer(gc.zone_id, gc.coords), radius, Chebyshev)
+                .iter().map(|t| GlobalCoord::new(t.zone_id, (t.lx, t.ly))).collect()
+        }).collect();
+        for (i, from_a) in views.iter().enumerate() {
+            for (j, from_b) in views.iter().enumerate() {
+                assert!(from_a.contains(&tiles[j]) == from_b.contains(&tiles[i]),
+                        "seed {}: {} and {} disagree", seed, tiles[i], tiles[j]);
+            }
+        }
+        // and observers_of agrees with each of those views
+        let observers: Vec<(RelativeCoord, uint)> =
+            tiles.iter().map(|gc| (viewer(gc.zone_id, gc.coords), radius)).collect();
+        for (j, target) in tiles.iter().enumerate() {
+            let expected: Vec<uint> = range(0, tiles.len())
+                .filter(|&i| views[i].contains(target)).collect();
+            assert!(fov::symmetric_observers_of(&world, target, observers.as_slice(), Chebyshev)
+                        == expected,
+                    "seed {}: observers of {} (#{}) disagree", seed, target, j);
+        }
+    }
+}
+
+#[test]
+fn observers_of_matches_each_observers_compute_across_a_portal() {
+    for seed in range(1, 8u64) {
+        let mut rng = Rng::new(seed);
+        let (world, a, b) = portal_pair(seed);
+        let mut observers: Vec<(RelativeCoord, uint)> = Vec::new();
+        for i in range(0u, 12) {
+            let zid = if i % 2 == 0 { a } else { b };
+            // some share a tile, with different radii
+            let c = if i % 4 == 3 {
+                let (ref o, _) = observers[i - 2];
+                (o.lx, o.ly)
+            } else { random_open(&world, zid, &mut rng) };
+            observers.push((viewer(zid, c), 2 + rng.below(6)));
+        }
+        let (mut start_ang, mut end_ang) = angle_buffers(8);
+        for _ in range(0u, 10) {
+            // portal tiles included
+            let zid = if rng.one_in(2) { a } else { b };
+            let target = GlobalCoord::new(zid, random_open(&world, zid, &mut rng));
+            let expected: Vec<uint> = observers.iter().enumerate()
+                .filter(|&(_, &(ref o, r))| {
+                    compute(&world, o.clone(), r, Chebyshev).iter()
+                        .any(|t| t.zone_id == target.zone_id && (t.lx, t.ly) == target.coords)
+                })
+                .map(|(i, _)| i).collect();
+            let found = fov::observers_of(&world, &target, observers.as_slice(), Chebyshev,
+                                          start_ang.as_mut_slice(), end_ang.as_mut_slice());
+            assert!(found == expected, "seed {}: observers of {}: {} against {}",
+                    seed, target, found, expected);
+        }
+    }
+}
+
+#[test]
+fn symmetric_fov_looks_through_portals() {
+    // with nothing in the way, the portal is as good as open ground: the
+    // column of the first zone's portal is one spot with the second zone's
+    let mut world: TestWorld = World::new(());
+    let a = world.new_zone(8, (), |z| fill_open(z));
+    let b = world.new_zone(8, (), |z| fill_open(z));
+    world.new_portal_span((a, (7, 1), East), (b, (0, 1), West), 6);
+    let seen: HashSet<RelativeCoord> =
+        fov::compute_symmetric(&world, viewer(a, (3, 3)), 6, Chebyshev).into_iter().collect();
+    for x in range(1u, 3) {
+        assert!(seen.contains(&RelativeCoord::new(b, (x, 3), (x as int + 4, 0))),
+                "{},3 on the far side wasn't seen", x);
+    }
+    assert!(!seen.contains(&RelativeCoord::new(b, (3, 3), (7, 0))), "saw past the radius");
+}
+
+fn fill_open(z: &mut Zone<(), support::Cell>) {
+    for y in range(0, 8u) {
+        for x in range(0, 8u) {
+            set_cell(z, (x, y), Transparent);
+        }
+    }
+}
+
+#[test]
+fn compute_into_matches_compute() {
+    let mut scratch = fov::FovScratch::new();
+    let mut out = Vec::new();
+    for seed in range(1, SEEDS as u64) {
+        let mut rng = Rng::new(seed);
+        let (world, zid) = scattered(32, 5, seed);
+        let c = random_open(&world, zid, &mut rng);
+        let radius = rng.below(16);
+        fov::compute_into(&world, &viewer(zid, c), radius, Chebyshev, &mut scratch, &mut out);
+        let reused: HashSet<RelativeCoord> = out.iter().map(|t| t.clone()).collect();
+        assert!(reused == compute(&world, viewer(zid, c), radius, Chebyshev), "seed {}", seed);
+    }
+}
+
+#[test]
+fn compute_into_stops_allocating_once_warmed_up() {
+    for seed in range(1, 4u64) {
+        let mut rng = Rng::new(seed);
+        let (world, zones) = portal_grid(3, 12);
+        let c = random_open(&world, zones[4], &mut rng);
+        let mut scratch = fov::FovScratch::new();
+        let mut out = Vec::new();
+        let bytes = alloc::per_call(|| {
+            fov::compute_into(&world, &viewer(zones[4], c), 14, Chebyshev, &mut scratch, &mut out)
+        });
+        assert!(bytes == 0, "seed {}: {} bytes allocated", seed, bytes);
+    }
+}
+
+// what `compute_many` should give: each viewer's `compute`, merged
+fn each_alone(world: &TestWorld, viewers: &[(RelativeCoord, uint)])
+        -> HashMap<GlobalCoord, Vec<uint>> {
+    let mut expected: HashMap<GlobalCoord, Vec<uint>> = HashMap::new();
+    for (i, &(ref v, r)) in viewers.iter().enumerate() {
+        for t in compute(world, v.clone(), r, Chebyshev).iter() {
+            let gc = GlobalCoord::new(t.zone_id, (t.lx, t.ly));
+            let found = match expected.find_mut(&gc) {
+                Some(seen_by) => { seen_by.push(i); true },
+                None => false
+            };
+            if !found { expected.insert(gc, vec!(i)); }
+        }
+    }
+    expected
+}
+
+#[test]
+fn compute_many_matches_each_viewer_alone() {
+    for seed in range(1, 8u64) {
+        let mut rng = Rng::new(seed);
+        let (world, zid) = scattered(32, 5, seed);
+        // some viewers share a tile, with different radii
+        let mut viewers: Vec<(RelativeCoord, uint)> = Vec::new();
+        for i in range(0u, 10) {
+            let c = if i % 3 == 2 {
+                let (ref v, _) = viewers[i - 1];
+                (v.lx, v.ly)
+            } else { random_open(&world, zid, &mut rng) };
+            viewers.push((viewer(zid, c), 2 + rng.below(10)));
+        }
+        let (mut start_ang, mut end_ang) = angle_buffers(12);
+        let merged = fov::compute_many(&world, viewers.as_slice(), Chebyshev,
+                                       start_ang.as_mut_slice(), end_ang.as_mut_slice());
+        assert!(merged == each_alone(&world, viewers.as_slice()), "seed {}", seed);
+    }
+}
+
+#[test]
+fn compute_many_matches_each_viewer_alone_across_portals_and_layers() {
+    for seed in range(1, 8u64) {
+        let mut rng = Rng::new(seed);
+        let (mut world, zones) = portal_grid(2, 16);
+        // walls in the zones themselves, and smoke in a layer over them, so
+        // the cells scans share are the combined ones
+        for zid in zones.iter() {
+            let zone = world.get_zone_mut(zid);
+            zone.add_layer("smoke", true, false);
+            for _ in range(0u, 12) {
+                set_cell(zone, (1 + rng.below(14), 1 + rng.below(14)), Blocking);
+                zone.get_layer_cell_mut("smoke", (1 + rng.below(14), 1 + rng.below(14)))
+                    .payload = support::Cell { fov: Blocking };
+            }
+        }
+        let mut viewers: Vec<(RelativeCoord, uint)> = Vec::new();
+        for _ in range(0u, 12) {
+            let zid = zones[rng.below(zones.len())];
+            viewers.push((viewer(zid, random_open(&world, zid, &mut rng)), 2 + rng.below(10)));
+        }
+        let (mut start_ang, mut end_ang) = angle_buffers(12);
+        let merged = fov::compute_many(&world, viewers.as_slice(), Chebyshev,
+                                       start_ang.as_mut_slice(), end_ang.as_mut_slice());
+        assert!(merged == each_alone(&world, viewers.as_slice()), "seed {}", seed);
+    }
+}
+
+// An open zone with two curtains of smoke running top to bottom, each
+// letting half the light through, and what's seen from the left of both.
+fn behind_curtains(threshold: f64) -> (TestWorld, Uuid, HashMap<(uint, uint), f64>) {
+    let (mut world, zid) = open_field(16);
+    {
+        let zone = world.get_zone_mut(&zid);
+        for y in range(0, 16u) {
+            set_cell(zone, (5, y), Translucent(0.5));
+            set_cell(zone, (8, y), Translucent(0.5));
+        }
+    }
+    let (mut start_ang, mut end_ang) = angle_buffers(12);
+    let lit = fov::compute_lit(&world, viewer(zid, (2, 8)), 12, Chebyshev, threshold,
+                               start_ang.as_mut_slice(), end_ang.as_mut_slice())
+        .into_iter().map(|(t, light)| ((t.lx, t.ly), light)).collect();
+    (world, zid, lit)
+}
+
+#[test]
+fn light_halves_through_each_curtain_it_passes() {
+    let (world, zid, lit) = behind_curtains(1.0);
+    // dimmed, but nothing hidden
+    let seen: HashSet<(uint, uint)> = compute(&world, viewer(zid, (2, 8)), 12, Chebyshev)
+        .into_iter().map(|t| (t.lx, t.ly)).collect();
+    assert!(lit.keys().map(|c| *c).collect::<HashSet<(uint, uint)>>() == seen);
+    // a curtain is seen in the light that reaches it, and dims what's past
+    for x in range(3, 15u) {
+        let expected = if x <= 5 { 1.0 } else if x <= 8 { 0.5 } else { 0.25 };
+        assert!(lit.find(&(x, 8)) == Some(&expected), "{} lit {}", (x, 8), lit.find(&(x, 8)));
+    }
+    // and no way round them is any brighter
+    for (&(x, y), &light) in lit.iter() {
+        let most = if x <= 5 { 1.0 } else if x <= 8 { 0.5 } else { 0.25 };
+        assert!(light <= most, "{} lit {}", (x, y), light);
+    }
+}
+
+#[test]
+fn light_dimmed_past_the_threshold_is_cut_off() {
+    // past the first curtain the view is half hidden, which is still under
+    // the threshold; past the second it's three quarters, which isn't
+    let (_, _, lit) = behind_curtains(0.7);
+    assert!(lit.find(&(7, 8)) == Some(&0.5));
+    // the second curtain is seen, as a wall would be, but hides what's past
+    assert!(lit.find(&(8, 8)) == Some(&0.5));
+    for &(x, y) in lit.keys() {
+        assert!(x <= 8, "{} was seen through both curtains", (x, y));
+    }
+    // but a little higher and it shows through again
+    let (_, _, lit) = behind_curtains(0.8);
+    assert!(lit.find(&(9, 8)) == Some(&0.25));
+}
+
+// how far round from `facing` the tile at (gx, gy) from the viewer lies, in
+// 0..PI
+fn off_facing(gx: int, gy: int, facing: f64) -> f64 {
+    let mut d = ((gy as f64).atan2(gx as f64) - facing) % (2.0 * PI);
+    if d < 0.0 { d += 2.0 * PI; }
+    if d > PI { 2.0 * PI - d } else { d }
+}
+
+// `compute_cone` should see exactly what `compute` does within the cone's
+// angles, and nothing outside them.
+fn check_cone(world: &TestWorld, focus: RelativeCoord, radius: uint, cone: Cone, label: &str) {
+    let (mut start_ang, mut end_ang) = angle_buffers(radius);
+    let coned: HashSet<RelativeCoord> = fov::compute_cone(
+        world, focus.clone(), radius, Chebyshev, cone,
+        start_ang.as_mut_slice(), end_ang.as_mut_slice()).into_iter().collect();
+    let seen = compute(world, focus.clone(), radius, Chebyshev);
+    assert!(coned.contains(&focus), "{}: the viewer isn't in its own cone", label);
+    for t in coned.iter() {
+        assert!(seen.contains(t), "{}: {} is in the cone, but can't be seen", label, t);
+        if (t.gx, t.gy) != (0, 0) {
+            assert!(off_facing(t.gx, t.gy, cone.facing) <= cone.width / 2.0 + 1e-9,
+                    "{}: {} is outside {}", label, t, cone);
+        }
+    }
+    for t in seen.iter() {
+        if off_facing(t.gx, t.gy, cone.facing) < cone.width / 2.0 - 1e-9 {
+            assert!(coned.contains(t), "{}: {} is inside {}, but wasn't seen", label, t, cone);
+        }
+    }
+    if cone.width >= 2.0 * PI {
+        assert!(coned == seen, "{}: a full circle sees less than compute", label);
+    }
+}
+
+#[test]
+fn cones_see_what_compute_does_within_their_angles() {
+    for seed in range(1, SEEDS as u64) {
+        let mut rng = Rng::new(seed);
+        let (world, zid) = scattered(32, 5, seed);
+        let c = random_open(&world, zid, &mut rng);
+        let radius = rng.below(16);
+        // facings all the way round, including either side of west where
+        // angles wrap, and widths up to a full circle
+        let facing = rng.below(64) as f64 * PI / 32.0 - PI;
+        let width = (1 + rng.below(16)) as f64 * PI / 8.0;
+        check_cone(&world, viewer(zid, c), radius, Cone::new(facing, width),
+                   format!("seed {}", seed).as_slice());
+    }
+}
+
+#[test]
+fn cones_look_through_portals() {
+    let (world, zones) = portal_grid(3, 12);
+    let focus = viewer(zones[4], (6, 6));
+    for &facing in [-PI / 2.0, 0.0, PI / 2.0, PI, -3.0 * PI / 4.0].iter() {
+        let cone = Cone::new(facing, PI / 2.0);
+        check_cone(&world, focus.clone(), 14, cone, format!("facing {}", facing).as_slice());
+    }
+    // facing north, into the zone that way and none of the others
+    let (mut start_ang, mut end_ang) = angle_buffers(14);
+    let north: HashSet<Uuid> = fov::compute_cone(
+        &world, focus, 14, Chebyshev, Cone::new(-PI / 2.0, PI / 2.0),
+        start_ang.as_mut_slice(), end_ang.as_mut_slice())
+        .into_iter().map(|t| t.zone_id).collect();
+    assert!(north.contains(&zones[1]));
+    for &other in [3u, 5, 7].iter() {
+        assert!(!north.contains(&zones[other]), "zone {} was seen facing north", other);
+    }
+}
+
+// Ask `cache` for a view, and say whether it had to be worked out again:
+// a result that's reused allocates nothing.
+fn redone(cache: &mut FovCache, world: &TestWorld, focus: &RelativeCoord, radius: uint)
+          -> (bool, HashSet<RelativeCoord>) {
+    let (mut start_ang, mut end_ang) = angle_buffers(radius);
+    let (_, bytes) = alloc::allocated_by(|| {
+        cache.compute(world, focus.clone(), radius, Mrpas(Chebyshev),
+                      start_ang.as_mut_slice(), end_ang.as_mut_slice()).len()
+    });
+    let seen = cache.compute(world, focus.clone(), radius, Mrpas(Chebyshev),
+                             start_ang.as_mut_slice(), end_ang.as_mut_slice())
+        .iter().map(|t| t.clone()).collect();
+    (bytes > 0, seen)
+}
+
+#[test]
+fn cached_views_are_redone_after_edits_to_any_zone_they_touch() {
+    let (mut world, zones) = portal_grid(3, 8);
+    let mut cache = FovCache::new();
+    // close enough to the portal north that only its tiles are in range,
+    // and further out, where what's past it is too
+    for &(focus, radius) in [((4u, 2u), 2u), ((4, 4), 5)].iter() {
+        let focus = viewer(zones[4], focus);
+        let seen = compute(&world, focus.clone(), radius, Chebyshev);
+        let mut touched = HashSet::new();
+        for t in seen.iter() {
+            touched.insert(t.zone_id);
+            match world.get_zone(&t.zone_id).get_tile((t.lx, t.ly)).portal_id {
+                Some(pid) => {
+                    let (past, _) = world.get_portal(pid).info_from(t.zone_id);
+                    touched.insert(past);
+                },
+                None => {}
+            }
+        }
+        assert!(touched.contains(&zones[1]) && !touched.contains(&zones[0]),
+                "radius {}: touched {}", radius, touched);
+
+        for zid in zones.iter() {
+            redone(&mut cache, &world, &focus, radius);
+            let (again, _) = redone(&mut cache, &world, &focus, radius);
+            assert!(!again, "radius {}: an unchanged view was redone", radius);
+            // wall up somewhere it saw, if it saw anything here
+            let at = seen.iter()
+                .find(|t| t.zone_id == *zid && (t.lx, t.ly) != (focus.lx, focus.ly))
+                .map(|t| (t.lx, t.ly)).unwrap_or((0, 0));
+            set_cell(world.get_zone_mut(zid), at, Blocking);
+            let (again, after) = redone(&mut cache, &world, &focus, radius);
+            assert!(again == touched.contains(zid),
+                    "radius {}: an edit at {} redone {}", radius, at, again);
+            assert!(after == compute(&world, focus.clone(), radius, Chebyshev));
+            set_cell(world.get_zone_mut(zid), at, Transparent);
+        }
+    }
+}
+
+#[cfg(feature = "parallel")]
+#[test]
+fn compute_parallel_matches_compute() {
+    use std::sync::{Arc, TaskPool};
+    let pool = TaskPool::new(4);
+    for seed in range(1, SEEDS as u64) {
+        let mut rng = Rng::new(seed);
+        // pillars scattered over every zone of the grid, so passes through
+        // portals have shadows of their own to work out
+        let (mut world, zones) = portal_grid(3, 12);
+        for zid in zones.iter() {
+            let zone = world.get_zone_mut(zid);
+            for _ in range(0u, 8) {
+                let c = (1 + rng.below(10), 1 + rng.below(10));
+                set_cell(zone, c, Blocking);
+            }
+        }
+        let c = random_open(&world, zones[4], &mut rng);
+        let radius = 4 + rng.below(12);
+        let metric = [Chebyshev, Euclidean, Manhattan][rng.below(3)];
+        let expected = compute(&world, viewer(zones[4], c), radius, metric);
+        let world = Arc::new(world);
+        let actual: HashSet<RelativeCoord> =
+            fov::compute_parallel(world.clone(), viewer(zones[4], c), radius, metric, &pool)
+                .into_iter().collect();
+        assert!(actual == expected, "seed {}: {} tiles seen, expected {}",
+                seed, actual.len(), expected.len());
+    }
+}
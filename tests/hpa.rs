@@ -0,0 +1,149 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// routes planned over the portal graph, checked against a flat search of
+// every tile in the world
+
+extern crate libc;
+extern crate serialize;
+extern crate uuid;
+extern crate p2d;
+
+use p2d::world::GlobalCoord;
+use p2d::path;
+use p2d::path::DijkstraMap;
+use p2d::hpa::HierarchicalPlanner;
+use p2d::fov::{Blocking, Translucent, Transparent};
+
+use support::{TestWorld, Walker, Rng, set_cell, portal_grid};
+
+mod support;
+
+static SEEDS: uint = 12;
+static SIZE: uint = 8;
+
+// a grid of zones with walls and brush scattered through each of them
+fn rough_grid(seed: u64) -> (TestWorld, Vec<GlobalCoord>) {
+    let (mut world, zones) = portal_grid(3, SIZE);
+    let mut rng = Rng::new(seed);
+    let mut open = Vec::new();
+    for zid in zones.iter() {
+        let zone = world.get_zone_mut(zid);
+        for y in range(0, SIZE) {
+            for x in range(0, SIZE) {
+                if rng.one_in(5) {
+                    set_cell(zone, (x, y), Blocking);
+                } else {
+                    if rng.one_in(4) { set_cell(zone, (x, y), Translucent(0.5)); }
+                    open.push(GlobalCoord::new(*zid, (x, y)));
+                }
+            }
+        }
+    }
+    (world, open)
+}
+
+// The cost of walking `route` from `from`, checking each step along the way
+// is one a mover could actually take.
+fn walk(world: &TestWorld, from: &GlobalCoord, route: &Vec<GlobalCoord>) -> f64 {
+    let mut at = from.clone();
+    let mut cost = 0.0;
+    for next in route.iter() {
+        let step = path::neighbors(world, &at, &Walker).into_iter()
+            .find(|&(_, ref dest, _)| dest == next);
+        match step {
+            Some((_, _, c)) => cost += c,
+            None => panic!("{} isn't a step away from {}", next, at)
+        }
+        at = next.clone();
+    }
+    cost
+}
+
+// Plan from `from` to `to`, and check the route against a flat search.
+fn check_plan(planner: &mut HierarchicalPlanner<Walker>, world: &TestWorld,
+              from: &GlobalCoord, to: &GlobalCoord, label: &str) {
+    let flat = DijkstraMap::new(world, [to.clone()].as_slice(), &Walker).get(from);
+    match (planner.plan(world, from, to), flat) {
+        (Some(route), Some(expected)) => {
+            if from != to {
+                assert!(route.last() == Some(to), "{}: route doesn't end at {}", label, to);
+            }
+            let cost = walk(world, from, &route);
+            assert!((cost - expected).abs() < 1e-9,
+                    "{}: {} to {} costs {}, a flat search found {}",
+                    label, from, to, cost, expected);
+        },
+        (None, None) => {},
+        (planned, _) => panic!("{}: {} to {} planned {}, a flat search found {}",
+                               label, from, to, planned.is_some(), flat)
+    }
+}
+
+#[test]
+fn plans_cost_what_a_flat_search_finds() {
+    for seed in range(1, SEEDS as u64) {
+        let (world, open) = rough_grid(seed);
+        let mut planner = HierarchicalPlanner::new(Walker);
+        let mut rng = Rng::new(seed * 31);
+        for _ in range(0u, 20) {
+            let from = &open[rng.below(open.len())];
+            let to = &open[rng.below(open.len())];
+            check_plan(&mut planner, &world, from, to, format!("seed {}", seed).as_slice());
+        }
+    }
+}
+
+#[test]
+fn wide_portals_are_crossed_at_any_open_tile() {
+    let (mut world, zones) = portal_grid(2, SIZE);
+    // wall off the first tile of the portal from zone 0 east into zone 1,
+    // and where stepping through it lands
+    set_cell(world.get_zone_mut(&zones[0]), (SIZE - 1, 1), Blocking);
+    set_cell(world.get_zone_mut(&zones[1]), (1, 1), Blocking);
+    let mut planner = HierarchicalPlanner::new(Walker);
+    let from = GlobalCoord::new(zones[0], (SIZE - 2, 3));
+    let to = GlobalCoord::new(zones[1], (2, 3));
+    check_plan(&mut planner, &world, &from, &to, "wide portal");
+    // straight across the rest of the span, not around through the other
+    // zones
+    assert!(planner.plan(&world, &from, &to).unwrap().iter().all(|gc| {
+        gc.zone_id == zones[0] || gc.zone_id == zones[1]
+    }));
+}
+
+#[test]
+fn plans_follow_edits_to_zones_already_crossed() {
+    let (mut world, zones) = portal_grid(2, SIZE);
+    let mut planner = HierarchicalPlanner::new(Walker);
+    let from = GlobalCoord::new(zones[0], (1, 1));
+    let to = GlobalCoord::new(zones[3], (SIZE - 2, SIZE - 2));
+    check_plan(&mut planner, &world, &from, &to, "before");
+
+    // wall off where zone 1 is entered from zone 0; editing its tiles moves
+    // its revision on, which is all the planner needs to notice
+    {
+        let zone = world.get_zone_mut(&zones[1]);
+        for y in range(0, SIZE) { set_cell(zone, (1, y), Blocking); }
+    }
+    check_plan(&mut planner, &world, &from, &to, "after walling off zone 1");
+
+    // and likewise zone 2, leaving nothing to cross
+    {
+        let zone = world.get_zone_mut(&zones[2]);
+        for x in range(0, SIZE) { set_cell(zone, (x, 1), Blocking); }
+    }
+    check_plan(&mut planner, &world, &from, &to, "after walling off zone 2");
+    assert!(planner.plan(&world, &from, &to).is_none());
+
+    {
+        let zone = world.get_zone_mut(&zones[2]);
+        for x in range(0, SIZE) { set_cell(zone, (x, 1), Transparent); }
+    }
+    check_plan(&mut planner, &world, &from, &to, "reopened");
+    assert!(planner.plan(&world, &from, &to).is_some());
+}
@@ -0,0 +1,314 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// movement costs and the searches built on them, checked against plain
+// relaxation over `path::neighbors` and against small hand-built maps
+
+extern crate libc;
+extern crate serialize;
+extern crate uuid;
+extern crate p2d;
+
+use std::f64;
+use std::collections::{HashMap, BinaryHeap, RingBuf};
+use uuid::Uuid;
+
+use p2d::world::GlobalCoord;
+use p2d::world::TraversalDirection::*;
+use p2d::zone::ZoneTraversalResult::{Destination, DestinationBlocked};
+use p2d::path;
+use p2d::path::{DijkstraMap, MovementCost, ZoneSearch, Frontier};
+use p2d::fov::{Blocking, Translucent, Void};
+
+use support::{TestWorld, Cell, Walker, Rng, set_cell, open_field, portal_grid};
+
+mod support;
+
+static SEEDS: uint = 8;
+static SIZE: uint = 8;
+
+// gets over brush as easily as anything else, but still not through walls
+struct Flyer;
+impl MovementCost<Flyer> for Cell {
+    fn movement_cost(&self, _: &Flyer) -> f64 {
+        match self.fov {
+            Blocking | Void => f64::INFINITY,
+            _ => 1.0
+        }
+    }
+}
+
+// a grid of zones with walls and brush scattered through each of them
+fn rough_grid(seed: u64) -> TestWorld {
+    let (mut world, zones) = portal_grid(2, SIZE);
+    let mut rng = Rng::new(seed);
+    for zid in zones.iter() {
+        let zone = world.get_zone_mut(zid);
+        for y in range(0, SIZE) {
+            for x in range(0, SIZE) {
+                if rng.one_in(6) {
+                    set_cell(zone, (x, y), Blocking);
+                } else if rng.one_in(4) {
+                    set_cell(zone, (x, y), Translucent(0.5));
+                }
+            }
+        }
+    }
+    world
+}
+
+fn every_tile(world: &TestWorld) -> Vec<GlobalCoord> {
+    let mut tiles = Vec::new();
+    for (zid, zone) in world.zones.iter() {
+        for y in range(0, zone.size) {
+            for x in range(0, zone.size) {
+                tiles.push(GlobalCoord::new(*zid, (x, y)));
+            }
+        }
+    }
+    tiles
+}
+
+// The cost from each tile that can be stood on to the nearest goal, found
+// by stepping forwards from every tile over and over until nothing gets
+// any cheaper.
+fn costs_to(world: &TestWorld, goals: &[GlobalCoord]) -> HashMap<GlobalCoord, f64> {
+    let tiles = every_tile(world);
+    let mut costs = HashMap::new();
+    for g in goals.iter() { costs.insert(g.clone(), 0.0); }
+    loop {
+        let mut changed = false;
+        for t in tiles.iter() {
+            if path::step_cost(world, t, &Walker) == f64::INFINITY { continue; }
+            for (_, n, step) in path::neighbors(world, t, &Walker).into_iter() {
+                let via = match costs.find(&n) {
+                    Some(c) => *c + step,
+                    None => continue
+                };
+                let better = match costs.find(t) {
+                    Some(c) => via < *c - 1e-9,
+                    None => true
+                };
+                if better {
+                    costs.insert(t.clone(), via);
+                    changed = true;
+                }
+            }
+        }
+        if !changed { break; }
+    }
+    costs
+}
+
+fn assert_same_costs(world: &TestWorld, map: &DijkstraMap,
+                     expected: &HashMap<GlobalCoord, f64>, label: &str) {
+    for t in every_tile(world).iter() {
+        match (map.get(t), expected.find(t)) {
+            (Some(a), Some(b)) => assert!((a - *b).abs() < 1e-9,
+                                          "{}: {} is {} away, not {}", label, t, a, b),
+            (None, None) => {},
+            (a, b) => panic!("{}: {} has {} against {}", label, t, a, b)
+        }
+    }
+}
+
+#[test]
+fn dijkstra_maps_reach_across_portals() {
+    let (world, zones) = portal_grid(2, SIZE);
+    let goal = GlobalCoord::new(zones[1], (2, 3));
+    let map = DijkstraMap::new(&world, [goal.clone()].as_slice(), &Walker);
+    // onto the portal tile, through it, and one more step in
+    assert!(map.get(&GlobalCoord::new(zones[0], (SIZE - 3, 3))) == Some(4.0));
+    // and up from the zone below it, through the portal between them
+    assert!(map.get(&GlobalCoord::new(zones[3], (2, 3))) == Some(7.0));
+}
+
+#[test]
+fn dijkstra_maps_match_relaxing_every_tile() {
+    for seed in range(1, SEEDS as u64) {
+        let world = rough_grid(seed);
+        let tiles = every_tile(&world);
+        let mut rng = Rng::new(seed * 7);
+        let goals: Vec<GlobalCoord> = range(0u, 1 + rng.below(3))
+            .map(|_| tiles[rng.below(tiles.len())].clone())
+            .collect();
+        let map = DijkstraMap::new(&world, goals.as_slice(), &Walker);
+        assert_same_costs(&world, &map, &costs_to(&world, goals.as_slice()),
+                          format!("seed {}", seed).as_slice());
+    }
+}
+
+#[test]
+fn combined_maps_are_weighted_sums_of_tiles_in_all() {
+    let world = rough_grid(3);
+    let tiles = every_tile(&world);
+    let a = DijkstraMap::new(&world, [tiles[9].clone()].as_slice(), &Walker);
+    let b = DijkstraMap::new(&world, [tiles[200].clone()].as_slice(), &Walker);
+    let combined = DijkstraMap::combine([(&a, 1.0), (&b, -0.5)].as_slice());
+    for t in tiles.iter() {
+        match (a.get(t), b.get(t)) {
+            (Some(x), Some(y)) => assert!(combined.get(t) == Some(x - 0.5 * y), "{}", t),
+            _ => assert!(combined.get(t).is_none(), "{} isn't in both maps", t)
+        }
+    }
+}
+
+#[test]
+fn each_mover_pays_its_own_costs() {
+    let (mut world, zid) = open_field(SIZE);
+    {
+        let zone = world.get_zone_mut(&zid);
+        for y in range(0, SIZE) { set_cell(zone, (4, y), Translucent(0.5)); }
+        set_cell(zone, (1, 0), Blocking);
+    }
+    let goal = [GlobalCoord::new(zid, (SIZE - 1, 1))];
+    let from = GlobalCoord::new(zid, (0, 1));
+    // seven steps, one of them through brush
+    assert!(DijkstraMap::new(&world, goal.as_slice(), &Walker).get(&from) == Some(9.0));
+    assert!(DijkstraMap::new(&world, goal.as_slice(), &Flyer).get(&from) == Some(7.0));
+
+    let wall = GlobalCoord::new(zid, (0, 0));
+    assert!(world.try_traversal_as(wall.clone(), East, &Walker) == DestinationBlocked);
+    assert!(world.try_traversal_as(from.clone(), East, &Walker) ==
+            Destination(GlobalCoord::new(zid, (1, 1))));
+}
+
+// a corridor along the top of an otherwise walled-in zone
+fn corridor() -> (TestWorld, Uuid) {
+    let (mut world, zid) = open_field(SIZE);
+    {
+        let zone = world.get_zone_mut(&zid);
+        for y in range(1, SIZE) {
+            for x in range(0, SIZE) { set_cell(zone, (x, y), Blocking); }
+        }
+    }
+    (world, zid)
+}
+
+// where rolling downhill from `from` comes to rest
+fn roll(world: &TestWorld, map: &DijkstraMap, from: GlobalCoord) -> GlobalCoord {
+    let mut at = from;
+    loop {
+        match map.roll_downhill(world, &at, &Walker) {
+            Some((_, next)) => at = next,
+            None => return at
+        }
+    }
+}
+
+#[test]
+fn rolling_downhill_reaches_the_goal_and_fleeing_runs_from_it() {
+    let (world, zid) = corridor();
+    let chaser = GlobalCoord::new(zid, (2, 0));
+    let towards = DijkstraMap::new(&world, [chaser.clone()].as_slice(), &Walker);
+    assert!(towards.get(&GlobalCoord::new(zid, (0, 1))).is_none());
+    assert!(roll(&world, &towards, GlobalCoord::new(zid, (SIZE - 1, 0))) == chaser);
+    assert!(towards.roll_downhill(&world, &chaser, &Walker).is_none());
+
+    let away = towards.flee(&world, 1.2, &Walker);
+    // from beside the chaser, run for the long end of the corridor rather
+    // than the short one
+    match away.roll_downhill(&world, &GlobalCoord::new(zid, (3, 0)), &Walker) {
+        Some((dir, _)) => assert!(dir == East, "fled {}", dir),
+        None => panic!("fleeing stood still")
+    }
+    assert!(roll(&world, &away, chaser.clone()) == GlobalCoord::new(zid, (SIZE - 1, 0)));
+    // the rescan smooths the map: no tile is more than a step above its
+    // lowest neighbor
+    for x in range(0, SIZE) {
+        let gc = GlobalCoord::new(zid, (x, 0));
+        let here = away.get(&gc).unwrap();
+        let lowest = path::neighbors(&world, &gc, &Walker).iter()
+            .filter_map(|&(_, ref n, _)| away.get(n))
+            .fold(f64::INFINITY, |a, b| a.min(b));
+        assert!(here <= lowest + 1.0 + 1e-9, "{} at {} against {}", here, x, lowest);
+    }
+}
+
+// Steps from `from` to every tile of its zone it can reach, by a plain
+// breadth-first search over the zone's grid.
+fn steps_within(world: &TestWorld, from: &GlobalCoord) -> HashMap<(uint, uint), uint> {
+    let zone = world.get_zone(&from.zone_id);
+    let open = |x: int, y: int| {
+        x >= 0 && y >= 0 && zone.coords_in_bounds((x as uint, y as uint)) &&
+            zone.get_tile((x as uint, y as uint)).payload.movement_cost(&Walker) < f64::INFINITY
+    };
+    let mut steps = HashMap::new();
+    let mut queue = RingBuf::new();
+    steps.insert(from.coords, 0u);
+    queue.push_back(from.coords);
+    loop {
+        let (x, y) = match queue.pop_front() {
+            Some(c) => c,
+            None => break
+        };
+        let here = *steps.find(&(x, y)).unwrap();
+        for &(dx, dy) in [(0i, -1i), (1, 0), (0, 1), (-1, 0)].iter() {
+            let (nx, ny) = (x as int + dx, y as int + dy);
+            if !open(nx, ny) { continue; }
+            let n = (nx as uint, ny as uint);
+            if steps.contains_key(&n) { continue; }
+            steps.insert(n, here + 1);
+            queue.push_back(n);
+        }
+    }
+    steps
+}
+
+#[test]
+fn zone_searches_find_the_shortest_routes_within_a_zone() {
+    for seed in range(1, SEEDS as u64) {
+        // walls only, so every step costs the same
+        let (mut world, zones) = portal_grid(2, SIZE);
+        let mut rng = Rng::new(seed);
+        for zid in zones.iter() {
+            let zone = world.get_zone_mut(zid);
+            for _ in range(0u, SIZE * 2) {
+                set_cell(zone, (rng.below(SIZE), rng.below(SIZE)), Blocking);
+            }
+        }
+        for _ in range(0u, 10) {
+            let from = GlobalCoord::new(zones[rng.below(zones.len())],
+                                        (rng.below(SIZE), rng.below(SIZE)));
+            if path::step_cost(&world, &from, &Walker) == f64::INFINITY { continue; }
+            let search = ZoneSearch::new(&world, &from, &Walker);
+            let expected = steps_within(&world, &from);
+            for y in range(0, SIZE) {
+                for x in range(0, SIZE) {
+                    let found = search.cost_to((x, y));
+                    let bfs = expected.find(&(x, y)).map(|s| *s as f64);
+                    assert!(found == bfs, "seed {}: {} to {} costs {}, not {}",
+                            seed, from, (x, y), found, bfs);
+                    match search.route_to((x, y)) {
+                        Some(route) => {
+                            assert!(Some(route.len() as f64) == bfs);
+                            assert!(route.iter().all(|gc| gc.zone_id == from.zone_id));
+                        },
+                        None => assert!(bfs.is_none())
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn frontiers_come_off_the_heap_cheapest_first() {
+    let gc = GlobalCoord::new(Uuid::new_v4(), (0, 0));
+    let mut heap = BinaryHeap::new();
+    for &cost in [3.0, 0.5, 7.0, 2.0, 0.5].iter() {
+        heap.push(Frontier { cost: cost, gc: gc.clone() });
+    }
+    let mut popped = Vec::new();
+    loop {
+        match heap.pop() {
+            Some(f) => popped.push(f.cost),
+            None => break
+        }
+    }
+    assert!(popped == vec!(0.5, 0.5, 2.0, 3.0, 7.0), "popped {}", popped);
+}
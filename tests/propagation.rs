@@ -0,0 +1,94 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// spreading through open ground, through portals, and through whatever's
+// layered over the tiles
+
+extern crate libc;
+extern crate serialize;
+extern crate uuid;
+extern crate p2d;
+
+use std::f64;
+
+use p2d::world::GlobalCoord;
+use p2d::layer::LayerCell;
+use p2d::propagation::{Attenuation, propagate};
+use p2d::fov::{Blocking, Translucent, Transparent, Void};
+
+use support::{Cell, set_cell, open_field, portal_grid};
+
+mod support;
+
+static SIZE: uint = 8;
+
+struct Sound;
+impl Attenuation<Sound> for Cell {
+    fn attenuation(&self, _: &Sound) -> f64 {
+        match self.fov {
+            Blocking => f64::INFINITY,
+            Transparent => 1.0,
+            Translucent(_) => 3.0,
+            // what empty layer cells hold
+            Void => 0.0
+        }
+    }
+}
+
+fn brush() -> LayerCell<Cell> {
+    LayerCell { passable: true, payload: Cell { fov: Translucent(0.5) } }
+}
+
+#[test]
+fn sound_fades_a_step_at_a_time_in_the_open() {
+    let (world, zid) = open_field(SIZE);
+    let heard = propagate(&world, &GlobalCoord::new(zid, (3, 3)), 4.0, &Sound);
+    for y in range(0, SIZE) {
+        for x in range(0, SIZE) {
+            let steps = (x as int - 3).abs() + (y as int - 3).abs();
+            let expected = if steps < 4 { Some(4.0 - steps as f64) } else { None };
+            let found = heard.find(&GlobalCoord::new(zid, (x, y))).map(|h| *h);
+            assert!(found == expected, "{} at {}, not {}", found, (x, y), expected);
+        }
+    }
+}
+
+#[test]
+fn sound_carries_through_portals() {
+    let (world, zones) = portal_grid(2, SIZE);
+    let heard = propagate(&world, &GlobalCoord::new(zones[0], (SIZE - 3, 3)), 10.0, &Sound);
+    // onto the portal tile, through it, and one more step in
+    assert!(heard.find(&GlobalCoord::new(zones[1], (2, 3))) == Some(&6.0));
+}
+
+#[test]
+fn layers_over_a_tile_take_their_share_too() {
+    let (mut world, zid) = open_field(SIZE);
+    {
+        let zone = world.get_zone_mut(&zid);
+        // brush that neither hides nor blocks anything, but still muffles
+        zone.add_layer("brush", false, false);
+        for y in range(0, SIZE) {
+            *zone.get_layer_cell_mut("brush", (2, y)) = brush();
+        }
+        // and a wall that's only in a layer, with a gap at the bottom
+        zone.add_layer("walls", true, true);
+        for y in range(0, SIZE - 1) {
+            *zone.get_layer_cell_mut("walls", (5, y)) =
+                LayerCell { passable: false, payload: Cell { fov: Blocking } };
+        }
+    }
+    let heard = propagate(&world, &GlobalCoord::new(zid, (0, 3)), 20.0, &Sound);
+    // the tile's own cost, and the brush's on top
+    assert!(heard.find(&GlobalCoord::new(zid, (2, 3))) == Some(&(20.0 - 1.0 - 4.0)));
+    assert!(heard.find(&GlobalCoord::new(zid, (4, 3))) == Some(&(20.0 - 1.0 - 4.0 - 2.0)));
+    assert!(heard.find(&GlobalCoord::new(zid, (5, 3))).is_none());
+    // past the wall, only by way of the gap: fourteen steps, one of them
+    // into the brush
+    let around = 14.0 + 3.0;
+    assert!(heard.find(&GlobalCoord::new(zid, (6, 3))) == Some(&(20.0 - around)));
+}
@@ -0,0 +1,142 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// the spatial index against looking at every entity, and `payloads_near`
+// across portals
+
+extern crate libc;
+extern crate serialize;
+extern crate uuid;
+extern crate p2d;
+
+use std::collections::HashSet;
+use uuid::Uuid;
+
+use p2d::world::RelativeCoord;
+use p2d::spatial::{SpatialIndex, payloads_near};
+use p2d::fov::{RadiusMetric, Chebyshev, Euclidean, Manhattan};
+
+use support::{Rng, portal_grid, viewer};
+
+mod support;
+
+static SEEDS: uint = 10;
+static SIZE: uint = 40;
+
+// an index over `count` entities scattered about a zone, along with a plain
+// list of them to check it against
+fn scattered_entities(rng: &mut Rng, count: uint) -> (SpatialIndex, Vec<(Uuid, (uint, uint))>) {
+    let mut index = SpatialIndex::new(SIZE);
+    let mut all = Vec::new();
+    for _ in range(0, count) {
+        let (id, coords) = (Uuid::new_v4(), (rng.below(SIZE), rng.below(SIZE)));
+        index.insert(id, coords);
+        all.push((id, coords));
+    }
+    (index, all)
+}
+
+fn ids(found: Vec<(Uuid, (uint, uint))>) -> HashSet<Uuid> {
+    found.into_iter().map(|(id, _)| id).collect()
+}
+
+fn distance(from: (uint, uint), to: (uint, uint), metric: RadiusMetric) -> f64 {
+    let ((fx, fy), (tx, ty)) = (from, to);
+    metric.distance(tx as int - fx as int, ty as int - fy as int)
+}
+
+#[test]
+fn in_rect_and_within_find_what_a_scan_does() {
+    for seed in range(1, SEEDS as u64) {
+        let mut rng = Rng::new(seed);
+        let (index, all) = scattered_entities(&mut rng, 60);
+        for _ in range(0u, 20) {
+            let (x0, y0) = (rng.below(SIZE), rng.below(SIZE));
+            let (x1, y1) = (x0 + rng.below(SIZE - x0), y0 + rng.below(SIZE - y0));
+            let expected: HashSet<Uuid> = all.iter()
+                .filter(|&&(_, (x, y))| x >= x0 && x <= x1 && y >= y0 && y <= y1)
+                .map(|&(id, _)| id).collect();
+            assert!(ids(index.in_rect((x0, y0), (x1, y1))) == expected,
+                    "seed {}: rect {} to {}", seed, (x0, y0), (x1, y1));
+
+            let center = (rng.below(SIZE), rng.below(SIZE));
+            let radius = rng.below(SIZE / 2);
+            for &metric in [Chebyshev, Euclidean, Manhattan].iter() {
+                let expected: HashSet<Uuid> = all.iter()
+                    .filter(|&&(_, (x, y))| {
+                        let (cx, cy) = center;
+                        metric.within(x as int - cx as int, y as int - cy as int, radius)
+                    })
+                    .map(|&(id, _)| id).collect();
+                assert!(ids(index.within(center, radius, metric)) == expected,
+                        "seed {}: {} within {} of {}", seed, metric, radius, center);
+            }
+        }
+    }
+}
+
+#[test]
+fn nearest_finds_the_closest_in_order() {
+    for seed in range(1, SEEDS as u64) {
+        let mut rng = Rng::new(seed);
+        let (index, all) = scattered_entities(&mut rng, 30);
+        for _ in range(0u, 20) {
+            let center = (rng.below(SIZE), rng.below(SIZE));
+            let k = 1 + rng.below(all.len() + 5);
+            for &metric in [Chebyshev, Euclidean, Manhattan].iter() {
+                // ties can come back in any order, so compare distances
+                let mut expected: Vec<f64> = all.iter()
+                    .map(|&(_, c)| distance(center, c, metric)).collect();
+                expected.sort_by(|a, b| a.partial_cmp(b).unwrap());
+                expected.truncate(k);
+                let found: Vec<f64> = index.nearest(center, k, metric).into_iter()
+                    .map(|(_, c)| distance(center, c, metric)).collect();
+                assert!(found == expected, "seed {}: {} nearest {} to {}: {} against {}",
+                        seed, k, metric, center, found, expected);
+            }
+        }
+    }
+}
+
+#[test]
+fn the_index_follows_payloads_as_they_move() {
+    let (mut world, zones) = portal_grid(1, 32);
+    let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+    {
+        let zone = world.get_zone_mut(&zones[0]);
+        zone.add_payload(a, (2, 2));
+        zone.add_payload(b, (20, 20));
+        zone.move_payload(&a, (18, 19));
+        zone.remove_payload(&b);
+    }
+    let zone = world.get_zone(&zones[0]);
+    assert!(zone.find_payload(&a) == Some((18, 19)));
+    assert!(zone.find_payload(&b).is_none());
+    assert!(zone.spatial().in_rect((0, 0), (31, 31)) == vec!((a, (18, 19))));
+    assert!(zone.spatial().within((2, 2), 4, Chebyshev).is_empty());
+}
+
+#[test]
+fn payloads_near_reaches_through_portals() {
+    // zone 1 lies east of zone 0, overlapping its last column
+    let (mut world, zones) = portal_grid(2, 12);
+    let (near, far, beside) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+    world.get_zone_mut(&zones[1]).add_payload(near, (2, 5));
+    world.get_zone_mut(&zones[1]).add_payload(far, (6, 5));
+    world.get_zone_mut(&zones[0]).add_payload(beside, (8, 6));
+
+    let focus = viewer(zones[0], (9, 5));
+    let found = payloads_near(&world, &focus, 5, Chebyshev);
+    let at = |id: Uuid| -> Vec<RelativeCoord> {
+        found.iter().filter(|&&(e, _)| e == id).map(|&(_, ref rc)| rc.clone()).collect()
+    };
+    assert!(at(near) == vec!(RelativeCoord::new(zones[1], (2, 5), (4, 0))),
+            "found {}", found);
+    assert!(at(beside) == vec!(RelativeCoord::new(zones[0], (8, 6), (-1, 1))),
+            "found {}", found);
+    assert!(at(far).is_empty(), "found {}", found);
+}
@@ -0,0 +1,110 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// floors joined by stairs: climbing them, searching over them, and lining
+// tiles up between the floors they join
+
+extern crate libc;
+extern crate serialize;
+extern crate uuid;
+extern crate p2d;
+
+use uuid::Uuid;
+
+use p2d::world::{World, GlobalCoord};
+use p2d::world::TraversalDirection::*;
+use p2d::zone::ZoneTraversalResult::{Destination, DestinationBlocked, DestinationOutsideBounds};
+use p2d::path::DijkstraMap;
+use p2d::fov::{Blocking, Translucent, Transparent, Void};
+
+use support::{TestWorld, Walker, set_cell};
+
+mod support;
+
+static SIZE: uint = 8;
+
+fn open_floor(world: &mut TestWorld) -> Uuid {
+    world.new_zone(SIZE, (), |z| {
+        for y in range(0, SIZE) {
+            for x in range(0, SIZE) { set_cell(z, (x, y), Transparent); }
+        }
+    })
+}
+
+// a ground floor with stairs at (2, 2) up to (5, 5) on the floor above
+fn two_floors() -> (TestWorld, Uuid, Uuid) {
+    let mut world = World::new(());
+    let ground = open_floor(&mut world);
+    let upper = open_floor(&mut world);
+    world.new_portal((ground, (2, 2), Up), (upper, (5, 5), Down));
+    (world, ground, upper)
+}
+
+#[test]
+fn stairs_are_climbed_up_and_down() {
+    let (mut world, ground, upper) = two_floors();
+    let foot = GlobalCoord::new(ground, (2, 2));
+    let top = GlobalCoord::new(upper, (5, 5));
+    assert!(world.try_traversal(foot.clone(), Up) == Destination(top.clone()));
+    assert!(world.try_traversal(top.clone(), Down) == Destination(foot.clone()));
+    // only the way the stairs go, and only from the stairs
+    assert!(world.try_traversal(foot.clone(), Down) == DestinationOutsideBounds);
+    assert!(world.try_traversal(GlobalCoord::new(ground, (3, 2)), Up) == DestinationOutsideBounds);
+    // stairs are walked over like any other tile
+    assert!(world.try_traversal(foot.clone(), East) ==
+            Destination(GlobalCoord::new(ground, (3, 2))));
+
+    set_cell(world.get_zone_mut(&upper), (5, 5), Blocking);
+    assert!(world.try_traversal(foot, Up) == DestinationBlocked);
+}
+
+#[test]
+fn searches_climb_stairs_at_the_cost_of_the_tile_climbed_to() {
+    let (world, ground, upper) = two_floors();
+    let goal = GlobalCoord::new(upper, (7, 5));
+    let map = DijkstraMap::new(&world, [goal].as_slice(), &Walker);
+    // two steps to the stairs, one up them and two more along
+    assert!(map.get(&GlobalCoord::new(ground, (0, 2))) == Some(5.0));
+}
+
+#[test]
+fn movers_are_only_stopped_by_where_they_d_end_up() {
+    let (mut world, ground, upper) = two_floors();
+    let foot = GlobalCoord::new(ground, (2, 2));
+    // passable, but nothing a walker can stand on
+    set_cell(world.get_zone_mut(&upper), (5, 5), Void);
+    assert!(world.try_traversal(foot.clone(), Up) ==
+            Destination(GlobalCoord::new(upper, (5, 5))));
+    assert!(world.try_traversal_as(foot.clone(), Up, &Walker) == DestinationBlocked);
+    set_cell(world.get_zone_mut(&upper), (5, 5), Translucent(0.5));
+    assert!(world.try_traversal_as(foot.clone(), Up, &Walker) ==
+            Destination(GlobalCoord::new(upper, (5, 5))));
+
+    // and one that's somehow standing in the void can still step out of it
+    set_cell(world.get_zone_mut(&ground), (4, 4), Void);
+    assert!(world.try_traversal_as(GlobalCoord::new(ground, (4, 4)), East, &Walker) ==
+            Destination(GlobalCoord::new(ground, (5, 4))));
+}
+
+#[test]
+fn floors_line_up_through_the_stairs_between_them() {
+    let (mut world, ground, upper) = two_floors();
+    let at = |zid: Uuid, c: (uint, uint)| GlobalCoord::new(zid, c);
+    assert!(world.level_above(&at(ground, (2, 2))) == Some(at(upper, (5, 5))));
+    assert!(world.level_above(&at(ground, (4, 3))) == Some(at(upper, (7, 6))));
+    assert!(world.level_above(&at(ground, (0, 0))) == Some(at(upper, (3, 3))));
+    assert!(world.level_below(&at(upper, (3, 7))) == Some(at(ground, (0, 4))));
+    // past the edge of the floor above, and with no floor below
+    assert!(world.level_above(&at(ground, (7, 7))).is_none());
+    assert!(world.level_below(&at(ground, (2, 2))).is_none());
+
+    // with a second flight up to a loft, whichever stairs are closer count
+    let loft = open_floor(&mut world);
+    world.new_portal((ground, (6, 6), Up), (loft, (1, 1), Down));
+    assert!(world.level_above(&at(ground, (5, 6))) == Some(at(loft, (0, 1))));
+    assert!(world.level_above(&at(ground, (3, 3))) == Some(at(upper, (6, 6))));
+}
@@ -0,0 +1,162 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// paging zones through a `ZoneCache` backed by a `DirectoryStore`, by hand
+// and by a world that's been given the cache
+
+extern crate libc;
+extern crate serialize;
+extern crate uuid;
+extern crate p2d;
+
+use std::collections::HashSet;
+use std::io::TempDir;
+use std::io::fs;
+use serialize::json;
+use uuid::Uuid;
+
+use p2d::world::{GlobalCoord, RelativeCoord};
+use p2d::world::TraversalDirection::*;
+use p2d::zone::Zone;
+use p2d::zone::ZoneTraversalResult::Destination;
+use p2d::store::{ZoneStore, DirectoryStore, ZoneCache, Pager};
+use p2d::fov;
+use p2d::fov::{FovCache, Chebyshev, Blocking};
+use p2d::fov::FovAlgorithm::Mrpas;
+
+use support::{TestWorld, Cell, portal_grid, set_cell, viewer, angle_buffers};
+
+mod support;
+
+type Store = DirectoryStore<(), Cell>;
+
+fn encode(zone: &Zone<(), Cell>) -> String {
+    json::encode(zone)
+}
+
+fn decode(text: &str) -> Option<Zone<(), Cell>> {
+    json::decode(text).ok()
+}
+
+// write every zone in `zones` out to `dir`, and take them out of the world,
+// leaving the portals between them
+fn store_all(world: &mut TestWorld, zones: &[Uuid], dir: &TempDir) -> Store {
+    let mut store = DirectoryStore::new(dir.path().clone(), encode, decode);
+    for zid in zones.iter() {
+        let zone = world.remove_zone(zid).unwrap();
+        store.save(&zone);
+    }
+    store
+}
+
+fn loaded(world: &TestWorld, zones: &[Uuid]) -> Vec<bool> {
+    zones.iter().map(|zid| world.zones.contains_key(zid)).collect()
+}
+
+fn view(world: &TestWorld, focus: RelativeCoord, radius: uint) -> HashSet<RelativeCoord> {
+    let (mut start_ang, mut end_ang) = angle_buffers(radius);
+    fov::compute_with_metric(world, focus, radius, Chebyshev,
+                             start_ang.as_mut_slice(), end_ang.as_mut_slice())
+        .into_iter().collect()
+}
+
+#[test]
+fn the_least_recently_used_zone_is_paged_out_first() {
+    let dir = TempDir::new("p2d-store").unwrap();
+    let (mut world, zones) = portal_grid(2, 12);
+    let store = store_all(&mut world, zones.as_slice(), &dir);
+    let mut cache = ZoneCache::new(store, 2);
+
+    assert!(cache.page_in(&mut world, &zones[0]));
+    assert!(cache.page_in(&mut world, &zones[1]));
+    // using the first again leaves the second the oldest
+    assert!(cache.page_in(&mut world, &zones[0]));
+    assert!(cache.page_in(&mut world, &zones[2]));
+    assert!(loaded(&world, zones.as_slice()) == vec!(true, false, true, false));
+    assert!(cache.len() == 2);
+    assert!(!cache.page_in(&mut world, &Uuid::new_v4()));
+}
+
+#[test]
+fn changed_zones_are_written_back_and_unchanged_ones_left() {
+    let dir = TempDir::new("p2d-store").unwrap();
+    let (mut world, zones) = portal_grid(2, 12);
+    let store = store_all(&mut world, zones.as_slice(), &dir);
+    let mut cache = ZoneCache::new(store, 1);
+    let walker = Uuid::new_v4();
+
+    set_cell(cache.get_zone_mut(&mut world, &zones[0]).unwrap(), (4, 4), Blocking);
+    // a change that isn't to a tile counts too
+    cache.get_zone_mut(&mut world, &zones[1]).unwrap().add_payload(walker, (2, 3));
+    cache.page_in(&mut world, &zones[3]);
+    // with its file gone, paging out a zone that hasn't changed leaves none
+    let untouched = cache.store.path_for(&zones[3]);
+    fs::unlink(&untouched).unwrap();
+    cache.page_in(&mut world, &zones[0]);
+    assert!(!untouched.exists(), "an unchanged zone was written back");
+
+    assert!(world.get_zone(&zones[0]).get_tile((4, 4)).payload.fov == Blocking);
+    cache.page_in(&mut world, &zones[1]);
+    assert!(world.get_zone(&zones[1]).find_payload(&walker) == Some((2, 3)));
+}
+
+#[test]
+fn a_reloaded_zone_comes_back_past_its_old_revision() {
+    let dir = TempDir::new("p2d-store").unwrap();
+    let (mut world, zones) = portal_grid(2, 12);
+    let store = store_all(&mut world, zones.as_slice(), &dir);
+    world.page_through(box ZoneCache::new(store, 1));
+
+    // a view from the middle of the first zone, taken as soon as it's read
+    // in, then made out of date while it's loaded
+    let focus = viewer(zones[0], (5, 5));
+    let mut cache = FovCache::new();
+    let (mut start_ang, mut end_ang) = angle_buffers(3);
+    let before: HashSet<RelativeCoord> = cache.compute(
+        &world, focus.clone(), 3, Mrpas(Chebyshev),
+        start_ang.as_mut_slice(), end_ang.as_mut_slice()).iter().map(|c| c.clone()).collect();
+    let revision = world.get_zone_mut(&zones[0]).revision();
+    set_cell(world.get_zone_mut(&zones[0]), (6, 5), Blocking);
+
+    world.get_zone_mut(&zones[1]);
+    assert!(!world.zones.contains_key(&zones[0]));
+    assert!(world.get_zone(&zones[0]).revision() > revision + 1);
+
+    let after: HashSet<RelativeCoord> = cache.compute(
+        &world, focus.clone(), 3, Mrpas(Chebyshev),
+        start_ang.as_mut_slice(), end_ang.as_mut_slice()).iter().map(|c| c.clone()).collect();
+    assert!(after == view(&world, focus, 3));
+    assert!(after != before, "the cached view didn't see the new pillar");
+}
+
+#[test]
+fn a_paging_world_reads_in_what_it_needs() {
+    let dir = TempDir::new("p2d-store").unwrap();
+    let (mut world, zones) = portal_grid(2, 12);
+    for &c in [(8u, 8u), (10, 6), (6, 10)].iter() {
+        set_cell(world.get_zone_mut(&zones[0]), c, Blocking);
+    }
+    set_cell(world.get_zone_mut(&zones[3]), (3, 3), Blocking);
+    // looking out over the corner where all four zones meet
+    let focus = viewer(zones[0], (9, 9));
+    let expected = view(&world, focus.clone(), 8);
+    let store = store_all(&mut world, zones.as_slice(), &dir);
+    world.page_through(box ZoneCache::new(store, 2));
+
+    assert!(view(&world, focus, 8) == expected);
+    let step = world.try_traversal(GlobalCoord::new(zones[0], (11, 5)), East);
+    assert!(step == Destination(GlobalCoord::new(zones[1], (1, 5))), "stepped to {}", step);
+    // reading never pages out; settling does
+    assert!(world.zones.len() == 0);
+    world.settle(&[]);
+    assert!(world.zones.len() == 2);
+
+    // whatever's changed is written back when paging stops
+    set_cell(world.get_zone_mut(&zones[2]), (1, 1), Blocking);
+    let mut cache = world.stop_paging().unwrap();
+    assert!(cache.fetch(&zones[2]).unwrap().get_tile((1, 1)).payload.fov == Blocking);
+}
@@ -0,0 +1,59 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// counting what gets allocated, from jemalloc's own per-thread totals; the
+// standard library allocates everything through it, and there's no way to
+// put a counting allocator in its place
+
+use std::mem;
+use libc::{c_char, c_int, c_void, size_t};
+
+extern {
+    fn je_mallctl(name: *const c_char, oldp: *mut c_void, oldlenp: *mut size_t,
+                  newp: *mut c_void, newlen: size_t) -> c_int;
+}
+
+/// The bytes the calling thread has allocated so far, freed or not.
+pub fn allocated() -> u64 {
+    let name = b"thread.allocated\0";
+    let mut total = 0u64;
+    let mut len = mem::size_of::<u64>() as size_t;
+    let status = unsafe {
+        je_mallctl(name.as_ptr() as *const c_char, &mut total as *mut u64 as *mut c_void,
+                   &mut len, 0 as *mut c_void, 0)
+    };
+    if status != 0 {
+        panic!("alloc::allocated: jemalloc isn't keeping stats ({})", status);
+    }
+    total
+}
+
+/// The result of `f`, and the bytes allocated while running it.
+pub fn allocated_by<T>(f: || -> T) -> (T, u64) {
+    let before = allocated();
+    let result = f();
+    (result, allocated() - before)
+}
+
+/// The bytes a single call to `f` allocates, once a few calls before it
+/// have had the chance to grow any buffers it keeps.
+pub fn per_call<T>(f: || -> T) -> u64 {
+    let mut f = f;
+    for _ in range(0u, 3) { f(); }
+    let (_, bytes) = allocated_by(|| f());
+    bytes
+}
+
+/// Panic if a call to `f`, warmed up as for `per_call`, allocates at all.
+/// The benches check what's meant to run without allocating this way, so a
+/// change that starts to allocate fails outright instead of running slower.
+pub fn assert_none<T>(what: &str, f: || -> T) {
+    let bytes = per_call(f);
+    if bytes != 0 {
+        panic!("{} allocated {} bytes a call", what, bytes);
+    }
+}
@@ -0,0 +1,203 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// generated worlds shared by the tests and benchmarks. Everything is built
+// from a seed, so a failing case (or a slow one) can be rebuilt exactly.
+
+#![allow(dead_code)]
+
+use std::f64;
+use uuid::Uuid;
+
+use p2d::world::{World, Payloadable, RelativeCoord};
+use p2d::world::TraversalDirection::*;
+use p2d::zone::Zone;
+use p2d::fov::{FovItem, FovType, Blocking, Translucent, Transparent, Void};
+use p2d::path::MovementCost;
+
+pub mod alloc;
+
+#[deriving(Clone, Show, PartialEq, Encodable, Decodable)]
+pub struct Cell {
+    pub fov: FovType
+}
+impl Payloadable for Cell {
+    fn stub() -> Cell { Cell { fov: Void } }
+}
+impl FovItem for Cell {
+    fn get_fov(&self) -> FovType { self.fov }
+}
+
+pub type TestWorld = World<(), (), Cell>;
+
+/// The only mover the tests need: walls and the void stop it, and
+/// translucent cells (brush, say) are slow going.
+pub struct Walker;
+impl MovementCost<Walker> for Cell {
+    fn movement_cost(&self, _: &Walker) -> f64 {
+        match self.fov {
+            Blocking | Void => f64::INFINITY,
+            Translucent(_) => 3.0,
+            _ => 1.0
+        }
+    }
+}
+
+// a small xorshift generator; all the generators need is something
+// repeatable
+pub struct Rng {
+    state: u64
+}
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: if seed == 0 { 0x9E3779B97F4A7C15 } else { seed } }
+    }
+    pub fn next(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+    /// A number in 0..n
+    pub fn below(&mut self, n: uint) -> uint {
+        (self.next() % n as u64) as uint
+    }
+    /// True one time in `n`, on average.
+    pub fn one_in(&mut self, n: uint) -> bool {
+        self.below(n) == 0
+    }
+}
+
+pub fn set_cell(zone: &mut Zone<(), Cell>, coords: (uint, uint), fov: FovType) {
+    let t = zone.get_tile_mut(coords);
+    t.passable = fov != Blocking;
+    t.payload = Cell { fov: fov };
+}
+
+/// The viewer standing at `coords`; what it sees is placed relative to it.
+pub fn viewer(zid: Uuid, coords: (uint, uint)) -> RelativeCoord {
+    RelativeCoord::new(zid, coords, (0, 0))
+}
+
+/// Big enough angle buffers for a scan of `radius`; an octant can't hold
+/// more obstacles than it has cells.
+pub fn angle_buffers(radius: uint) -> (Vec<f64>, Vec<f64>) {
+    let size = (radius + 1) * (radius + 2);
+    (Vec::from_elem(size, 0.0f64), Vec::from_elem(size, 0.0f64))
+}
+
+/// A single zone with nothing in the way.
+pub fn open_field(size: uint) -> (TestWorld, Uuid) {
+    let mut world = World::new(());
+    let zid = world.new_zone(size, (), |z| fill(z, Transparent));
+    (world, zid)
+}
+
+/// A single zone with pillars scattered over roughly one tile in `one_in`.
+pub fn scattered(size: uint, one_in: uint, seed: u64) -> (TestWorld, Uuid) {
+    let mut rng = Rng::new(seed);
+    let mut world = World::new(());
+    let zid = world.new_zone(size, (), |z| {
+        fill(z, Transparent);
+        for y in range(0, size) {
+            for x in range(0, size) {
+                if rng.one_in(one_in) { set_cell(z, (x, y), Blocking); }
+            }
+        }
+    });
+    (world, zid)
+}
+
+/// A single zone carved into a maze of one-tile corridors, with the
+/// corridors on odd coordinates. (1, 1) is always open.
+pub fn maze(size: uint, seed: u64) -> (TestWorld, Uuid) {
+    let mut rng = Rng::new(seed);
+    let mut world = World::new(());
+    let zid = world.new_zone(size, (), |z| {
+        fill(z, Blocking);
+        // depth-first carving from (1, 1)
+        let mut stack = vec!((1u, 1u));
+        set_cell(z, (1, 1), Transparent);
+        loop {
+            let (x, y) = match stack.last() {
+                Some(&c) => c,
+                None => break
+            };
+            let mut options = Vec::new();
+            for &(dx, dy) in [(0i, -2i), (2, 0), (0, 2), (-2, 0)].iter() {
+                let (nx, ny) = (x as int + dx, y as int + dy);
+                if nx < 1 || ny < 1 || nx >= size as int - 1 || ny >= size as int - 1 {
+                    continue;
+                }
+                if z.get_tile((nx as uint, ny as uint)).payload.fov == Blocking {
+                    options.push((nx as uint, ny as uint));
+                }
+            }
+            if options.len() == 0 {
+                stack.pop();
+                continue;
+            }
+            let (nx, ny) = options[rng.below(options.len())];
+            set_cell(z, ((x + nx) / 2, (y + ny) / 2), Transparent);
+            set_cell(z, (nx, ny), Transparent);
+            stack.push((nx, ny));
+        }
+    });
+    (world, zid)
+}
+
+/// A `side` by `side` grid of open zones, each joined to its neighbors by
+/// portals spanning their shared edge (less the corners, which can only
+/// hold one portal each). Returns the zones in row order.
+pub fn portal_grid(side: uint, size: uint) -> (TestWorld, Vec<Uuid>) {
+    let mut world = World::new(());
+    let zones: Vec<Uuid> = range(0, side * side)
+        .map(|_| world.new_zone(size, (), |z| fill(z, Transparent)))
+        .collect();
+    for row in range(0, side) {
+        for col in range(0, side) {
+            let here = zones[row * side + col];
+            if col + 1 < side {
+                let east = zones[row * side + col + 1];
+                world.new_portal_span((here, (size - 1, 1), East), (east, (0, 1), West),
+                                      size - 2);
+            }
+            if row + 1 < side {
+                let south = zones[(row + 1) * side + col];
+                world.new_portal_span((here, (1, size - 1), South), (south, (1, 0), North),
+                                      size - 2);
+            }
+        }
+    }
+    (world, zones)
+}
+
+/// A copy of a single-zone world, flipped left to right and/or top to
+/// bottom.
+pub fn mirrored(world: &TestWorld, zid: Uuid, flip_x: bool, flip_y: bool) -> (TestWorld, Uuid) {
+    let src = world.get_zone(&zid);
+    let size = src.size;
+    let mut mirror = World::new(());
+    let mid = mirror.new_zone(size, (), |z| {
+        for y in range(0, size) {
+            for x in range(0, size) {
+                let mx = if flip_x { size - 1 - x } else { x };
+                let my = if flip_y { size - 1 - y } else { y };
+                set_cell(z, (mx, my), src.get_tile((x, y)).payload.fov);
+            }
+        }
+    });
+    (mirror, mid)
+}
+
+fn fill(zone: &mut Zone<(), Cell>, fov: FovType) {
+    for y in range(0, zone.size) {
+        for x in range(0, zone.size) {
+            set_cell(zone, (x, y), fov);
+        }
+    }
+}
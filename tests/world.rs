@@ -0,0 +1,364 @@
+// Copyright 2013-2014 Jeffery Olson
+//
+// Licensed under the 3-Clause BSD License, see LICENSE.txt
+// at the top-level of this repository.
+// This file may not be copied, modified, or distributed
+// except according to those terms.
+
+// round trips through the ways a `World` gets changed and changed back:
+// deltas, undo/redo, and the choice of tile storage
+
+extern crate libc;
+extern crate serialize;
+extern crate uuid;
+extern crate p2d;
+
+use std::collections::HashSet;
+use serialize::json;
+use uuid::Uuid;
+
+use p2d::world::{World, GlobalCoord, RelativeCoord, Payloadable};
+use p2d::world::TraversalDirection::*;
+use p2d::zone::{Zone, Tile, ZoneTraversalResult};
+use p2d::zone::ZoneTraversalResult::Destination;
+use p2d::tiles::TileStorage::{Dense, Chunked};
+use p2d::layer::LayerCell;
+use p2d::portal::Portal;
+use p2d::delta;
+use p2d::history::History;
+use p2d::fov;
+use p2d::fov::{FovType, Chebyshev, Blocking, Transparent};
+
+use support::{TestWorld, Cell, Rng, portal_grid, viewer, angle_buffers, set_cell};
+
+mod support;
+
+static SEEDS: uint = 10;
+
+// a copy of `world` made by handing its zones and portals to a new one, as a
+// client would be sent them
+fn copy_of(world: &TestWorld) -> TestWorld {
+    let mut copy = World::new(());
+    for (_, zone) in world.zones.iter() {
+        copy.insert_zone(zone.clone());
+    }
+    for (_, portal) in world.portals.iter() {
+        copy.insert_portal(portal.clone());
+    }
+    copy
+}
+
+// every zone, tile, layer, payload and portal in `a` is in `b` and the same,
+// and the other way around
+fn assert_same(a: &TestWorld, b: &TestWorld) {
+    assert!(a.zones.len() == b.zones.len(),
+            "{} zones against {}", a.zones.len(), b.zones.len());
+    for (zid, za) in a.zones.iter() {
+        let zb = match b.zones.find(zid) {
+            Some(z) => z,
+            None => panic!("zone {} is missing", zid)
+        };
+        assert!(za.size == zb.size, "zone {}: size {} against {}", zid, za.size, zb.size);
+        for idx in range(0, za.size * za.size) {
+            assert!(za.tile_at_idx(idx) == zb.tile_at_idx(idx),
+                    "zone {}: tile {} differs", zid, idx);
+        }
+        assert!(za.layers().len() == zb.layers().len(), "zone {}: layers differ", zid);
+        for (la, lb) in za.layers().iter().zip(zb.layers().iter()) {
+            assert!(la.name == lb.name, "zone {}: layer {} against {}", zid, la.name, lb.name);
+            for idx in range(0, za.size * za.size) {
+                assert!(la.cell_at_idx(idx) == lb.cell_at_idx(idx),
+                        "zone {}: cell {} in layer {} differs", zid, idx, la.name);
+            }
+        }
+        assert!(za.payload_coords() == zb.payload_coords(), "zone {}: payloads differ", zid);
+        assert!(za.portal_coords == zb.portal_coords, "zone {}: portal coords differ", zid);
+    }
+    assert!(a.portals.len() == b.portals.len(),
+            "{} portals against {}", a.portals.len(), b.portals.len());
+    for (pid, pa) in a.portals.iter() {
+        assert!(b.portals.find(pid) == Some(pa), "portal {} differs", pid);
+    }
+}
+
+// the portal `portal_grid` put from `a` to `b`
+fn portal_between(world: &TestWorld, a: Uuid, b: Uuid) -> Uuid {
+    match world.portals.iter().find(|&(_, p)| p.a_zid() == a && p.b_zid() == b) {
+        Some((pid, _)) => *pid,
+        None => panic!("no portal from {} to {}", a, b)
+    }
+}
+
+fn random_fov(rng: &mut Rng) -> FovType {
+    if rng.one_in(3) { Blocking } else { Transparent }
+}
+
+#[test]
+fn applying_a_diff_brings_a_copy_up_to_date() {
+    for seed in range(1, SEEDS as u64) {
+        let mut rng = Rng::new(seed);
+        let (mut old, zones) = portal_grid(2, 16);
+        let spare = old.new_zone(8, (), |_| {});
+        old.get_zone_mut(&zones[2]).add_layer("floor", false, false);
+        let walkers: Vec<Uuid> = range(0u, 4).map(|_| Uuid::new_v4()).collect();
+        for (i, id) in walkers.iter().enumerate() {
+            old.get_zone_mut(&zones[0]).add_payload(*id, (2 + i, 2));
+        }
+
+        let mut new = copy_of(&old);
+        for _ in range(0u, 20) {
+            let zid = zones[rng.below(zones.len())];
+            let c = (rng.below(16), rng.below(16));
+            set_cell(new.get_zone_mut(&zid), c, random_fov(&mut rng));
+        }
+        *new.get_zone_mut(&zones[2]).get_layer_cell_mut("floor", (3, 3)) =
+            LayerCell { passable: false, payload: Cell { fov: Blocking } };
+        // a layer added to a zone means sending it whole
+        new.get_zone_mut(&zones[3]).add_layer("decals", false, false);
+        {
+            let zone = new.get_zone_mut(&zones[0]);
+            zone.move_payload(&walkers[0], (rng.below(16), rng.below(16)));
+            zone.remove_payload(&walkers[1]);
+            zone.remove_payload(&walkers[2]);
+        }
+        new.get_zone_mut(&zones[1]).add_payload(walkers[2], (5, 5));
+        new.get_zone_mut(&zones[1]).add_payload(Uuid::new_v4(), (6, 6));
+        new.remove_zone(&spare);
+        // one portal goes, and another narrows
+        new.remove_portal(&portal_between(&old, zones[2], zones[3]));
+        let narrowed = portal_between(&old, zones[0], zones[1]);
+        new.remove_portal(&narrowed);
+        new.insert_portal(Portal::with_width(narrowed, zones[0], East, zones[1], West, 6));
+        new.get_zone_mut(&zones[0]).add_portal_span(narrowed, (15, 1), East, 6);
+        new.get_zone_mut(&zones[1]).add_portal_span(narrowed, (0, 1), West, 6);
+        let annex = new.new_zone(8, (), |z| set_cell(z, (1, 1), Blocking));
+        new.new_portal_span((zones[0], (0, 1), West), (annex, (7, 1), East), 6);
+
+        let d = delta::diff(&old, &new);
+        let mut applied = copy_of(&old);
+        d.apply(&mut applied);
+        assert_same(&applied, &new);
+        assert!(delta::diff(&applied, &new).is_empty(), "seed {}: left a difference", seed);
+    }
+}
+
+#[test]
+fn undo_and_redo_give_back_each_state() {
+    let (mut world, zones) = portal_grid(2, 16);
+    let grid_portal = *world.get_zone(&zones[0]).portal_coords.keys().next().unwrap();
+    let mut history: History<(), Cell> = History::new();
+    let mut states = vec!(copy_of(&world));
+
+    history.begin("paint");
+    for i in range(1u, 6) {
+        history.edit_tile(&mut world, GlobalCoord::new(zones[0], (i, i)), |t| {
+            t.passable = false;
+            t.payload = Cell { fov: Blocking };
+        });
+    }
+    // painting over a portal leaves the portal where it was
+    let edge = GlobalCoord::new(zones[0], (15, 1));
+    let covering = world.get_zone(&zones[0]).get_tile(edge.coords).portal_id;
+    assert!(covering.is_some());
+    history.set_tile(&mut world, edge.clone(),
+                     Tile { passable: false, payload: Cell { fov: Blocking }, portal_id: None });
+    assert!(world.get_zone(&zones[0]).get_tile(edge.coords).portal_id == covering);
+    history.commit();
+    states.push(copy_of(&world));
+
+    history.begin("annex");
+    let annex = history.new_zone(&mut world, 8, (), |z| set_cell(z, (1, 1), Blocking));
+    history.new_portal(&mut world, (zones[0], (0, 1), West), (annex, (7, 1), East), 6);
+    // zones made elsewhere go in whole, chunked or not
+    let mut cellar: Zone<(), Cell> = Zone::new_chunked(64, Uuid::new_v4(), ());
+    set_cell(&mut cellar, (40, 40), Blocking);
+    let cellar = history.insert_zone(&mut world, cellar);
+    history.commit();
+    states.push(copy_of(&world));
+
+    history.begin("tear down");
+    history.remove_portal(&mut world, grid_portal);
+    history.commit();
+    states.push(copy_of(&world));
+
+    // taking out a zone takes the portals into it along with it
+    history.begin("demolish");
+    history.remove_zone(&mut world, annex);
+    history.commit();
+    assert!(world.portals.iter().all(|(_, p)| p.a_zid() != annex && p.b_zid() != annex));
+    assert!(world.get_zone(&zones[0]).get_tile((0, 1)).portal_id.is_none());
+    states.push(copy_of(&world));
+
+    for i in range(0, states.len() - 1).rev() {
+        assert!(history.undo(&mut world).is_some(), "nothing to undo back to state {}", i);
+        assert_same(&world, &states[i]);
+    }
+    assert!(!history.can_undo());
+    for i in range(1, states.len()) {
+        assert!(history.redo(&mut world).is_some(), "nothing to redo up to state {}", i);
+        assert_same(&world, &states[i]);
+    }
+    assert!(!history.can_redo());
+    assert!(chunks_allocated(world.get_zone(&cellar)) == 1);
+}
+
+fn chunks_allocated(zone: &Zone<(), Cell>) -> uint {
+    match *zone.tiles() {
+        Chunked(ref tiles) => tiles.allocated(),
+        _ => panic!("a chunked zone's tiles aren't chunked")
+    }
+}
+
+fn landing(result: ZoneTraversalResult) -> Option<GlobalCoord> {
+    match result {
+        Destination(gc) => Some(gc),
+        _ => None
+    }
+}
+
+#[test]
+fn chunked_and_dense_zones_agree() {
+    for seed in range(1, SEEDS as u64) {
+        let mut rng = Rng::new(seed);
+        let zid = Uuid::new_v4();
+        let mut dense: TestWorld = World::new(());
+        dense.insert_zone(Zone::new(64, zid, ()));
+        let mut chunked: TestWorld = World::new(());
+        chunked.insert_zone(Zone::new_chunked(64, zid, ()));
+
+        // a room with pillars in it, in a corner of an otherwise empty zone
+        for y in range(4u, 28) {
+            for x in range(4u, 28) {
+                let fov = if rng.one_in(8) { Blocking } else { Transparent };
+                set_cell(dense.get_zone_mut(&zid), (x, y), fov);
+                set_cell(chunked.get_zone_mut(&zid), (x, y), fov);
+            }
+        }
+        let allocated = chunks_allocated(chunked.get_zone(&zid));
+        assert!(allocated == 4, "{} chunks allocated", allocated);
+        for idx in range(0, 64 * 64) {
+            assert!(dense.get_zone(&zid).tile_at_idx(idx) ==
+                    chunked.get_zone(&zid).tile_at_idx(idx), "seed {}: tile {}", seed, idx);
+        }
+
+        for _ in range(0u, 10) {
+            let c = (rng.below(40), rng.below(40));
+            let radius = 1 + rng.below(20);
+            let (mut start_ang, mut end_ang) = angle_buffers(radius);
+            let a: HashSet<RelativeCoord> = fov::compute_with_metric(
+                &dense, viewer(zid, c), radius, Chebyshev,
+                start_ang.as_mut_slice(), end_ang.as_mut_slice()).into_iter().collect();
+            let b: HashSet<RelativeCoord> = fov::compute_with_metric(
+                &chunked, viewer(zid, c), radius, Chebyshev,
+                start_ang.as_mut_slice(), end_ang.as_mut_slice()).into_iter().collect();
+            assert!(a == b, "seed {}: views from {} differ", seed, c);
+            for dir in [North, East, South, West].iter() {
+                let gc = GlobalCoord::new(zid, c);
+                assert!(landing(dense.try_traversal(gc.clone(), *dir)) ==
+                        landing(chunked.try_traversal(gc, *dir)),
+                        "seed {}: stepping {} from {} differs", seed, dir, c);
+            }
+        }
+    }
+}
+
+#[test]
+fn chunked_zones_needn_t_fit_a_whole_number_of_chunks() {
+    for &size in [7u, 24, 40].iter() {
+        let zid = Uuid::new_v4();
+        let mut dense: Zone<(), Cell> = Zone::new(size, zid, ());
+        let mut chunked: Zone<(), Cell> = Zone::new_chunked(size, zid, ());
+        for y in range(0, size) {
+            for x in range(0, size) {
+                let fov = if (x * 7 + y * 3) % 5 == 0 { Blocking } else { Transparent };
+                set_cell(&mut dense, (x, y), fov);
+                set_cell(&mut chunked, (x, y), fov);
+            }
+        }
+        for idx in range(0, size * size) {
+            assert!(dense.tile_at_idx(idx) == chunked.tile_at_idx(idx),
+                    "size {}: tile {}", size, idx);
+        }
+    }
+}
+
+#[test]
+fn stubs_written_back_leave_chunks_free() {
+    let mut zone: Zone<(), Cell> = Zone::new_chunked(64, Uuid::new_v4(), ());
+    zone.add_layer("floor", true, true);
+    zone.set_tile((40, 40), Tile::stub());
+    zone.set_layer_cell("floor", (40, 40), LayerCell { passable: true, payload: Cell::stub() });
+    assert!(chunks_allocated(&zone) == 0, "a stub allocated a chunk");
+
+    set_cell(&mut zone, (3, 3), Blocking);
+    zone.set_layer_cell("floor", (3, 3), LayerCell { passable: false,
+                                                     payload: Cell { fov: Blocking } });
+    assert!(chunks_allocated(&zone) == 1);
+    // written back, the chunk's still there until it's compacted
+    zone.set_tile((3, 3), Tile::stub());
+    zone.set_layer_cell("floor", (3, 3), LayerCell { passable: true, payload: Cell::stub() });
+    assert!(chunks_allocated(&zone) == 1);
+    let revision = zone.revision();
+    zone.compact();
+    assert!(chunks_allocated(&zone) == 0);
+    assert!(zone.revision() == revision);
+    assert!(*zone.get_tile((3, 3)) == Tile::stub());
+    assert!(zone.passable_at((3, 3)) == Tile::<Cell>::stub().passable);
+}
+
+fn same_tiles(a: &Zone<(), Cell>, b: &Zone<(), Cell>) -> bool {
+    a.size == b.size &&
+        range(0, a.size * a.size).all(|idx| a.tile_at_idx(idx) == b.tile_at_idx(idx))
+}
+
+#[test]
+fn zones_decode_with_the_tile_layout_they_were_saved_with() {
+    let mut rng = Rng::new(7);
+    let zid = Uuid::new_v4();
+    let mut dense: Zone<(), Cell> = Zone::new(40, zid, ());
+    let mut chunked: Zone<(), Cell> = Zone::new_chunked(40, zid, ());
+    for _ in range(0u, 60) {
+        let (c, fov) = ((rng.below(20), rng.below(20)), random_fov(&mut rng));
+        set_cell(&mut dense, c, fov);
+        set_cell(&mut chunked, c, fov);
+    }
+    let decoded: Zone<(), Cell> = json::decode(json::encode(&chunked).as_slice()).unwrap();
+    assert!(chunks_allocated(&decoded) == chunks_allocated(&chunked));
+    assert!(same_tiles(&decoded, &chunked));
+
+    let decoded: Zone<(), Cell> = json::decode(json::encode(&dense).as_slice()).unwrap();
+    match *decoded.tiles() {
+        Dense(_) => {},
+        _ => panic!("a dense zone came back chunked")
+    }
+    assert!(same_tiles(&decoded, &dense));
+
+    // zones saved before there was a choice have no `tiles_version`, and
+    // keep their tiles as a plain list
+    let old = without_fields(&dense, ["tiles_version"].as_slice());
+    let decoded: Zone<(), Cell> = json::decode(old.as_slice()).unwrap();
+    assert!(same_tiles(&decoded, &dense));
+}
+
+// `zone` encoded as it would have been before `fields` were added to it
+fn without_fields(zone: &Zone<(), Cell>, fields: &[&str]) -> String {
+    let mut encoded = json::from_str(json::encode(zone).as_slice()).unwrap();
+    match encoded {
+        json::Json::Object(ref mut o) => {
+            for f in fields.iter() { o.remove(&f.to_string()); }
+        },
+        _ => panic!("a zone isn't encoded as an object")
+    }
+    json::encode(&encoded)
+}
+
+#[test]
+fn zones_saved_before_layers_decode_with_none() {
+    let mut zone: Zone<(), Cell> = Zone::new(8, Uuid::new_v4(), ());
+    set_cell(&mut zone, (2, 5), Blocking);
+    let old = without_fields(&zone, ["layers", "tiles_version"].as_slice());
+    let decoded: Zone<(), Cell> = json::decode(old.as_slice()).unwrap();
+    assert!(same_tiles(&decoded, &zone));
+    assert!(decoded.payloads_at((2, 5)).len() == 1);
+    assert!(decoded.fov_at((2, 5)) == Blocking);
+}